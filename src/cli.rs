@@ -0,0 +1,259 @@
+//! This module implements the command-line interface of the binary.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    css::{
+        loader::{self, Loader},
+        Stylesheet,
+    },
+    dom::Arena,
+    error::Error,
+    headless::OutputFormat,
+    html,
+};
+
+pub const USAGE: &str = "\
+Usage: exercise-rendering-tree [OPTIONS] [INPUT]
+
+INPUT is a path, a file:// URL or `-` for the standard input.
+Without INPUT, a built-in example page is rendered.
+
+Options:
+    --headless               print the page to the standard output instead of starting the terminal UI
+    --format FORMAT          output format of --headless: text, ansi or json (default: text)
+    --width COLUMNS          viewport width of --headless (default: 80)
+    --height ROWS            viewport height of --headless (default: 24)
+    --no-js                  do not run scripts
+    --user-stylesheet PATH   apply the stylesheet at PATH as the user stylesheet
+    -h, --help               print this message
+";
+
+/// `Input` is where the document is read from.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Example,
+    Stdin,
+    Path(PathBuf),
+}
+
+/// `Options` represents the parsed command-line arguments.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub input: Input,
+    // Noneなら端末のUIを使う
+    pub headless: Option<OutputFormat>,
+    pub width: usize,
+    pub height: usize,
+    pub scripts: bool,
+    pub user_stylesheet: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            input: Input::Example,
+            headless: None,
+            width: 80,
+            height: 24,
+            scripts: true,
+            user_stylesheet: None,
+            help: false,
+        }
+    }
+}
+
+/// `parse_args` parses the arguments, excluding the program name.
+/// Options with a value accept both `--width 100` and `--width=100`.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut input = None;
+    let mut format = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("`{}` requires a value", name))
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--headless" => options.headless = Some(OutputFormat::Text),
+            "--format" => format = Some(value()?.parse()?),
+            "--width" => {
+                let width = value()?;
+                options.width = width
+                    .parse()
+                    .ok()
+                    .filter(|&width| width > 0)
+                    .ok_or_else(|| format!("invalid width `{}`", width))?;
+            },
+            "--height" => {
+                let height = value()?;
+                options.height = height
+                    .parse()
+                    .ok()
+                    .filter(|&height| height > 0)
+                    .ok_or_else(|| format!("invalid height `{}`", height))?;
+            },
+            "--no-js" => options.scripts = false,
+            "--user-stylesheet" => options.user_stylesheet = Some(to_path(&value()?)?),
+            _ if name.starts_with('-') && name != "-" => return Err(format!("unknown option `{}`", name)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                input = Some(arg);
+            },
+        }
+    }
+
+    // --formatは--headlessを兼ねる
+    if let Some(format) = format {
+        options.headless = Some(format);
+    }
+    options.input = match input.as_deref() {
+        None => Input::Example,
+        Some("-") => Input::Stdin,
+        Some(arg) => Input::Path(to_path(arg)?),
+    };
+    Ok(options)
+}
+
+/// `to_path` converts an argument into a path. Only an argument with a scheme like `file://` is resolved as a URL,
+/// so that a local file named `notes#1.html` or `a?b.html` is opened as it is.
+fn to_path(arg: &str) -> Result<PathBuf, String> {
+    let has_scheme = arg.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if has_scheme {
+        loader::resolve(None, arg).map_err(|e| e.to_string())
+    } else {
+        Ok(PathBuf::from(arg))
+    }
+}
+
+/// `read_document` reads and parses the document from the input, and returns it with the parse errors the parser recovered from.
+/// The document remembers its path, so that the stylesheets it links to are found relative to it.
+pub fn read_document(input: &Input, example: &str) -> Result<(Arena, Vec<html::ParseError>), String> {
+    match input {
+        Input::Example => Ok(html::parse_with_errors(example)),
+        Input::Stdin => {
+            let mut raw = String::new();
+            io::stdin()
+                .read_to_string(&mut raw)
+                .map_err(|e| format!("failed to read the standard input: {}", e))?;
+            Ok(html::parse_with_errors(&raw))
+        },
+        Input::Path(path) => {
+            let raw = fs::read_to_string(path).map_err(|e| format!("failed to read `{}`: {}", path.display(), e))?;
+            let (mut document, errors) = html::parse_with_errors(&raw);
+            document.set_url(&path.to_string_lossy());
+            Ok((document, errors))
+        },
+    }
+}
+
+/// `read_user_stylesheet` loads the stylesheet and the ones it imports.
+/// Unlike the stylesheets of the document, any of them failing to load is an error.
+pub fn read_user_stylesheet(path: &Path) -> Result<Stylesheet, Error> {
+    let mut loader = Loader::new();
+    let stylesheet = loader.load_path(path.to_path_buf());
+    match (stylesheet, loader.errors.into_iter().next()) {
+        (Some(stylesheet), None) => Ok(stylesheet),
+        (_, Some(e)) => Err(e.into()),
+        (None, None) => unreachable!("a failed load always reports an error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// `TempFile` removes the file when dropped, even if the test fails.
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+        assert_eq!(
+            parse(&["--format", "ansi", "--width=100", "--height", "40", "--no-js", "--user-stylesheet", "user.css", "page.html"]),
+            Ok(Options {
+                input: Input::Path(PathBuf::from("page.html")),
+                headless: Some(OutputFormat::Ansi),
+                width: 100,
+                height: 40,
+                scripts: false,
+                user_stylesheet: Some(PathBuf::from("user.css")),
+                help: false,
+            })
+        );
+        assert_eq!(parse(&["--headless", "-"]).map(|o| (o.headless, o.input)), Ok((Some(OutputFormat::Text), Input::Stdin)));
+        assert_eq!(
+            parse(&["file:///tmp/page.html"]).map(|o| o.input),
+            Ok(Input::Path(PathBuf::from("/tmp/page.html")))
+        );
+        assert_eq!(parse(&["-h"]).map(|o| o.help), Ok(true));
+
+        // paths are not URLs, so `?` and `#` are parts of the file names
+        assert_eq!(parse(&["notes#1.html"]).map(|o| o.input), Ok(Input::Path(PathBuf::from("notes#1.html"))));
+        assert_eq!(parse(&["a?b.html"]).map(|o| o.input), Ok(Input::Path(PathBuf::from("a?b.html"))));
+        assert_eq!(
+            parse(&["--user-stylesheet", "file:///tmp/user.css#x"]).map(|o| o.user_stylesheet),
+            Ok(Some(PathBuf::from("/tmp/user.css")))
+        );
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert_eq!(parse(&["--width", "wide"]), Err("invalid width `wide`".to_string()));
+        assert_eq!(parse(&["--width"]), Err("`--width` requires a value".to_string()));
+        assert_eq!(parse(&["--height=0"]), Err("invalid height `0`".to_string()));
+        assert_eq!(parse(&["--color"]), Err("unknown option `--color`".to_string()));
+        assert_eq!(parse(&["a.html", "b.html"]), Err("unexpected argument `b.html`".to_string()));
+        assert_eq!(
+            parse(&["--format=pdf"]),
+            Err("unknown output format `pdf`; expected `text`, `ansi` or `json`".to_string())
+        );
+        assert_eq!(
+            parse(&["https://example.com/"]),
+            Err("unsupported URL `https://example.com/`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_read_document() {
+        let path = std::env::temp_dir().join(format!("cli-read-document-{}#1.html", std::process::id()));
+        let _file = TempFile(path.clone());
+        fs::write(&path, "<p a a>hello</p>").unwrap();
+        let (document, errors) = read_document(&Input::Path(path.clone()), "").unwrap();
+        assert_eq!(document.url(), Some(path.to_string_lossy().as_ref()));
+        assert_eq!(errors.len(), 1);
+
+        let missing = Input::Path(PathBuf::from("/nonexistent/page.html"));
+        assert_eq!(read_document(&missing, "").unwrap_err().starts_with("failed to read `/nonexistent/page.html`"), true);
+    }
+}
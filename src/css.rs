@@ -1,73 +1,353 @@
 use combine::{
-    choice,
+    attempt, choice,
     error::StreamError,
-    many, many1, optional,
-    parser::char::{self, letter, newline, space},
-    sep_by, sep_end_by, ParseError, Parser, Stream,
+    any, many, many1, one_of, optional, satisfy,
+    parser::char::{self, digit, hex_digit, letter, newline, space},
+    eof, sep_by, sep_by1, ParseError, Parser, Stream,
 };
+use std::{fmt, ops::Range};
 
-use crate::dom::{Node, NodeType};
+use crate::dom::{Arena, Node, NodeId, NodeType};
+use crate::source::{LineIndex, SourcePosition};
+
+pub mod loader;
+pub mod tokenizer;
+
+use tokenizer::{Token, Tokenizer};
 
 /// `Stylesheet` represents a single stylesheet.
 /// It consists of multiple rules, which are called "rule-list" in the standard (https://www.w3.org/TR/css-syntax-3/).
-#[derive(Debug, PartialEq)]
+///
+/// Rules in `@media` blocks are kept apart in `media_rules` until they are evaluated by `resolve_media`.
+#[derive(Debug, PartialEq, Clone)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    pub media_rules: Vec<MediaRule>,
+    pub at_rules: Vec<AtRule>,
+    pub origin: Origin,
 }
 
 impl Stylesheet {
+    /// `new` creates an author stylesheet, which is the one written in the document.
     pub fn new(rules: Vec<Rule>) -> Self {
-        Stylesheet { rules: rules }
+        Stylesheet {
+            rules,
+            media_rules: vec![],
+            at_rules: vec![],
+            origin: Origin::Author,
+        }
+    }
+
+    /// `resolve_media` returns a stylesheet without conditional rules, in which the rules of `@media` blocks
+    /// matching the viewport are placed where the blocks were written.
+    pub fn resolve_media(&self, viewport: &Viewport) -> Stylesheet {
+        let mut rules = vec![];
+        let mut media_rules = self.media_rules.iter().peekable();
+        for i in 0..=self.rules.len() {
+            while let Some(media_rule) = media_rules.next_if(|media_rule| media_rule.position == i) {
+                if media_rule.media.iter().any(|query| query.matches(viewport)) {
+                    rules.extend(media_rule.rules.iter().cloned());
+                }
+            }
+            rules.extend(self.rules.get(i).cloned());
+        }
+        Stylesheet {
+            rules,
+            media_rules: vec![],
+            at_rules: self.at_rules.clone(),
+            origin: self.origin,
+        }
+    }
+
+    /// `locate` records that the rules parsed from a string are written in `source`, a URL or a path,
+    /// and converts their positions into the ones in `source` if the string starts at `start` in it,
+    /// e.g. the content of `<style>` in a document.
+    /// Rules which already know their source, such as imported ones, are left as they are.
+    pub fn locate(&mut self, source: Option<&str>, start: Option<SourcePosition>) {
+        let rules = self
+            .rules
+            .iter_mut()
+            .chain(self.media_rules.iter_mut().flat_map(|media_rule| media_rule.rules.iter_mut()));
+        for rule in rules.filter(|rule| rule.source.is_none()) {
+            rule.source = source.map(str::to_string);
+            if let Some(start) = start {
+                rule.position = rule.position.map(|p| p.relative_to(start));
+                for declaration in &mut rule.declarations {
+                    declaration.position = declaration.position.map(|p| p.relative_to(start));
+                }
+            }
+        }
+    }
+}
+
+/// `Viewport` is the size of the area the document is rendered into, in character cells.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Viewport {
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A character cell is regarded as `CELL_WIDTH_PX` pixels wide and `CELL_HEIGHT_PX` pixels tall,
+/// so that `1ch` is a cell and `1em` is a line.
+pub const CELL_WIDTH_PX: f64 = 8.0;
+pub const CELL_HEIGHT_PX: f64 = 16.0;
+
+/// `MediaRule` represents an `@media` rule.
+/// See https://www.w3.org/TR/css-conditional-3/#at-media
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaRule {
+    pub media: MediaQueryList,
+    pub rules: Vec<Rule>,
+    // この`@media`より前にあるルールの数。カスケードの順序を保つために使う
+    pub position: usize,
+}
+
+/// `MediaQueryList` matches when any of the queries matches.
+/// An empty list matches everything, as in `@media { ... }`.
+pub type MediaQueryList = Vec<MediaQuery>;
+
+/// `MediaQuery` represents a media query like `not screen and (max-width: 80ch)`.
+/// See https://www.w3.org/TR/mediaqueries-4/#media
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaQuery {
+    pub negated: bool,
+    // 省略された場合は`all`
+    pub media_type: String,
+    pub features: Vec<MediaFeature>,
+}
+
+/// `MediaFeature` represents a media feature like `(max-width: 80ch)` or `(color)`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaFeature {
+    pub name: String,
+    pub value: Option<CSSValue>,
+}
+
+impl MediaQuery {
+    pub fn matches(&self, viewport: &Viewport) -> bool {
+        let media_type = matches!(self.media_type.to_ascii_lowercase().as_str(), "all" | "screen");
+        let matches = media_type && self.features.iter().all(|feature| feature.matches(viewport));
+        matches != self.negated
     }
 }
 
+impl MediaFeature {
+    /// `matches` evaluates the feature. Unknown features never match.
+    /// See https://www.w3.org/TR/mediaqueries-4/#mq-features
+    pub fn matches(&self, viewport: &Viewport) -> bool {
+        let width = viewport.width as f64 * CELL_WIDTH_PX;
+        let height = viewport.height as f64 * CELL_HEIGHT_PX;
+        let length = |value: &Option<CSSValue>| match value {
+            Some(CSSValue::Length(value, unit)) => Some(unit.to_px(*value, viewport)),
+            Some(CSSValue::Integer(0)) => Some(0.0),
+            _ => None,
+        };
+
+        let name = self.name.to_ascii_lowercase();
+        match (name.as_str(), &self.value) {
+            ("width", None) => width > 0.0,
+            ("height", None) => height > 0.0,
+            ("width", value) => length(value).is_some_and(|v| width == v),
+            ("min-width", value) => length(value).is_some_and(|v| width >= v),
+            ("max-width", value) => length(value).is_some_and(|v| width <= v),
+            ("height", value) => length(value).is_some_and(|v| height == v),
+            ("min-height", value) => length(value).is_some_and(|v| height >= v),
+            ("max-height", value) => length(value).is_some_and(|v| height <= v),
+            ("orientation", Some(CSSValue::Keyword(orientation))) => match orientation.to_ascii_lowercase().as_str() {
+                "portrait" => height >= width,
+                "landscape" => width > height,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// `AtRule` represents an at-rule like `@font-face { ... }`, which is kept as it is written.
+/// The prelude and the block are the source text in which comments are replaced with spaces.
+/// See https://www.w3.org/TR/css-syntax-3/#at-rule
+#[derive(Debug, PartialEq, Clone)]
+pub struct AtRule {
+    pub name: String,
+    pub prelude: String,
+    // `@import "a.css";`のようにブロックを持たないものはNone
+    pub block: Option<String>,
+}
+
+/// `Origin` represents where a stylesheet comes from.
+/// See https://www.w3.org/TR/css-cascade-4/#cascading-origins
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
 /// `Rule` represents a single CSS rule.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    // セレクタの先頭の、スタイルシート中の位置
+    pub position: Option<SourcePosition>,
+    // 規則が書かれたスタイルシートまたは文書のURL。文字列から読んだ場合はNone
+    pub source: Option<String>,
+}
+
+/// Rules are compared regardless of their positions and sources, so that the same rules parsed from different sources are equal.
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.selectors == other.selectors && self.declarations == other.declarations
+    }
 }
 
 impl Rule {
-    pub fn matches(&self, n: &Box<Node>) -> bool {
-        self.selectors.iter().any(|s| s.matches(n))
+    pub fn matches(&self, arena: &Arena, id: NodeId) -> bool {
+        self.selectors.iter().any(|s| s.matches(arena, id))
+    }
+
+    /// `matching_specificity` returns the highest specificity among the selectors matching the node.
+    /// See https://www.w3.org/TR/selectors-4/#specificity-rules
+    pub fn matching_specificity(&self, arena: &Arena, id: NodeId) -> Option<Specificity> {
+        self.selectors
+            .iter()
+            .filter(|s| s.matches(arena, id))
+            .map(|s| s.specificity())
+            .max()
     }
 }
 
-/// NOTE: This is not compliant to the standard for simplicity.
+/// `Specificity` is a tuple of the numbers of ID selectors, class-like selectors and type selectors.
+/// Specificities are compared lexicographically.
+/// See https://www.w3.org/TR/selectors-4/#specificity
+pub type Specificity = (usize, usize, usize);
+
+/// `Selector` represents *a complex selector*, i.e. a chain of compound selectors separated by combinators.
+/// See https://www.w3.org/TR/selectors-4/#complex
 ///
-/// In the standard, *a selector* is *a chain* of one or more sequences of simple selectors separated by combinators,
-/// where a sequence of simple selectors is a chain of simple selectors that are not separated by a combinator.
-/// Hence `Selector` is in fact something like `Vec<Vec<SimpleSelector>>`.
-pub type Selector = SimpleSelector;
+/// The chain is stored from right to left: `compound` is the rightmost compound selector,
+/// and `context` is the combinator and the rest of the chain on its left.
+/// For example, `ul > li` is `Selector { compound: li, context: Some((Child, ul)) }`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Selector {
+    pub compound: CompoundSelector,
+    pub context: Option<(Combinator, Box<Selector>)>,
+}
+
+/// `CompoundSelector` represents *a compound selector*, i.e. a sequence of simple selectors that are not separated by a combinator.
+/// It matches an element only when all of the simple selectors match it, e.g. `div#main.note`.
+/// See https://www.w3.org/TR/selectors-4/#compound
+pub type CompoundSelector = Vec<SimpleSelector>;
+
+/// `Combinator` represents a combinator defined in the following standard:
+/// https://www.w3.org/TR/selectors-3/#combinators
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Combinator {
+    Descendant,        // A B
+    Child,             // A > B
+    NextSibling,       // A + B
+    SubsequentSibling, // A ~ B
+}
+
+impl From<CompoundSelector> for Selector {
+    fn from(compound: CompoundSelector) -> Self {
+        Selector {
+            compound,
+            context: None,
+        }
+    }
+}
+
+impl From<SimpleSelector> for Selector {
+    fn from(simple: SimpleSelector) -> Self {
+        Selector::from(vec![simple])
+    }
+}
+
+impl Selector {
+    pub fn specificity(&self) -> Specificity {
+        let (a, b, c) = self
+            .context
+            .as_ref()
+            .map(|(_, context)| context.specificity())
+            .unwrap_or((0, 0, 0));
+        self.compound.iter().fold((a, b, c), |(a, b, c), simple| match simple {
+            SimpleSelector::IdSelector { .. } => (a + 1, b, c),
+            SimpleSelector::ClassSelector { .. } | SimpleSelector::AttributeSelector { .. } => (a, b + 1, c),
+            SimpleSelector::TypeSelector { .. } => (a, b, c + 1),
+            SimpleSelector::UniversalSelector => (a, b, c),
+        })
+    }
+
+    /// `matches` checks the rightmost compound selector against the node first,
+    /// and then walks ancestors or previous siblings to match the rest of the chain.
+    /// Only elements are matched; e.g. `*` does not match a text.
+    pub fn matches(&self, arena: &Arena, id: NodeId) -> bool {
+        if !matches!(arena[id].node_type, NodeType::Element(_)) {
+            return false;
+        }
+        if !self.compound.iter().all(|simple| simple.matches(&arena[id])) {
+            return false;
+        }
+        let (combinator, context) = match self.context {
+            Some((combinator, ref context)) => (combinator, context),
+            None => return true,
+        };
+
+        let is_element = |node: &NodeId| matches!(arena[*node].node_type, NodeType::Element(_));
+        match combinator {
+            Combinator::Descendant => arena
+                .ancestors(id)
+                .filter(is_element)
+                .any(|ancestor| context.matches(arena, ancestor)),
+            Combinator::Child => arena
+                .ancestors(id)
+                .take(1)
+                .filter(is_element)
+                .any(|parent| context.matches(arena, parent)),
+            Combinator::NextSibling => arena
+                .previous_siblings(id)
+                .filter(is_element)
+                .take(1)
+                .any(|sibling| context.matches(arena, sibling)),
+            Combinator::SubsequentSibling => arena
+                .previous_siblings(id)
+                .filter(is_element)
+                .any(|sibling| context.matches(arena, sibling)),
+        }
+    }
+}
 
 /// `SimpleSelector` represents a simple selector defined in the following standard:
 /// https://www.w3.org/TR/selectors-3/#selector-syntax
-#[derive(Debug, PartialEq)]
+///
+/// Tag names and attribute names are parsed in lowercase, as the HTML parser lowercases them.
+#[derive(Debug, PartialEq, Clone)]
 pub enum SimpleSelector {
     UniversalSelector,
     TypeSelector {
         tag_name: String,
     },
     AttributeSelector {
-        tag_name: String,
         op: AttributeSelectorOp,
         attribute: String,
         value: String,
+        // `[attr=value i]` compares values ASCII case-insensitively
+        case_insensitive: bool,
     },
     ClassSelector {
         class_name: String,
     },
-    // TODO (enhancement): support multiple attribute selectors like `a[href=bar][ping=foo]`
-    // TODO (enhancement): support more attribute selectors
+    IdSelector {
+        id: String,
+    },
 }
 
 impl SimpleSelector {
-    pub fn matches(&self, n: &Box<Node>) -> bool {
-        // todo!("you need to implement this")
+    pub fn matches(&self, n: &Node) -> bool {
         match self {
             // *
-            SimpleSelector::UniversalSelector => true,
+            SimpleSelector::UniversalSelector => matches!(n.node_type, NodeType::Element(_)),
             // tag_name
             SimpleSelector::TypeSelector { tag_name } => {
                 match &n.node_type {
@@ -77,20 +357,33 @@ impl SimpleSelector {
             },
             // attribute
             SimpleSelector::AttributeSelector {
-                tag_name,
                 op,
                 attribute,
-                value
+                value,
+                case_insensitive,
             } => {
                 match &n.node_type {
                     NodeType::Element(e) => {
-                        let is_mached_tag_name = e.tag_name.as_str() == tag_name;
-                        is_mached_tag_name && match op {
-                            AttributeSelectorOp::Eq => e.attributes.get(attribute) == Some(value),
-                            AttributeSelectorOp::Contain => e.attributes.get(attribute).map(|value| {
-                                value.split_ascii_whitespace().find(|v| v == value).is_some()
-                            })
-                            .unwrap_or(false)
+                        let actual = match e.attribute(attribute) {
+                            Some(actual) => actual,
+                            None => return false,
+                        };
+                        let (actual, expected) = if *case_insensitive {
+                            (actual.to_ascii_lowercase(), value.to_ascii_lowercase())
+                        } else {
+                            (actual.clone(), value.clone())
+                        };
+                        match op {
+                            AttributeSelectorOp::Exists => true,
+                            AttributeSelectorOp::Eq => actual == expected,
+                            AttributeSelectorOp::Contain => actual.split_ascii_whitespace().any(|v| v == expected),
+                            AttributeSelectorOp::DashMatch => {
+                                actual == expected || actual.starts_with(&format!("{}-", expected))
+                            },
+                            // 空文字列との比較は何にもマッチしない
+                            AttributeSelectorOp::Prefix => !expected.is_empty() && actual.starts_with(&expected),
+                            AttributeSelectorOp::Suffix => !expected.is_empty() && actual.ends_with(&expected),
+                            AttributeSelectorOp::Substring => !expected.is_empty() && actual.contains(&expected),
                         }
                     },
                     _ => false,
@@ -98,7 +391,14 @@ impl SimpleSelector {
             },
             SimpleSelector::ClassSelector { class_name } => {
                 match &n.node_type {
-                    NodeType::Element(e) => e.attributes.get("class") == Some(class_name),
+                    NodeType::Element(e) => e.class_list().iter().any(|c| c == class_name),
+                    _ => false
+                }
+            },
+            // #id
+            SimpleSelector::IdSelector { id } => {
+                match &n.node_type {
+                    NodeType::Element(e) => e.id() == Some(id),
                     _ => false
                 }
             },
@@ -108,10 +408,15 @@ impl SimpleSelector {
 
 /// `AttributeSelectorOp` is an operator which is allowed to use.
 /// See https://www.w3.org/TR/selectors-3/#attribute-selectors to check the full list of available operators.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum AttributeSelectorOp {
-    Eq,      // =
-    Contain, // ~=
+    Exists,    // [attr]
+    Eq,        // =
+    Contain,   // ~=
+    DashMatch, // |=
+    Prefix,    // ^=
+    Suffix,    // $=
+    Substring, // *=
 }
 
 /// `Declaration` represents a CSS declaration defined at [CSS Syntax Module Level 3](https://www.w3.org/TR/css-syntax-3/#declaration)
@@ -121,24 +426,627 @@ pub enum AttributeSelectorOp {
 /// - properties, which are mostly used in "qualified rules" like `.foo {bar: piyo}` https://www.w3.org/Style/CSS/all-descriptors.en.html
 ///
 /// For simplicity, we handle two types of declarations together.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Declaration {
     pub name: String,
     pub value: CSSValue,
-    // TODO (enhancement): add a field for `!important`
+    // `!important`が付いているかどうか
+    pub important: bool,
+    // プロパティ名の先頭の、スタイルシート中の位置
+    pub position: Option<SourcePosition>,
+}
+
+/// Declarations are compared regardless of their positions, as `Rule` is.
+impl PartialEq for Declaration {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.value == other.value && self.important == other.important
+    }
 }
 
 /// `CSSValue` represents some of *component value types* defined at [CSS Values and Units Module Level 3](https://www.w3.org/TR/css-values-3/#component-types).
 #[derive(Debug, PartialEq, Clone)]
 pub enum CSSValue {
     Keyword(String),
+    Length(f64, Unit),
+    Percentage(f64),
+    Integer(i64),
+    Number(f64),
+    Color(Color),
+    String(String),
+    Url(String),
+    // `1px 2px`
+    SpaceList(Vec<CSSValue>),
+    // `"Foo Bar", serif`
+    CommaList(Vec<CSSValue>),
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)?;
+        if self.important {
+            write!(f, " !important")?;
+        }
+        Ok(())
+    }
+}
+
+/// `CSSValue` is serialized into the form it can be parsed from.
+/// See https://www.w3.org/TR/cssom-1/#serializing-css-values
+impl fmt::Display for CSSValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        match self {
+            CSSValue::Keyword(keyword) => write!(f, "{}", keyword),
+            CSSValue::Length(value, unit) => write!(f, "{}{}", value, unit.name()),
+            CSSValue::Percentage(value) => write!(f, "{}%", value),
+            CSSValue::Integer(value) => write!(f, "{}", value),
+            CSSValue::Number(value) => write!(f, "{}", value),
+            CSSValue::Color(Color { r, g, b, a: 255 }) => write!(f, "rgb({}, {}, {})", r, g, b),
+            CSSValue::Color(Color { r, g, b, a }) => write!(f, "rgba({}, {}, {}, {})", r, g, b, *a as f64 / 255.0),
+            CSSValue::String(value) => write!(f, "\"{}\"", escape(value)),
+            CSSValue::Url(url) => write!(f, "url(\"{}\")", escape(url)),
+            CSSValue::SpaceList(values) | CSSValue::CommaList(values) => {
+                let separator = if matches!(self, CSSValue::SpaceList(_)) { " " } else { ", " };
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", separator)?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl CSSValue {
+    /// `to_color` returns the color the value represents, resolving named colors like `red`.
+    pub fn to_color(&self) -> Option<Color> {
+        match self {
+            CSSValue::Color(color) => Some(*color),
+            CSSValue::Keyword(name) => Color::from_name(name),
+            _ => None,
+        }
+    }
+}
+
+/// `Unit` represents a unit of `<length>`.
+/// See https://www.w3.org/TR/css-values-3/#lengths
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Unit {
+    // font-relative lengths
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    // viewport-percentage lengths
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    // absolute lengths
+    Px,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
+}
+
+impl Unit {
+    /// `to_px` converts a length into pixels.
+    /// See https://www.w3.org/TR/css-values-3/#absolute-lengths
+    pub fn to_px(self, value: f64, viewport: &Viewport) -> f64 {
+        let viewport_width = viewport.width as f64 * CELL_WIDTH_PX;
+        let viewport_height = viewport.height as f64 * CELL_HEIGHT_PX;
+        match self {
+            // フォントサイズは1行の高さとみなす
+            Unit::Em | Unit::Rem => value * CELL_HEIGHT_PX,
+            Unit::Ex | Unit::Ch => value * CELL_WIDTH_PX,
+            Unit::Vw => value * viewport_width / 100.0,
+            Unit::Vh => value * viewport_height / 100.0,
+            Unit::Vmin => value * viewport_width.min(viewport_height) / 100.0,
+            Unit::Vmax => value * viewport_width.max(viewport_height) / 100.0,
+            Unit::Px => value,
+            Unit::Cm => value * 96.0 / 2.54,
+            Unit::Mm => value * 96.0 / 25.4,
+            Unit::Q => value * 96.0 / 101.6,
+            Unit::In => value * 96.0,
+            Unit::Pt => value * 96.0 / 72.0,
+            Unit::Pc => value * 96.0 / 6.0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Ex => "ex",
+            Unit::Ch => "ch",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::Vmin => "vmin",
+            Unit::Vmax => "vmax",
+            Unit::Px => "px",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+            Unit::Q => "q",
+            Unit::In => "in",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Unit> {
+        // 単位は大文字小文字を区別しない
+        match name.to_ascii_lowercase().as_str() {
+            "em" => Some(Unit::Em),
+            "rem" => Some(Unit::Rem),
+            "ex" => Some(Unit::Ex),
+            "ch" => Some(Unit::Ch),
+            "vw" => Some(Unit::Vw),
+            "vh" => Some(Unit::Vh),
+            "vmin" => Some(Unit::Vmin),
+            "vmax" => Some(Unit::Vmax),
+            "px" => Some(Unit::Px),
+            "cm" => Some(Unit::Cm),
+            "mm" => Some(Unit::Mm),
+            "q" => Some(Unit::Q),
+            "in" => Some(Unit::In),
+            "pt" => Some(Unit::Pt),
+            "pc" => Some(Unit::Pc),
+            _ => None,
+        }
+    }
+}
+
+/// `Color` represents a `<color>` value in sRGB.
+/// See https://www.w3.org/TR/css-color-3/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    /// `from_name` resolves the basic color keywords and `transparent`.
+    /// See https://www.w3.org/TR/css-color-3/#html4
+    pub fn from_name(name: &str) -> Option<Color> {
+        let color = match name.to_ascii_lowercase().as_str() {
+            "black" => Color::rgb(0, 0, 0),
+            "silver" => Color::rgb(192, 192, 192),
+            "gray" | "grey" => Color::rgb(128, 128, 128),
+            "white" => Color::rgb(255, 255, 255),
+            "maroon" => Color::rgb(128, 0, 0),
+            "red" => Color::rgb(255, 0, 0),
+            "purple" => Color::rgb(128, 0, 128),
+            "fuchsia" | "magenta" => Color::rgb(255, 0, 255),
+            "green" => Color::rgb(0, 128, 0),
+            "lime" => Color::rgb(0, 255, 0),
+            "olive" => Color::rgb(128, 128, 0),
+            "yellow" => Color::rgb(255, 255, 0),
+            "navy" => Color::rgb(0, 0, 128),
+            "blue" => Color::rgb(0, 0, 255),
+            "teal" => Color::rgb(0, 128, 128),
+            "aqua" | "cyan" => Color::rgb(0, 255, 255),
+            "orange" => Color::rgb(255, 165, 0),
+            "transparent" => Color { r: 0, g: 0, b: 0, a: 0 },
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
+/// `Diagnostic` records a part of a stylesheet which was ignored because of a parse error.
+/// `line` and `column` are 1-origin, and `column` counts characters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
 }
 
 pub fn parse(raw: &str) -> Stylesheet {
-    rules()
+    parse_with_diagnostics(raw).0
+}
+
+/// `parse_with_diagnostics` parses a stylesheet without failing.
+/// Following the error handling of CSS Syntax Module Level 3, an invalid declaration is dropped by itself,
+/// and a rule with an invalid selector is dropped as a whole. The skipped parts are reported as diagnostics.
+/// See https://www.w3.org/TR/css-syntax-3/#error-handling
+pub fn parse_with_diagnostics(raw: &str) -> (Stylesheet, Vec<Diagnostic>) {
+    let mut parser = RuleParser::new(raw);
+    let mut stylesheet = Stylesheet::new(vec![]);
+    parser.consume_rule_list(&mut stylesheet);
+    (stylesheet, parser.diagnostics)
+}
+
+/// `parse_declarations` parses a list of declarations without braces, such as the value of a `style` attribute.
+/// Invalid declarations are dropped and reported as `parse_with_diagnostics` does.
+/// The positions of the declarations are relative to `raw`.
+/// See https://www.w3.org/TR/css-style-attr/#syntax
+pub fn parse_declarations(raw: &str) -> (Vec<Declaration>, Vec<Diagnostic>) {
+    let mut parser = RuleParser::new(raw);
+    let declarations = parser.parse_declaration_list(0..parser.end);
+    (declarations, parser.diagnostics)
+}
+
+/// `parse_declaration` parses a single declaration like `color: red`, which must not be followed by anything.
+pub fn parse_declaration(raw: &str) -> Option<Declaration> {
+    (whitespaces(), declaration(), whitespaces(), eof())
         .parse(raw)
-        .map(|(rules, _)| Stylesheet::new(rules))
-        .unwrap()
+        .ok()
+        .map(|((_, declaration, _, _), _)| declaration)
+}
+
+/// `parse_media` parses a media query list such as the `media` attribute of `<style>` and `<link>`.
+/// An empty string is an empty list, which matches everything.
+/// See https://html.spec.whatwg.org/multipage/semantics.html#attr-link-media
+pub fn parse_media(raw: &str) -> Option<MediaQueryList> {
+    (media_query_list(), eof())
+        .parse(raw.trim())
+        .ok()
+        .map(|((media, _), _)| media)
+}
+
+/// `parse_value` parses a declaration value like `1px solid`.
+pub fn parse_value(raw: &str) -> Option<CSSValue> {
+    (whitespaces(), css_value(), whitespaces(), eof())
+        .parse(raw)
+        .ok()
+        .map(|((_, value, _, _), _)| value)
+}
+
+/// `RuleParser` groups the tokens of a stylesheet into rules and declarations.
+/// Selectors and declaration values are then parsed from their source text, in which comments are replaced with spaces.
+/// See https://www.w3.org/TR/css-syntax-3/#parsing
+struct RuleParser<'a> {
+    raw: &'a str,
+    lines: LineIndex,
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+    // ブロックの中身を読むときは、ブロックの終わりまでに制限する
+    end: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> RuleParser<'a> {
+    fn new(raw: &'a str) -> RuleParser<'a> {
+        let tokens = Tokenizer::new(raw).tokenize();
+        RuleParser {
+            raw,
+            lines: LineIndex::new(raw),
+            end: tokens.len(),
+            tokens,
+            pos: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens[..self.end].get(self.pos).map(|(token, _)| token)
+    }
+
+    /// `text` returns the source text of the tokens in `range`.
+    /// A comment between the tokens is replaced with a space, so that `div/**/p` does not become `divp`.
+    fn text(&self, range: Range<usize>) -> String {
+        let mut text = String::new();
+        let mut end = None;
+        for (_, span) in &self.tokens[range] {
+            // トークンの間の隙間はコメントだけ
+            if end.is_some_and(|end| end < span.start) {
+                text.push(' ');
+            }
+            text.push_str(&self.raw[span.clone()]);
+            end = Some(span.end);
+        }
+        text
+    }
+
+    /// `offset` returns the byte offset of the first non-whitespace token in `range`.
+    fn offset(&self, range: Range<usize>) -> usize {
+        self.tokens[range.clone()]
+            .iter()
+            .find(|(token, _)| *token != Token::Whitespace)
+            .or_else(|| self.tokens.get(range.start))
+            .map(|(_, span)| span.start)
+            .unwrap_or_else(|| self.raw.len())
+    }
+
+    fn position(&self, offset: usize) -> SourcePosition {
+        self.lines.position(self.raw, offset)
+    }
+
+    fn report(&mut self, offset: usize, message: String) {
+        let SourcePosition { line, column, .. } = self.position(offset);
+        self.diagnostics.push(Diagnostic { line, column, message });
+    }
+
+    /// `consume_rule_list` adds the rules to `stylesheet`.
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-list-of-rules
+    fn consume_rule_list(&mut self, stylesheet: &mut Stylesheet) {
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Whitespace | Token::Cdo | Token::Cdc => self.pos += 1,
+                Token::AtKeyword(name) if name.eq_ignore_ascii_case("media") => {
+                    if let Some(media_rule) = self.consume_media_rule(stylesheet.rules.len()) {
+                        stylesheet.media_rules.push(media_rule);
+                    }
+                },
+                Token::AtKeyword(_) => {
+                    let (at_rule, _) = self.consume_at_rule();
+                    stylesheet.at_rules.push(at_rule);
+                },
+                _ => {
+                    if let Some(rule) = self.consume_qualified_rule() {
+                        stylesheet.rules.push(rule);
+                    }
+                },
+            }
+        }
+    }
+
+    /// `consume_media_rule` parses the media query list and the rules in the block of `@media`.
+    fn consume_media_rule(&mut self, position: usize) -> Option<MediaRule> {
+        let at = self.tokens[self.pos].1.start;
+        let (at_rule, block) = self.consume_at_rule();
+        let block = match block {
+            Some(block) => block,
+            None => {
+                self.report(at, "`@media` without a block; the rule is ignored".to_string());
+                return None;
+            },
+        };
+
+        let media = (media_query_list(), eof()).parse(at_rule.prelude.as_str()).map(|((media, _), _)| media);
+        let media = match media {
+            Ok(media) => media,
+            Err(_) => {
+                self.report(at, format!("invalid media query `{}`; the rule is ignored", at_rule.prelude));
+                return None;
+            },
+        };
+
+        let (resume, end) = (self.pos, self.end);
+        self.pos = block.start;
+        self.end = block.end;
+        let mut nested = Stylesheet::new(vec![]);
+        self.consume_rule_list(&mut nested);
+        self.pos = resume;
+        self.end = end;
+
+        if !nested.media_rules.is_empty() {
+            self.report(at, "nested `@media` is not supported; the inner rules are ignored".to_string());
+        }
+        Some(MediaRule {
+            media,
+            rules: nested.rules,
+            position,
+        })
+    }
+
+    /// `consume_at_rule` also returns the range of the tokens in the block if any.
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-at-rule
+    fn consume_at_rule(&mut self) -> (AtRule, Option<Range<usize>>) {
+        let (name, at) = match &self.tokens[self.pos] {
+            (Token::AtKeyword(name), span) => (name.clone(), span.start),
+            _ => unreachable!("an at-rule starts with an at-keyword"),
+        };
+        self.pos += 1;
+
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                None => {
+                    self.report(at, format!("unexpected end of input in `@{}`", name));
+                    let prelude = self.text(start..self.pos).trim().to_string();
+                    return (AtRule { name, prelude, block: None }, None);
+                },
+                Some(Token::Semicolon) => {
+                    let prelude = self.text(start..self.pos).trim().to_string();
+                    self.pos += 1;
+                    return (AtRule { name, prelude, block: None }, None);
+                },
+                Some(Token::OpenCurly) => {
+                    let prelude = self.text(start..self.pos).trim().to_string();
+                    let block = self.consume_block();
+                    let at_rule = AtRule {
+                        name,
+                        prelude,
+                        block: Some(self.text(block.clone())),
+                    };
+                    return (at_rule, Some(block));
+                },
+                Some(_) => self.skip_component_value(),
+            }
+        }
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-qualified-rule
+    fn consume_qualified_rule(&mut self) -> Option<Rule> {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                None => {
+                    let offset = self.offset(start..self.pos);
+                    self.report(offset, "unexpected end of input; the rule is ignored".to_string());
+                    return None;
+                },
+                Some(Token::OpenCurly) => break,
+                Some(_) => self.skip_component_value(),
+            }
+        }
+        let prelude = self.text(start..self.pos);
+        let prelude_offset = self.offset(start..self.pos);
+
+        let block = self.consume_block();
+        let declarations = self.parse_declaration_list(block);
+
+        let selectors = (selectors(), eof()).parse(prelude.trim()).map(|((selectors, _), _)| selectors);
+        match selectors {
+            Ok(selectors) => Some(Rule {
+                selectors,
+                declarations,
+                position: Some(self.position(prelude_offset)),
+                source: None,
+            }),
+            Err(_) => {
+                self.report(prelude_offset, format!("invalid selector `{}`; the rule is ignored", prelude.trim()));
+                None
+            },
+        }
+    }
+
+    /// `consume_block` consumes `{ ... }` and returns the range of the tokens inside.
+    /// An unclosed block is closed at the end of input.
+    fn consume_block(&mut self) -> Range<usize> {
+        let open = self.tokens[self.pos].1.start;
+        self.pos += 1;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                None => {
+                    self.report(open, "unclosed block".to_string());
+                    return start..self.pos;
+                },
+                Some(Token::CloseCurly) => {
+                    self.pos += 1;
+                    return start..self.pos - 1;
+                },
+                Some(_) => self.skip_component_value(),
+            }
+        }
+    }
+
+    /// `skip_component_value` skips a token, or a whole block or function with its contents.
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-component-value
+    fn skip_component_value(&mut self) {
+        let close = match self.tokens[self.pos].0 {
+            Token::OpenCurly => Token::CloseCurly,
+            Token::OpenSquare => Token::CloseSquare,
+            Token::OpenParen | Token::Function(_) => Token::CloseParen,
+            _ => {
+                self.pos += 1;
+                return;
+            },
+        };
+        self.pos += 1;
+        while let Some(token) = self.peek() {
+            if *token == close {
+                self.pos += 1;
+                return;
+            }
+            self.skip_component_value();
+        }
+    }
+
+    /// `parse_declaration_list` parses the declarations in `range`, which are separated by `;`.
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
+    fn parse_declaration_list(&mut self, range: Range<usize>) -> Vec<Declaration> {
+        let resume = self.pos;
+        self.pos = range.start;
+
+        let mut declarations = vec![];
+        while self.pos < range.end {
+            let start = self.pos;
+            while self.pos < range.end && self.peek() != Some(&Token::Semicolon) {
+                self.skip_component_value();
+            }
+            let end = self.pos.min(range.end);
+            let source = self.text(start..end);
+            let source = source.trim();
+            if !source.is_empty() {
+                match (declaration().skip(whitespaces()), eof()).parse(source) {
+                    Ok(((declaration, _), _)) => {
+                        let position = self.position(self.offset(start..end));
+                        declarations.push(Declaration {
+                            position: Some(position),
+                            ..declaration
+                        });
+                    },
+                    Err(_) => {
+                        let offset = self.offset(start..end);
+                        self.report(offset, format!("invalid declaration `{}`; the declaration is ignored", source));
+                    },
+                }
+            }
+            self.pos = end + 1;
+        }
+
+        self.pos = resume;
+        declarations
+    }
+}
+
+/// `media_query_list` parses a comma-separated list of media queries.
+/// NOTE: the range syntax like `(width < 80ch)` is not supported.
+/// See https://www.w3.org/TR/mediaqueries-4/#mq-syntax
+fn media_query_list<Input>() -> impl Parser<Input, Output = MediaQueryList>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let keyword = |name: &'static str| {
+        attempt(
+            identifier()
+                .and_then(move |ident: String| {
+                    if ident.eq_ignore_ascii_case(name) {
+                        Ok(ident)
+                    } else {
+                        Err(message_error::<Input>("unexpected keyword"))
+                    }
+                })
+                .skip(whitespaces()),
+        )
+    };
+    let and = move || keyword("and");
+    let feature = || {
+        (
+            char::char('(').skip(whitespaces()),
+            identifier().skip(whitespaces()),
+            optional((char::char(':').skip(whitespaces()), component_value().skip(whitespaces())).map(|(_, v)| v)),
+            char::char(')').skip(whitespaces()),
+        )
+            .map(|(_, name, value, _)| MediaFeature { name, value })
+    };
+    let features = move || sep_by1::<Vec<_>, _, _, _>(feature(), and());
+
+    // `screen and (min-width: 80ch)`
+    let typed_query = (
+        optional(choice((keyword("not").map(|_| true), keyword("only").map(|_| false)))),
+        identifier().skip(whitespaces()),
+        many::<Vec<_>, _, _>((and(), feature()).map(|(_, feature)| feature)),
+    )
+        .map(|(negated, media_type, features)| MediaQuery {
+            negated: negated.unwrap_or(false),
+            media_type,
+            features,
+        });
+    // `(min-width: 80ch) and (orientation: landscape)`
+    let feature_query = features().map(|features| MediaQuery {
+        negated: false,
+        media_type: "all".to_string(),
+        features,
+    });
+
+    (
+        whitespaces(),
+        sep_by::<Vec<_>, _, _, _>(choice((feature_query, typed_query)), char::char(',').skip(whitespaces())),
+    )
+        .map(|(_, queries)| queries)
 }
 
 fn whitespaces<Input>() -> impl Parser<Input, Output = String>
@@ -149,40 +1057,80 @@ where
     many::<String, _, _>(space().or(newline()))
 }
 
-fn rules<Input>() -> impl Parser<Input, Output = Vec<Rule>>
+fn selectors<Input>() -> impl Parser<Input, Output = Vec<Selector>>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (whitespaces(), many(rule().skip(whitespaces()))).map(|(_, rules)| rules)
+    sep_by(
+        selector().skip(whitespaces()),
+        char::char(',').skip(whitespaces()),
+    )
 }
 
-fn rule<Input>() -> impl Parser<Input, Output = Rule>
+fn selector<Input>() -> impl Parser<Input, Output = Selector>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
-        selectors().skip(whitespaces()),
-        char::char('{').skip(whitespaces()),
-        declarations().skip(whitespaces()),
-        char::char('}'),
+        compound_selector(),
+        many::<Vec<_>, _, _>(attempt((combinator(), compound_selector()))),
     )
-        .map(|(selectors, _, declarations, _)| Rule {
-            selectors,
-            declarations,
+        .map(|(first, rest)| {
+            rest.into_iter()
+                .fold(Selector::from(first), |context, (combinator, compound)| Selector {
+                    compound,
+                    context: Some((combinator, Box::new(context))),
+                })
         })
 }
 
-fn selectors<Input>() -> impl Parser<Input, Output = Vec<Selector>>
+fn combinator<Input>() -> impl Parser<Input, Output = Combinator>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    sep_by(
-        simple_selector().skip(whitespaces()),
-        char::char(',').skip(whitespaces()),
+    let explicit_combinator = (
+        whitespaces(),
+        one_of(">+~".chars()),
+        whitespaces(),
     )
+        .map(|(_, c, _)| match c {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            _ => Combinator::SubsequentSibling,
+        });
+    // 空白だけの場合は子孫結合子
+    let descendant_combinator = many1::<String, _, _>(space().or(newline())).map(|_| Combinator::Descendant);
+
+    choice((attempt(explicit_combinator), descendant_combinator))
+}
+
+fn compound_selector<Input>() -> impl Parser<Input, Output = CompoundSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    many1::<Vec<_>, _, _>(simple_selector()).and_then(|compound| {
+        // `*`とタグ名は先頭にしか書けない
+        let misplaced = compound.iter().skip(1).any(|simple| {
+            matches!(
+                simple,
+                SimpleSelector::UniversalSelector | SimpleSelector::TypeSelector { .. }
+            )
+        });
+        if misplaced {
+            return Err(<Input::Error as combine::error::ParseError<
+                char,
+                Input::Range,
+                Input::Position,
+            >>::StreamError::message_static_message(
+                "type selector must come first in a compound selector",
+            ));
+        }
+        Ok(compound)
+    })
 }
 
 fn simple_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
@@ -192,60 +1140,103 @@ where
 {
     let universal_selector = char::char('*').map(|_| SimpleSelector::UniversalSelector);
     let class_selector =
-        (char::char('.'), many1(letter())).map(|(_, class_name)| SimpleSelector::ClassSelector {
+        (char::char('.'), identifier()).map(|(_, class_name)| SimpleSelector::ClassSelector {
             class_name: class_name,
         });
-    let type_or_attribute_selector = (
-        many1(letter()).skip(whitespaces()),
+    let id_selector = (char::char('#'), identifier()).map(|(_, id)| SimpleSelector::IdSelector { id });
+    // タグ名には`h1`のように数字や、カスタム要素の`my-element`のようにハイフンも含まれる
+    // HTMLのタグ名と属性名は大文字と小文字を区別しないので、パーサーと同じく小文字にそろえる
+    let type_selector = identifier().map(|tag_name| SimpleSelector::TypeSelector {
+        tag_name: tag_name.to_ascii_lowercase(),
+    });
+    let attribute_selector = (
+        char::char('[').skip(whitespaces()),
+        identifier().skip(whitespaces()),
         optional((
-            char::char('[').skip(whitespaces()),
-            many1(letter()),
-            choice((char::string("="), char::string("~="))),
-            many1(letter()),
-            char::char(']'),
+            attribute_selector_op().skip(whitespaces()),
+            choice((identifier(), string_literal())).skip(whitespaces()),
+            optional(one_of("iIsS".chars()).skip(whitespaces())),
         )),
+        char::char(']'),
     )
-        .and_then(|(tag_name, opts)| match opts {
-            Some((_, attribute, op, value, _)) => {
-                let op = match op {
-                    "=" => AttributeSelectorOp::Eq,
-                    "~=" => AttributeSelectorOp::Contain,
-                    _ => {
-                        return Err(<Input::Error as combine::error::ParseError<
-                            char,
-                            Input::Range,
-                            Input::Position,
-                        >>::StreamError::message_static_message(
-                            "invalid attribute selector op",
-                        ))
-                    }
-                };
-                Ok(SimpleSelector::AttributeSelector {
-                    tag_name: tag_name,
-                    attribute: attribute,
-                    op: op,
-                    value: value,
-                })
-            }
-            None => Ok(SimpleSelector::TypeSelector { tag_name: tag_name }),
+        .map(|(_, attribute, matcher, _)| (attribute.to_ascii_lowercase(), matcher))
+        .map(|(attribute, matcher)| match matcher {
+            Some((op, value, flag)) => SimpleSelector::AttributeSelector {
+                attribute,
+                op,
+                value,
+                case_insensitive: matches!(flag, Some('i') | Some('I')),
+            },
+            None => SimpleSelector::AttributeSelector {
+                attribute,
+                op: AttributeSelectorOp::Exists,
+                value: "".to_string(),
+                case_insensitive: false,
+            },
         });
 
     choice((
         universal_selector,
         class_selector,
-        type_or_attribute_selector,
+        id_selector,
+        attribute_selector,
+        type_selector,
+    ))
+}
+
+fn attribute_selector_op<Input>() -> impl Parser<Input, Output = AttributeSelectorOp>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        char::string("=").map(|_| AttributeSelectorOp::Eq),
+        char::string("~=").map(|_| AttributeSelectorOp::Contain),
+        char::string("|=").map(|_| AttributeSelectorOp::DashMatch),
+        char::string("^=").map(|_| AttributeSelectorOp::Prefix),
+        char::string("$=").map(|_| AttributeSelectorOp::Suffix),
+        char::string("*=").map(|_| AttributeSelectorOp::Substring),
     ))
 }
 
-fn declarations<Input>() -> impl Parser<Input, Output = Vec<Declaration>>
+/// `identifier` parses a name like `data-role`, `_private` or `-webkit-box`.
+/// NOTE: escapes are not supported for simplicity.
+/// See https://www.w3.org/TR/css-syntax-3/#ident-token-diagram
+fn identifier<Input>() -> impl Parser<Input, Output = String>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    sep_end_by(
-        declaration().skip(whitespaces()),
-        char::char(';').skip(whitespaces()),
+    let name_start = || satisfy(|c: char| c.is_ascii_alphabetic() || c == '_' || !c.is_ascii());
+    (
+        choice((
+            name_start().map(|c: char| c.to_string()),
+            attempt((char::char('-'), choice((name_start(), char::char('-')))))
+                .map(|(hyphen, c)| format!("{}{}", hyphen, c)),
+        )),
+        many::<String, _, _>(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || !c.is_ascii())),
     )
+        .map(|(head, tail)| format!("{}{}", head, tail))
+}
+
+/// `string_literal` parses a string quoted by `"` or `'`. A backslash escapes the next character.
+fn string_literal<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let quoted = |quote: char| {
+        (
+            char::char(quote),
+            many(choice((
+                (char::char('\\'), any()).map(|(_, c)| c),
+                satisfy(move |c: char| c != quote && c != '\\'),
+            ))),
+            char::char(quote),
+        )
+            .map(|(_, s, _)| s)
+    };
+    choice((quoted('"'), quoted('\'')))
 }
 
 fn declaration<Input>() -> impl Parser<Input, Output = Declaration>
@@ -253,149 +1244,694 @@ where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
+    let important = (
+        whitespaces(),
+        char::char('!').skip(whitespaces()),
+        char::string_cmp("important", |l: char, r: char| l.eq_ignore_ascii_case(&r)),
+    );
     (
-        many1(letter()).skip(whitespaces()),
+        identifier().skip(whitespaces()),
         char::char(':').skip(whitespaces()),
         css_value(),
+        optional(attempt(important)),
     )
-        .map(|(k, _, v)| Declaration { name: k, value: v })
+        .map(|(k, _, v, important)| Declaration {
+            name: k,
+            value: v,
+            important: important.is_some(),
+            position: None,
+        })
 }
 
+/// `css_value` parses a comma-separated list of space-separated lists of component values.
+/// A list with a single item is returned as the item itself.
+/// See https://www.w3.org/TR/css-values-3/#component-combinators
 fn css_value<Input>() -> impl Parser<Input, Output = CSSValue>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    let keyword = many1(letter()).map(|s| CSSValue::Keyword(s));
-    keyword
+    let space_list = (
+        component_value(),
+        many::<Vec<_>, _, _>(attempt((many1::<String, _, _>(space().or(newline())), component_value()).map(|(_, v)| v))),
+    )
+        .map(|(first, mut rest)| {
+            if rest.is_empty() {
+                first
+            } else {
+                rest.insert(0, first);
+                CSSValue::SpaceList(rest)
+            }
+        });
+    sep_by1::<Vec<_>, _, _, _>(space_list, attempt((whitespaces(), char::char(','), whitespaces()))).map(|mut values| {
+        if values.len() == 1 {
+            values.remove(0)
+        } else {
+            CSSValue::CommaList(values)
+        }
+    })
+}
+
+fn component_value<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let hex_color = (char::char('#'), many1::<String, _, _>(hex_digit())).and_then(|(_, hex)| {
+        parse_hex_color(&hex)
+            .map(CSSValue::Color)
+            .ok_or_else(|| message_error::<Input>("invalid hex color"))
+    });
+    let url = (
+        attempt(char::string_cmp("url(", |l: char, r: char| l.eq_ignore_ascii_case(&r))).skip(whitespaces()),
+        choice((
+            string_literal(),
+            many1(satisfy(|c: char| !c.is_whitespace() && !"()'\"".contains(c))),
+        ))
+        .skip(whitespaces()),
+        char::char(')'),
+    )
+        .map(|(_, url, _)| CSSValue::Url(url));
+    let rgb = (
+        attempt(
+            (
+                char::string_cmp("rgb", |l: char, r: char| l.eq_ignore_ascii_case(&r)),
+                optional(one_of("aA".chars())),
+                char::char('('),
+            ),
+        )
+        .skip(whitespaces()),
+        sep_by1::<Vec<_>, _, _, _>(numeric().skip(whitespaces()), char::char(',').skip(whitespaces())),
+        char::char(')'),
+    )
+        .and_then(|(_, args, _)| {
+            rgb_color(&args)
+                .map(CSSValue::Color)
+                .ok_or_else(|| message_error::<Input>("invalid rgb() arguments"))
+        });
+    let keyword = identifier().map(CSSValue::Keyword);
+    let string = string_literal().map(CSSValue::String);
+
+    choice((
+        hex_color,
+        url,
+        rgb,
+        attempt(numeric()),
+        keyword,
+        string,
+    ))
+}
+
+/// `numeric` parses `<number>`, `<integer>`, `<percentage>` and `<length>`.
+/// See https://www.w3.org/TR/css-syntax-3/#consume-numeric-token
+fn numeric<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let digits = || many1::<String, _, _>(digit());
+    let sign = || optional(one_of("+-".chars())).map(|sign| sign.map(String::from).unwrap_or_default());
+    let mantissa = choice((
+        (digits(), optional((char::char('.'), digits()))).map(|(int, frac)| match frac {
+            Some((_, frac)) => (format!("{}.{}", int, frac), false),
+            None => (int, true),
+        }),
+        (char::char('.'), digits()).map(|(_, frac)| (format!("0.{}", frac), false)),
+    ));
+    let exponent = attempt((one_of("eE".chars()), sign(), digits())).map(|(_, sign, exp)| format!("e{}{}", sign, exp));
+    let unit = choice((
+        char::char('%').map(|_| "%".to_string()),
+        many1::<String, _, _>(letter()),
+    ));
+
+    (sign(), mantissa, optional(exponent), optional(unit)).and_then(|(sign, (mantissa, is_integer), exponent, unit)| {
+        let is_integer = is_integer && exponent.is_none();
+        let raw = format!("{}{}{}", sign, mantissa, exponent.unwrap_or_default());
+        let number: f64 = raw.parse().map_err(|_| message_error::<Input>("invalid number"))?;
+        match unit.as_deref() {
+            None if is_integer => raw
+                .parse()
+                .map(CSSValue::Integer)
+                .map_err(|_| message_error::<Input>("integer out of range")),
+            None => Ok(CSSValue::Number(number)),
+            Some("%") => Ok(CSSValue::Percentage(number)),
+            Some(unit) => Unit::from_name(unit)
+                .map(|unit| CSSValue::Length(number, unit))
+                .ok_or_else(|| message_error::<Input>("unknown unit")),
+        }
+    })
+}
+
+/// `parse_hex_color` parses the digits of `#rgb`, `#rgba`, `#rrggbb` and `#rrggbbaa`.
+fn parse_hex_color(hex: &str) -> Option<Color> {
+    let channel = |i: usize, width: usize| {
+        let digits = &hex[i * width..(i + 1) * width];
+        // `#abc`は`#aabbcc`と同じ
+        u8::from_str_radix(&digits.repeat(3 - width), 16).ok()
+    };
+    let width = match hex.len() {
+        3 | 4 => 1,
+        6 | 8 => 2,
+        _ => return None,
+    };
+    Some(Color {
+        r: channel(0, width)?,
+        g: channel(1, width)?,
+        b: channel(2, width)?,
+        a: if hex.len() == 4 || hex.len() == 8 { channel(3, width)? } else { 255 },
+    })
+}
+
+/// `rgb_color` builds a color from the arguments of `rgb()` or `rgba()`.
+/// Channels are numbers in 0..=255 or percentages, and the optional alpha is a number in 0..=1 or a percentage.
+fn rgb_color(args: &[CSSValue]) -> Option<Color> {
+    let to_u8 = |v: f64| v.clamp(0.0, 255.0).round() as u8;
+    let channel = |value: &CSSValue| match *value {
+        CSSValue::Integer(v) => Some(to_u8(v as f64)),
+        CSSValue::Number(v) => Some(to_u8(v)),
+        CSSValue::Percentage(v) => Some(to_u8(v * 2.55)),
+        _ => None,
+    };
+    let alpha = |value: &CSSValue| match *value {
+        CSSValue::Integer(v) => Some(to_u8(v as f64 * 255.0)),
+        CSSValue::Number(v) => Some(to_u8(v * 255.0)),
+        CSSValue::Percentage(v) => Some(to_u8(v * 2.55)),
+        _ => None,
+    };
+    match args {
+        [r, g, b] => Some(Color::rgb(channel(r)?, channel(g)?, channel(b)?)),
+        [r, g, b, a] => Some(Color {
+            r: channel(r)?,
+            g: channel(g)?,
+            b: channel(b)?,
+            a: alpha(a)?,
+        }),
+        _ => None,
+    }
+}
+
+fn message_error<Input>(
+    message: &'static str,
+) -> <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    StreamError::message_static_message(message)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::dom::Element;
+    use crate::{dom::Arena, html};
 
     use super::*;
 
+    fn parse_rule(raw: &str) -> Rule {
+        let mut stylesheet = parse(raw);
+        assert_eq!(stylesheet.rules.len(), 1);
+        stylesheet.rules.remove(0)
+    }
+
     #[test]
     fn test_stylesheet() {
         assert_eq!(
-            rules().parse("test [foo=bar] { aa: bb; cc: dd } rule { ee: dd;  }"),
-            Ok((
-                vec![
-                    Rule {
-                        selectors: vec![SimpleSelector::AttributeSelector {
+            parse("test[foo=bar] { aa: bb; cc: dd } rule { ee: dd;  }").rules,
+            vec![
+                Rule {
+                    selectors: vec![vec![
+                        SimpleSelector::TypeSelector {
                             tag_name: "test".to_string(),
+                        },
+                        SimpleSelector::AttributeSelector {
                             attribute: "foo".to_string(),
                             op: AttributeSelectorOp::Eq,
+                            case_insensitive: false,
                             value: "bar".to_string()
-                        }],
-                        declarations: vec![
-                            Declaration {
-                                name: "aa".to_string(),
-                                value: CSSValue::Keyword("bb".to_string())
-                            },
-                            Declaration {
-                                name: "cc".to_string(),
-                                value: CSSValue::Keyword("dd".to_string()),
-                            }
-                        ]
-                    },
-                    Rule {
-                        selectors: vec![SimpleSelector::TypeSelector {
-                            tag_name: "rule".to_string(),
-                        }],
-                        declarations: vec![Declaration {
-                            name: "ee".to_string(),
-                            value: CSSValue::Keyword("dd".to_string())
-                        }]
-                    },
-                ],
-                ""
-            ))
+                        },
+                    ].into()],
+                    declarations: vec![
+                        Declaration {
+                            name: "aa".to_string(),
+                            value: CSSValue::Keyword("bb".to_string()),
+                            important: false,
+                            position: None,
+                        },
+                        Declaration {
+                            name: "cc".to_string(),
+                            value: CSSValue::Keyword("dd".to_string()),
+                            important: false,
+                            position: None,
+                        }
+                    ],
+                    position: None,
+                    source: None,
+                },
+                Rule {
+                    selectors: vec![SimpleSelector::TypeSelector {
+                        tag_name: "rule".to_string(),
+                    }.into()],
+                    declarations: vec![Declaration {
+                        name: "ee".to_string(),
+                        value: CSSValue::Keyword("dd".to_string()),
+                        important: false,
+                        position: None,
+                    }],
+                    position: None,
+                    source: None,
+                },
+            ]
         );
     }
 
     #[test]
     fn test_rule() {
         assert_eq!(
-            rule().parse("test [foo=bar] {}"),
-            Ok((
-                Rule {
-                    selectors: vec![SimpleSelector::AttributeSelector {
+            parse_rule("test[foo=bar] {}"),
+            Rule {
+                selectors: vec![vec![
+                    SimpleSelector::TypeSelector {
+                        tag_name: "test".to_string(),
+                    },
+                    SimpleSelector::AttributeSelector {
+                        attribute: "foo".to_string(),
+                        op: AttributeSelectorOp::Eq,
+                        case_insensitive: false,
+                        value: "bar".to_string()
+                    },
+                ].into()],
+                declarations: vec![],
+                position: None,
+                source: None,
+            }
+        );
+
+        assert_eq!(
+            parse_rule("test[foo=bar], testtest[piyo~=guoo] {}"),
+            Rule {
+                selectors: vec![
+                    vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "test".to_string(),
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "foo".to_string(),
+                            op: AttributeSelectorOp::Eq,
+                            case_insensitive: false,
+                            value: "bar".to_string()
+                        },
+                    ].into(),
+                    vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "testtest".to_string(),
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "piyo".to_string(),
+                            op: AttributeSelectorOp::Contain,
+                            case_insensitive: false,
+                            value: "guoo".to_string()
+                        },
+                    ].into()
+                ],
+                declarations: vec![],
+                position: None,
+                source: None,
+            }
+        );
+
+        assert_eq!(
+            parse_rule("test[foo=bar] { aa: bb; cc: dd; }"),
+            Rule {
+                selectors: vec![vec![
+                    SimpleSelector::TypeSelector {
                         tag_name: "test".to_string(),
+                    },
+                    SimpleSelector::AttributeSelector {
                         attribute: "foo".to_string(),
                         op: AttributeSelectorOp::Eq,
+                        case_insensitive: false,
                         value: "bar".to_string()
+                    },
+                ].into()],
+                declarations: vec![
+                    Declaration {
+                        name: "aa".to_string(),
+                        value: CSSValue::Keyword("bb".to_string()),
+                        important: false,
+                        position: None,
+                    },
+                    Declaration {
+                        name: "cc".to_string(),
+                        value: CSSValue::Keyword("dd".to_string()),
+                        important: false,
+                        position: None,
+                    }
+                ],
+                position: None,
+                source: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_declarations() {
+        assert_eq!(
+            parse_rule("p { foo: bar; piyo: piyopiyo; }").declarations,
+            vec![
+                Declaration {
+                    name: "foo".to_string(),
+                    value: CSSValue::Keyword("bar".to_string()),
+                    important: false,
+                    position: None,
+                },
+                Declaration {
+                    name: "piyo".to_string(),
+                    value: CSSValue::Keyword("piyopiyo".to_string()),
+                    important: false,
+                    position: None,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let (stylesheet, diagnostics) = parse_with_diagnostics(
+            "p { color: red; width: @@; font-size: 12px }\n\
+             p:: { color: blue }\n\
+             div { display: block; \"; }\" }\n\
+             a { color: rgb(1, 2; 3); text-align: center }",
+        );
+        assert_eq!(
+            stylesheet.rules.iter().map(|rule| rule.declarations.iter().map(|d| d.name.as_str()).collect::<Vec<_>>()).collect::<Vec<_>>(),
+            vec![vec!["color", "font-size"], vec!["display"], vec!["text-align"]]
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    line: 1,
+                    column: 17,
+                    message: "invalid declaration `width: @@`; the declaration is ignored".to_string(),
+                },
+                Diagnostic {
+                    line: 2,
+                    column: 1,
+                    message: "invalid selector `p::`; the rule is ignored".to_string(),
+                },
+                Diagnostic {
+                    line: 3,
+                    column: 23,
+                    message: "invalid declaration `\"; }\"`; the declaration is ignored".to_string(),
+                },
+                Diagnostic {
+                    line: 4,
+                    column: 5,
+                    message: "invalid declaration `color: rgb(1, 2; 3)`; the declaration is ignored".to_string(),
+                },
+            ]
+        );
+        assert_eq!(diagnostics[0].to_string(), "1:17: invalid declaration `width: @@`; the declaration is ignored");
+    }
+
+    #[test]
+    fn test_comments() {
+        let (stylesheet, diagnostics) = parse_with_diagnostics(
+            "/* header */ p /* a */ > a { /* b */ color: red /* c */; font-size/**/: 12px }\n/* unclosed",
+        );
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(stylesheet.rules[0].selectors[0].compound, vec![SimpleSelector::TypeSelector { tag_name: "a".to_string() }]);
+        assert_eq!(
+            stylesheet.rules[0].declarations,
+            vec![
+                Declaration {
+                    name: "color".to_string(),
+                    value: CSSValue::Keyword("red".to_string()),
+                    important: false,
+                    position: None,
+                },
+                Declaration {
+                    name: "font-size".to_string(),
+                    value: CSSValue::Length(12.0, Unit::Px),
+                    important: false,
+                    position: None,
+                },
+            ]
+        );
+
+        // a comment separates selectors as whitespace does
+        let stylesheet = parse("div/**/p { margin: 1ch/**/2ch }");
+        let selector = &stylesheet.rules[0].selectors[0];
+        assert_eq!(selector.compound, vec![SimpleSelector::TypeSelector { tag_name: "p".to_string() }]);
+        assert_eq!(matches!(selector.context, Some((Combinator::Descendant, _))), true);
+        assert_eq!(stylesheet.rules[0].declarations[0].value.to_string(), "1ch 2ch");
+    }
+
+    #[test]
+    fn test_at_rules() {
+        let (stylesheet, diagnostics) = parse_with_diagnostics(
+            r#"@import url("a.css") screen;
+            @charset "utf-8";
+            @media screen and (min-width: 80px) { p { color: red; } .x { a: b } }
+            p { color: blue; }
+            @font-face { font-family: "Foo"; src: url(foo.woff); }
+            @supports (display: grid) { div { display: grid } }
+            @-webkit-keyframes spin { from { a: b } to { a: c } }
+            div { color: green; }
+            @unknown-rule"#,
+        );
+        assert_eq!(
+            stylesheet.at_rules.iter().map(|rule| (rule.name.as_str(), rule.prelude.as_str())).collect::<Vec<_>>(),
+            vec![
+                ("import", r#"url("a.css") screen"#),
+                ("charset", r#""utf-8""#),
+                ("font-face", ""),
+                ("supports", "(display: grid)"),
+                ("-webkit-keyframes", "spin"),
+                ("unknown-rule", ""),
+            ]
+        );
+        assert_eq!(stylesheet.at_rules[0].block, None);
+        assert_eq!(
+            stylesheet.at_rules[2].block,
+            Some(r#" font-family: "Foo"; src: url(foo.woff); "#.to_string())
+        );
+
+        // the rules around at-rules are kept
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(stylesheet.rules[0].declarations[0].value, CSSValue::Keyword("blue".to_string()));
+        assert_eq!(stylesheet.rules[1].declarations[0].value, CSSValue::Keyword("green".to_string()));
+        assert_eq!(stylesheet.media_rules.len(), 1);
+        assert_eq!(stylesheet.media_rules[0].rules.len(), 2);
+        assert_eq!(stylesheet.media_rules[0].position, 0);
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.column)).collect::<Vec<_>>(), vec![(9, 13)]);
+
+        // `@media` without a block is reported as well as the one with an invalid query
+        let (stylesheet, diagnostics) = parse_with_diagnostics("@media x;\np { color: red; }");
+        assert_eq!(stylesheet.media_rules.len(), 0);
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                line: 1,
+                column: 1,
+                message: "`@media` without a block; the rule is ignored".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_media_queries() {
+        let media = |raw: &str| (media_query_list(), eof()).parse(raw).map(|((media, _), _)| media).ok();
+        assert_eq!(
+            media("not screen and (max-width: 80ch), (orientation: portrait)"),
+            Some(vec![
+                MediaQuery {
+                    negated: true,
+                    media_type: "screen".to_string(),
+                    features: vec![MediaFeature {
+                        name: "max-width".to_string(),
+                        value: Some(CSSValue::Length(80.0, Unit::Ch)),
                     }],
-                    declarations: vec![]
                 },
-                ""
-            ))
+                MediaQuery {
+                    negated: false,
+                    media_type: "all".to_string(),
+                    features: vec![MediaFeature {
+                        name: "orientation".to_string(),
+                        value: Some(CSSValue::Keyword("portrait".to_string())),
+                    }],
+                },
+            ])
+        );
+        assert_eq!(media("screen and"), None);
+        assert_eq!(media("(max-width 80ch)"), None);
+
+        let narrow = Viewport { width: 40, height: 24 };
+        let wide = Viewport { width: 120, height: 24 };
+        let matches = |raw: &str, viewport: &Viewport| media(raw).unwrap().iter().any(|q| q.matches(viewport));
+        assert_eq!(matches("(max-width: 80ch)", &narrow), true);
+        assert_eq!(matches("(max-width: 80ch)", &wide), false);
+        assert_eq!(matches("screen and (min-width: 640px)", &wide), true);
+        assert_eq!(matches("only screen and (min-width: 40em)", &narrow), false);
+        assert_eq!(matches("not print", &narrow), true);
+        assert_eq!(matches("print", &narrow), false);
+        assert_eq!(matches("(orientation: landscape)", &narrow), false);
+        assert_eq!(matches("(hover: hover)", &wide), false);
+        assert_eq!(matches("print, (min-width: 100ch)", &wide), true);
+
+        // the `media` attribute may have surrounding spaces, and may be empty
+        assert_eq!(parse_media(" print "), media("print"));
+        assert_eq!(parse_media(""), Some(vec![]));
+        assert_eq!(parse_media("screen and"), None);
+    }
+
+    #[test]
+    fn test_resolve_media() {
+        let (stylesheet, diagnostics) = parse_with_diagnostics(
+            r#"p { color: black; }
+            @media (max-width: 80ch) { p { color: red; } }
+            div { color: black; }
+            @media screen and (min-width: 81ch) { div { color: blue; } }
+            @media (max-width) and { p { color: green; } }"#,
+        );
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.column)).collect::<Vec<_>>(), vec![(5, 13)]);
+        assert_eq!(stylesheet.media_rules.iter().map(|m| m.position).collect::<Vec<_>>(), vec![1, 2]);
+
+        let colors = |viewport: Viewport| {
+            stylesheet
+                .resolve_media(&viewport)
+                .rules
+                .iter()
+                .map(|rule| rule.declarations[0].value.clone())
+                .collect::<Vec<_>>()
+        };
+        let keyword = |k: &str| CSSValue::Keyword(k.to_string());
+        assert_eq!(
+            colors(Viewport { width: 40, height: 24 }),
+            vec![keyword("black"), keyword("red"), keyword("black")]
+        );
+        assert_eq!(
+            colors(Viewport { width: 100, height: 24 }),
+            vec![keyword("black"), keyword("black"), keyword("blue")]
+        );
+    }
+
+    #[test]
+    fn test_positions() {
+        let stylesheet = parse("/* 日本 */ p,\r\ndiv { color: red;\n  margin : 0 }\n@media print { a { color: blue } }");
+        let position = |p: Option<SourcePosition>| p.map(|p| (p.offset, p.line, p.column));
+
+        let rule = &stylesheet.rules[0];
+        assert_eq!(position(rule.position), Some((13, 1, 10)));
+        assert_eq!(position(rule.declarations[0].position), Some((23, 2, 7)));
+        assert_eq!(position(rule.declarations[1].position), Some((37, 3, 3)));
+        assert_eq!(position(stylesheet.media_rules[0].rules[0].position), Some((65, 4, 16)));
+
+        // positions are ignored when comparing rules
+        assert_eq!(parse("div{color:red}").rules, parse("\n\n  div { color: red }").rules);
+    }
+
+    #[test]
+    fn test_identifiers() {
+        let stylesheet = parse("my-element#main-nav._private.-x { -webkit-box-flex: 1; --main-color: navy; display: -webkit-box }");
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(
+            stylesheet.rules[0].selectors[0].compound,
+            vec![
+                SimpleSelector::TypeSelector { tag_name: "my-element".to_string() },
+                SimpleSelector::IdSelector { id: "main-nav".to_string() },
+                SimpleSelector::ClassSelector { class_name: "_private".to_string() },
+                SimpleSelector::ClassSelector { class_name: "-x".to_string() },
+            ]
+        );
+        assert_eq!(
+            stylesheet.rules[0].declarations.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(),
+            vec!["-webkit-box-flex", "--main-color", "display"]
         );
+        assert_eq!(stylesheet.rules[0].declarations[2].value, CSSValue::Keyword("-webkit-box".to_string()));
+
+        // identifiers cannot start with a digit or a hyphen followed by a digit
+        assert!(identifier().parse("1a").is_err());
+        assert!(identifier().parse("-1").is_err());
+        assert_eq!(identifier().parse("日本語"), Ok(("日本語".to_string(), "")));
+    }
+
+    #[test]
+    fn test_error_recovery_at_end_of_input() {
+        // an unclosed block is closed at the end of input
+        let (stylesheet, diagnostics) = parse_with_diagnostics("p { color: red; }\ndiv { color: blue");
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(stylesheet.rules[1].declarations.len(), 1);
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.column)).collect::<Vec<_>>(), vec![(2, 5)]);
+
+        // a prelude without a block is dropped
+        let (stylesheet, diagnostics) = parse_with_diagnostics("p { color: red; } div");
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.column)).collect::<Vec<_>>(), vec![(1, 19)]);
+
+        assert_eq!(parse_with_diagnostics(""), (Stylesheet::new(vec![]), vec![]));
+        assert_eq!(parse_with_diagnostics(" \n "), (Stylesheet::new(vec![]), vec![]));
+    }
 
+    #[test]
+    fn test_selectors() {
         assert_eq!(
-            rule().parse("test [foo=bar], testtest[piyo~=guoo] {}"),
+            selectors().parse("test[foo=bar], a"),
             Ok((
-                Rule {
-                    selectors: vec![
-                        SimpleSelector::AttributeSelector {
+                vec![
+                    vec![
+                        SimpleSelector::TypeSelector {
                             tag_name: "test".to_string(),
+                        },
+                        SimpleSelector::AttributeSelector {
                             attribute: "foo".to_string(),
                             op: AttributeSelectorOp::Eq,
+                            case_insensitive: false,
                             value: "bar".to_string()
                         },
-                        SimpleSelector::AttributeSelector {
-                            tag_name: "testtest".to_string(),
-                            attribute: "piyo".to_string(),
-                            op: AttributeSelectorOp::Contain,
-                            value: "guoo".to_string()
-                        }
-                    ],
-                    declarations: vec![]
-                },
-                ""
-            ))
-        );
-
-        assert_eq!(
-            rule().parse("test [foo=bar] { aa: bb; cc: dd; }"),
-            Ok((
-                Rule {
-                    selectors: vec![SimpleSelector::AttributeSelector {
-                        tag_name: "test".to_string(),
-                        attribute: "foo".to_string(),
-                        op: AttributeSelectorOp::Eq,
-                        value: "bar".to_string()
-                    }],
-                    declarations: vec![
-                        Declaration {
-                            name: "aa".to_string(),
-                            value: CSSValue::Keyword("bb".to_string())
-                        },
-                        Declaration {
-                            name: "cc".to_string(),
-                            value: CSSValue::Keyword("dd".to_string()),
-                        }
                     ]
-                },
+                    .into(),
+                    SimpleSelector::TypeSelector {
+                        tag_name: "a".to_string(),
+                    }
+                    .into()
+                ],
                 ""
             ))
         );
-    }
 
-    #[test]
-    fn test_declarations() {
         assert_eq!(
-            declarations().parse("foo: bar; piyo: piyopiyo;"),
+            selectors().parse(".sidebar a, ul>li"),
             Ok((
                 vec![
-                    Declaration {
-                        name: "foo".to_string(),
-                        value: CSSValue::Keyword("bar".to_string())
+                    Selector {
+                        compound: vec![SimpleSelector::TypeSelector {
+                            tag_name: "a".to_string(),
+                        }],
+                        context: Some((
+                            Combinator::Descendant,
+                            Box::new(
+                                SimpleSelector::ClassSelector {
+                                    class_name: "sidebar".to_string(),
+                                }
+                                .into()
+                            )
+                        )),
                     },
-                    Declaration {
-                        name: "piyo".to_string(),
-                        value: CSSValue::Keyword("piyopiyo".to_string())
+                    Selector {
+                        compound: vec![SimpleSelector::TypeSelector {
+                            tag_name: "li".to_string(),
+                        }],
+                        context: Some((
+                            Combinator::Child,
+                            Box::new(
+                                SimpleSelector::TypeSelector {
+                                    tag_name: "ul".to_string(),
+                                }
+                                .into()
+                            )
+                        )),
                     }
                 ],
                 ""
@@ -404,24 +1940,36 @@ mod tests {
     }
 
     #[test]
-    fn test_selectors() {
+    fn test_combinators() {
+        let tag = |tag_name: &str| {
+            vec![SimpleSelector::TypeSelector {
+                tag_name: tag_name.to_string(),
+            }]
+        };
+
+        // combinators are applied from left to right
         assert_eq!(
-            selectors().parse("test [foo=bar], a"),
+            selector().parse("h1 + p ~ div"),
             Ok((
-                vec![
-                    SimpleSelector::AttributeSelector {
-                        tag_name: "test".to_string(),
-                        attribute: "foo".to_string(),
-                        op: AttributeSelectorOp::Eq,
-                        value: "bar".to_string()
-                    },
-                    SimpleSelector::TypeSelector {
-                        tag_name: "a".to_string(),
-                    }
-                ],
+                Selector {
+                    compound: tag("div"),
+                    context: Some((
+                        Combinator::SubsequentSibling,
+                        Box::new(Selector {
+                            compound: tag("p"),
+                            context: Some((Combinator::NextSibling, Box::new(tag("h1").into()))),
+                        })
+                    )),
+                },
                 ""
             ))
         );
+
+        // whitespace around an explicit combinator is not a descendant combinator
+        assert_eq!(selector().parse("ul  >\n li"), selector().parse("ul>li"));
+
+        // trailing whitespace is left for the rule parser
+        assert_eq!(selector().parse("div {").map(|(_, rest)| rest), Ok(" {"));
     }
 
     #[test]
@@ -442,12 +1990,12 @@ mod tests {
         );
 
         assert_eq!(
-            simple_selector().parse("test [foo=bar]"),
+            simple_selector().parse("[foo=bar]"),
             Ok((
                 SimpleSelector::AttributeSelector {
-                    tag_name: "test".to_string(),
                     attribute: "foo".to_string(),
                     op: AttributeSelectorOp::Eq,
+                    case_insensitive: false,
                     value: "bar".to_string()
                 },
                 ""
@@ -463,6 +2011,133 @@ mod tests {
                 ""
             ))
         );
+
+        assert_eq!(
+            simple_selector().parse("#main"),
+            Ok((
+                SimpleSelector::IdSelector {
+                    id: "main".to_string(),
+                },
+                ""
+            ))
+        );
+    }
+
+    #[test]
+    fn test_attribute_selector() {
+        let attr = |op, value: &str, case_insensitive| SimpleSelector::AttributeSelector {
+            attribute: "data-role".to_string(),
+            op,
+            value: value.to_string(),
+            case_insensitive,
+        };
+
+        assert_eq!(
+            simple_selector().parse("[data-role]"),
+            Ok((attr(AttributeSelectorOp::Exists, "", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[ data-role ]"),
+            Ok((attr(AttributeSelectorOp::Exists, "", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[data-role~=tab]"),
+            Ok((attr(AttributeSelectorOp::Contain, "tab", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[data-role|=en]"),
+            Ok((attr(AttributeSelectorOp::DashMatch, "en", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[data-role^=tab]"),
+            Ok((attr(AttributeSelectorOp::Prefix, "tab", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[data-role$=tab]"),
+            Ok((attr(AttributeSelectorOp::Suffix, "tab", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse("[data-role*=tab]"),
+            Ok((attr(AttributeSelectorOp::Substring, "tab", false), ""))
+        );
+
+        // quoted values
+        assert_eq!(
+            simple_selector().parse(r#"[data-role="tab list"]"#),
+            Ok((attr(AttributeSelectorOp::Eq, "tab list", false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse(r#"[data-role='a "b"']"#),
+            Ok((attr(AttributeSelectorOp::Eq, r#"a "b""#, false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse(r#"[data-role="a\"b"]"#),
+            Ok((attr(AttributeSelectorOp::Eq, r#"a"b"#, false), ""))
+        );
+        assert_eq!(
+            simple_selector().parse(r#"[data-role=""]"#),
+            Ok((attr(AttributeSelectorOp::Eq, "", false), ""))
+        );
+
+        // case-sensitivity flags
+        assert_eq!(
+            simple_selector().parse("[data-role=Tab i]"),
+            Ok((attr(AttributeSelectorOp::Eq, "Tab", true), ""))
+        );
+        assert_eq!(
+            simple_selector().parse(r#"[data-role="Tab" s]"#),
+            Ok((attr(AttributeSelectorOp::Eq, "Tab", false), ""))
+        );
+
+        assert!(simple_selector().parse("[data-role=\"tab]").is_err());
+        assert!(simple_selector().parse("[data-role==tab]").is_err());
+        assert!(simple_selector().parse("[data-role=tab x]").is_err());
+    }
+
+    #[test]
+    fn test_compound_selector() {
+        assert_eq!(
+            compound_selector().parse("div#x.y"),
+            Ok((
+                vec![
+                    SimpleSelector::TypeSelector {
+                        tag_name: "div".to_string(),
+                    },
+                    SimpleSelector::IdSelector {
+                        id: "x".to_string(),
+                    },
+                    SimpleSelector::ClassSelector {
+                        class_name: "y".to_string(),
+                    },
+                ],
+                ""
+            ))
+        );
+
+        assert_eq!(
+            compound_selector().parse("[href=x][target=blank] a"),
+            Ok((
+                vec![
+                    SimpleSelector::AttributeSelector {
+                        attribute: "href".to_string(),
+                        op: AttributeSelectorOp::Eq,
+                        case_insensitive: false,
+                        value: "x".to_string()
+                    },
+                    SimpleSelector::AttributeSelector {
+                        attribute: "target".to_string(),
+                        op: AttributeSelectorOp::Eq,
+                        case_insensitive: false,
+                        value: "blank".to_string()
+                    },
+                ],
+                " a"
+            ))
+        );
+
+        // a type selector must come first
+        assert!(compound_selector().parse(".note p").is_ok());
+        assert!(compound_selector().parse(".note*").is_err());
     }
 
     #[test]
@@ -473,6 +2148,8 @@ mod tests {
                 Declaration {
                     name: "keykey".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
+                    position: None,
                 },
                 ""
             ))
@@ -484,6 +2161,8 @@ mod tests {
                 Declaration {
                     name: "keyabc".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
+                    position: None,
                 },
                 " "
             ))
@@ -495,18 +2174,150 @@ mod tests {
                 Declaration {
                     name: "keyhello".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
+                    position: None,
                 },
                 " "
             ))
         );
 
+        assert_eq!(
+            declaration().parse("color: red !important"),
+            Ok((
+                Declaration {
+                    name: "color".to_string(),
+                    value: CSSValue::Keyword("red".to_string()),
+                    important: true,
+                    position: None,
+                },
+                ""
+            ))
+        );
+
+        assert_eq!(
+            declaration().parse("color:red! IMPORTANT ;"),
+            Ok((
+                Declaration {
+                    name: "color".to_string(),
+                    value: CSSValue::Keyword("red".to_string()),
+                    important: true,
+                    position: None,
+                },
+                " ;"
+            ))
+        );
+
+        assert_eq!(
+            declaration().parse("width: 10px"),
+            Ok((
+                Declaration {
+                    name: "width".to_string(),
+                    value: CSSValue::Length(10.0, Unit::Px),
+                    important: false,
+                    position: None,
+                },
+                ""
+            ))
+        );
+
         assert!(declaration().parse("aaaaa").is_err())
     }
 
+    #[test]
+    fn test_css_value() {
+        let parse = |raw: &str| css_value().parse(raw).map(|(value, _)| value);
+        let keyword = |s: &str| CSSValue::Keyword(s.to_string());
+
+        assert_eq!(parse("block"), Ok(keyword("block")));
+        assert_eq!(parse("sans-serif"), Ok(keyword("sans-serif")));
+        assert_eq!(parse("-webkit-box"), Ok(keyword("-webkit-box")));
+
+        // numbers
+        assert_eq!(parse("10px"), Ok(CSSValue::Length(10.0, Unit::Px)));
+        assert_eq!(parse("1.5em"), Ok(CSSValue::Length(1.5, Unit::Em)));
+        assert_eq!(parse("-.5REM"), Ok(CSSValue::Length(-0.5, Unit::Rem)));
+        assert_eq!(parse("1e1vw"), Ok(CSSValue::Length(10.0, Unit::Vw)));
+        assert_eq!(parse("50%"), Ok(CSSValue::Percentage(50.0)));
+        assert_eq!(parse("42"), Ok(CSSValue::Integer(42)));
+        assert_eq!(parse("-3"), Ok(CSSValue::Integer(-3)));
+        assert_eq!(parse("0.25"), Ok(CSSValue::Number(0.25)));
+        assert_eq!(parse("2e-1"), Ok(CSSValue::Number(0.2)));
+        assert!(parse("10parsecs").is_err());
+
+        // colors
+        assert_eq!(parse("#ff0000"), Ok(CSSValue::Color(Color::rgb(255, 0, 0))));
+        assert_eq!(parse("#0F0"), Ok(CSSValue::Color(Color::rgb(0, 255, 0))));
+        assert_eq!(parse("#00f8"), Ok(CSSValue::Color(Color { r: 0, g: 0, b: 255, a: 0x88 })));
+        assert_eq!(parse("#11223344"), Ok(CSSValue::Color(Color { r: 0x11, g: 0x22, b: 0x33, a: 0x44 })));
+        assert!(parse("#12345").is_err());
+        assert_eq!(parse("rgb(1, 2, 3)"), Ok(CSSValue::Color(Color::rgb(1, 2, 3))));
+        assert_eq!(parse("rgb(100%,0%,0%)"), Ok(CSSValue::Color(Color::rgb(255, 0, 0))));
+        assert_eq!(parse("RGBA(1, 2, 3, 0.5)"), Ok(CSSValue::Color(Color { r: 1, g: 2, b: 3, a: 128 })));
+        assert!(parse("rgb(1, 2)").is_err());
+
+        // strings and urls
+        assert_eq!(parse(r#""x""#), Ok(CSSValue::String("x".to_string())));
+        assert_eq!(parse("'a b'"), Ok(CSSValue::String("a b".to_string())));
+        assert_eq!(parse("url(a.png)"), Ok(CSSValue::Url("a.png".to_string())));
+        assert_eq!(parse(r#"url( "a b.png" )"#), Ok(CSSValue::Url("a b.png".to_string())));
+
+        // lists
+        assert_eq!(
+            parse("1px 2px auto"),
+            Ok(CSSValue::SpaceList(vec![
+                CSSValue::Length(1.0, Unit::Px),
+                CSSValue::Length(2.0, Unit::Px),
+                keyword("auto"),
+            ]))
+        );
+        assert_eq!(
+            parse(r#""Foo Bar" , serif"#),
+            Ok(CSSValue::CommaList(vec![
+                CSSValue::String("Foo Bar".to_string()),
+                keyword("serif"),
+            ]))
+        );
+        assert_eq!(
+            parse("1px solid red, 2px dotted"),
+            Ok(CSSValue::CommaList(vec![
+                CSSValue::SpaceList(vec![CSSValue::Length(1.0, Unit::Px), keyword("solid"), keyword("red")]),
+                CSSValue::SpaceList(vec![CSSValue::Length(2.0, Unit::Px), keyword("dotted")]),
+            ]))
+        );
+
+        // `!important` and trailing whitespaces are not a part of the value
+        assert_eq!(css_value().parse("1px 2px !important"), Ok((
+            CSSValue::SpaceList(vec![CSSValue::Length(1.0, Unit::Px), CSSValue::Length(2.0, Unit::Px)]),
+            " !important"
+        )));
+    }
+
+    #[test]
+    fn test_to_color() {
+        assert_eq!(CSSValue::Keyword("Red".to_string()).to_color(), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(CSSValue::Color(Color::rgb(1, 2, 3)).to_color(), Some(Color::rgb(1, 2, 3)));
+        assert_eq!(CSSValue::Keyword("bogus".to_string()).to_color(), None);
+        assert_eq!(CSSValue::Integer(0).to_color(), None);
+    }
+
+    #[test]
+    fn test_specificity() {
+        let specificity = |selector_str: &str| selector().parse(selector_str).unwrap().0.specificity();
+
+        assert_eq!(specificity("*"), (0, 0, 0));
+        assert_eq!(specificity("li"), (0, 0, 1));
+        assert_eq!(specificity("ul li"), (0, 0, 2));
+        assert_eq!(specificity("ul > li.item"), (0, 1, 2));
+        assert_eq!(specificity("[data-role] + *"), (0, 1, 0));
+        assert_eq!(specificity("#main .note p"), (1, 1, 1));
+        assert_eq!(specificity("div#a.b.c[x]"), (1, 3, 1));
+    }
+
     #[test]
     fn test_universal_selector_behaviour() {
-        let e = &Element::new(
-            "p".to_string(),
+        let mut arena = Arena::new();
+        let id = arena.create_element(
+            "p",
             [
                 ("id".to_string(), "test".to_string()),
                 ("class".to_string(), "testclass".to_string()),
@@ -514,15 +2325,16 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
-            vec![],
         );
+        let e = &arena[id];
         assert_eq!(SimpleSelector::UniversalSelector.matches(e), true);
     }
 
     #[test]
     fn test_type_selector_behaviour() {
-        let e = &Element::new(
-            "p".to_string(),
+        let mut arena = Arena::new();
+        let id = arena.create_element(
+            "p",
             [
                 ("id".to_string(), "test".to_string()),
                 ("class".to_string(), "testclass".to_string()),
@@ -530,8 +2342,8 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
-            vec![],
         );
+        let e = &arena[id];
 
         assert_eq!(
             (SimpleSelector::TypeSelector {
@@ -552,8 +2364,9 @@ mod tests {
 
     #[test]
     fn test_attribute_selector_behaviour() {
-        let e = &Element::new(
-            "p".to_string(),
+        let mut arena = Arena::new();
+        let id = arena.create_element(
+            "p",
             [
                 ("id".to_string(), "test".to_string()),
                 ("class".to_string(), "testclass".to_string()),
@@ -561,15 +2374,15 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
-            vec![],
         );
+        let e = &arena[id];
 
         assert_eq!(
             (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
                 attribute: "id".into(),
                 value: "test".into(),
                 op: AttributeSelectorOp::Eq,
+                case_insensitive: false,
             })
             .matches(e),
             true
@@ -577,10 +2390,10 @@ mod tests {
 
         assert_eq!(
             (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
                 attribute: "id".into(),
                 value: "invalid".into(),
                 op: AttributeSelectorOp::Eq,
+                case_insensitive: false,
             })
             .matches(e),
             false
@@ -588,31 +2401,99 @@ mod tests {
 
         assert_eq!(
             (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
                 attribute: "invalid".into(),
                 value: "test".into(),
                 op: AttributeSelectorOp::Eq,
+                case_insensitive: false,
             })
             .matches(e),
             false
         );
 
-        assert_eq!(
-            (SimpleSelector::AttributeSelector {
-                tag_name: "invalid".into(),
-                attribute: "id".into(),
-                value: "test".into(),
-                op: AttributeSelectorOp::Eq,
-            })
-            .matches(e),
-            false
+    }
+
+    #[test]
+    fn test_attribute_selector_operator_behaviour() {
+        let arena = html::parse(
+            r#"<div id="panel" data-role="tab-panel" lang="en-US" data-state="Open"></div>"#,
+        );
+        let matches = |selector_str: &str| {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            selector.matches(&arena, arena.get_element_by_id("panel").unwrap())
+        };
+
+        assert_eq!(matches("[data-role]"), true);
+        assert_eq!(matches("[data-missing]"), false);
+        assert_eq!(matches("div[data-role]"), true);
+        assert_eq!(matches("p[data-role]"), false);
+
+        assert_eq!(matches("[lang|=en]"), true);
+        assert_eq!(matches("[lang|=en-US]"), true);
+        assert_eq!(matches("[lang|=e]"), false);
+
+        assert_eq!(matches("[data-role^=tab]"), true);
+        assert_eq!(matches("[data-role^=panel]"), false);
+        assert_eq!(matches(r#"[data-role^=""]"#), false);
+
+        assert_eq!(matches("[data-role$=panel]"), true);
+        assert_eq!(matches("[data-role$=tab]"), false);
+        assert_eq!(matches(r#"[data-role$=""]"#), false);
+
+        assert_eq!(matches("[data-role*=b-p]"), true);
+        assert_eq!(matches("[data-role*=list]"), false);
+        assert_eq!(matches(r#"[data-role*=""]"#), false);
+
+        assert_eq!(matches(r#"[data-role="tab-panel"]"#), true);
+        assert_eq!(matches("[data-state=open]"), false);
+        assert_eq!(matches("[data-state=open i]"), true);
+        assert_eq!(matches("[data-state=open s]"), false);
+        assert_eq!(matches("[data-state^=OP i]"), true);
+        assert_eq!(matches("[data-missing=open i]"), false);
+    }
+
+    #[test]
+    fn test_id_selector_behaviour() {
+        let mut arena = Arena::new();
+        let id = arena.create_element(
+            "p",
+            [
+                ("id".to_string(), "test".to_string()),
+                ("class".to_string(), "testclass".to_string()),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
         );
+        let e = &arena[id];
+
+        assert_eq!((SimpleSelector::IdSelector { id: "test".into() }).matches(e), true);
+        assert_eq!((SimpleSelector::IdSelector { id: "testclass".into() }).matches(e), false);
+    }
+
+    #[test]
+    fn test_compound_selector_behaviour() {
+        let arena = html::parse(r#"<p id="a" class="note">a</p><div id="x" class="y"><a id="link" href="x" target="blank">b</a></div>"#);
+        let matches = |selector_str: &str, id: &str| {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            selector.matches(&arena, arena.get_element_by_id(id).unwrap())
+        };
+
+        assert_eq!(matches("p.note", "a"), true);
+        assert_eq!(matches("div.note", "a"), false);
+        assert_eq!(matches("p#a.note", "a"), true);
+        assert_eq!(matches("p#b.note", "a"), false);
+        assert_eq!(matches("div#x.y a", "link"), true);
+        assert_eq!(matches("div#x.z a", "link"), false);
+        assert_eq!(matches("a[href=x][target=blank]", "link"), true);
+        assert_eq!(matches("a[href=x][target=self]", "link"), false);
+        assert_eq!(matches("#x > #link", "link"), true);
     }
 
     #[test]
     fn test_class_selector_behaviour() {
-        let e = &Element::new(
-            "p".to_string(),
+        let mut arena = Arena::new();
+        let id = arena.create_element(
+            "p",
             [
                 ("id".to_string(), "test".to_string()),
                 ("class".to_string(), "testclass".to_string()),
@@ -620,8 +2501,8 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
-            vec![],
         );
+        let e = &arena[id];
 
         assert_eq!(
             (SimpleSelector::ClassSelector {
@@ -639,4 +2520,122 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn test_multiple_class_selector_behaviour() {
+        let arena = html::parse(
+            r#"<p id="a" class="inline big">a</p><p id="b" class="  btn-primary	big ">b</p><p id="c">c</p>"#,
+        );
+        let matches = |selector_str: &str, id: &str| {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            selector.matches(&arena, arena.get_element_by_id(id).unwrap())
+        };
+
+        assert_eq!(matches(".inline", "a"), true);
+        assert_eq!(matches(".big", "a"), true);
+        assert_eq!(matches(".inline.big", "a"), true);
+        assert_eq!(matches("p.big.inline", "a"), true);
+        assert_eq!(matches(".inline.small", "a"), false);
+        assert_eq!(matches(".inl", "a"), false);
+        assert_eq!(matches(".btn-primary.big", "b"), true);
+        assert_eq!(matches(".big", "c"), false);
+    }
+
+    #[test]
+    fn test_contain_attribute_selector_behaviour() {
+        let arena = html::parse(r#"<p id="a" class="inline big" data-tags="x y">a</p><p id="b">b</p>"#);
+        let matches = |selector_str: &str, id: &str| {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            selector.matches(&arena, arena.get_element_by_id(id).unwrap())
+        };
+
+        // `~=` used to match any element which merely had the attribute
+        assert_eq!(matches("[class~=inline]", "a"), true);
+        assert_eq!(matches("[class~=big]", "a"), true);
+        assert_eq!(matches("[class~=small]", "a"), false);
+        assert_eq!(matches("[class~=inl]", "a"), false);
+        assert_eq!(matches(r#"[class~="inline big"]"#, "a"), false);
+        assert_eq!(matches(r#"[class~=""]"#, "a"), false);
+        assert_eq!(matches("[class~=inline]", "b"), false);
+        assert_eq!(matches("[data-tags~=y]", "a"), true);
+        assert_eq!(matches("[data-tags~=Y i]", "a"), true);
+        assert_eq!(matches("[data-tags~=Y]", "a"), false);
+    }
+
+    #[test]
+    fn test_combinator_behaviour() {
+        let arena = html::parse(
+            r#"<div class="sidebar"><p><a id="in">a</a></p></div><h1></h1>text<p id="next"></p><div id="later"></div><a id="out">b</a>"#,
+        );
+        let matches = |selector_str: &str, id: &str| {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            selector.matches(&arena, arena.get_element_by_id(id).unwrap())
+        };
+
+        assert_eq!(matches(".sidebar a", "in"), true);
+        assert_eq!(matches(".sidebar a", "out"), false);
+        assert_eq!(matches("p > a", "in"), true);
+        assert_eq!(matches("div > a", "in"), false);
+        assert_eq!(matches("h1 + p", "next"), true);
+        assert_eq!(matches("h1 + div", "later"), false);
+        assert_eq!(matches("h1 ~ div", "later"), true);
+        assert_eq!(matches("body > div ~ a", "out"), true);
+        assert_eq!(matches("div ~ h1 + p", "next"), true);
+        // the document node is not an element
+        assert_eq!(matches("* > html", "in"), false);
+
+        // tag names and attribute names are case-insensitive
+        assert_eq!(matches("DIV.sidebar A", "in"), true);
+        assert_eq!(matches("[ID=in]", "in"), true);
+        assert_eq!(matches("[id=IN]", "in"), false);
+
+        // texts are not matched even by the universal selector
+        let text = arena[arena.get_element_by_id("in").unwrap()].first_child().unwrap();
+        for selector_str in ["*", ".sidebar *", "a > *"] {
+            let (selector, _) = selector().parse(selector_str).unwrap();
+            assert_eq!(selector.matches(&arena, text), false);
+        }
+    }
+
+    #[test]
+    fn test_parse_declarations() {
+        let (declarations, diagnostics) = parse_declarations("color: red; ; font-weight:bold !important;; 1px; display: none");
+        assert_eq!(
+            declarations.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+            vec!["color: red", "font-weight: bold !important", "display: none"]
+        );
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.column)).collect::<Vec<_>>(), vec![(1, 45)]);
+
+        assert_eq!(
+            parse_declaration(" margin: 1px 2em "),
+            Some(Declaration {
+                name: "margin".to_string(),
+                value: CSSValue::SpaceList(vec![CSSValue::Length(1.0, Unit::Px), CSSValue::Length(2.0, Unit::Em)]),
+                important: false,
+                position: None,
+            })
+        );
+        assert_eq!(parse_declaration("color: red; display: none"), None);
+        assert_eq!(parse_declaration("color: @@"), None);
+    }
+
+    #[test]
+    fn test_css_value_to_string() {
+        for raw in [
+            "red",
+            "1.5em",
+            "50%",
+            "-3",
+            "0.5",
+            "rgb(1, 2, 3)",
+            "rgba(0, 0, 0, 0)",
+            r#""a \"quoted\" string""#,
+            r#"url("a.png")"#,
+            r#"1px 2px, "Foo Bar", serif"#,
+        ] {
+            let declaration = parse_declaration(&format!("x: {}", raw)).unwrap();
+            assert_eq!(declaration.value.to_string(), raw);
+            assert_eq!(parse_declaration(&declaration.to_string()), Some(declaration));
+        }
+    }
 }
@@ -0,0 +1,285 @@
+//! This module loads stylesheets from the local filesystem.
+//!
+//! Both `<link rel="stylesheet">` and `@import` refer to stylesheets by URL.
+//! Only paths and `file://` URLs are supported, which are resolved relative to the referring document or stylesheet.
+//! See https://www.w3.org/TR/css-cascade-4/#at-import
+
+use combine::{eof, Parser};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    component_value, media_query_list, parse_with_diagnostics, whitespaces, CSSValue, Diagnostic, MediaQueryList, MediaRule,
+    Stylesheet,
+};
+
+/// `LoadError` represents a stylesheet which could not be loaded.
+/// The stylesheet is skipped, and the others are still applied.
+#[derive(Debug)]
+pub enum LoadError {
+    // `http://`などローカルでないURL
+    Unsupported(String),
+    Io(PathBuf, io::Error),
+    // 読み込み中のスタイルシートを再び`@import`した
+    Cycle(PathBuf),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Unsupported(href) => write!(f, "unsupported URL `{}`", href),
+            LoadError::Io(path, e) => write!(f, "failed to read `{}`: {}", path.display(), e),
+            LoadError::Cycle(path) => write!(f, "`{}` imports itself", path.display()),
+        }
+    }
+}
+
+/// `resolve` returns the path `href` refers to.
+/// `base` is the path of the referring document or stylesheet; without it, `href` is relative to the working directory.
+pub fn resolve(base: Option<&Path>, href: &str) -> Result<PathBuf, LoadError> {
+    // クエリとフラグメントはファイルの指定には関係しない
+    let href = href.split(['?', '#']).next().unwrap_or_default();
+    let path = match href.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("file") => {
+            let path = rest.strip_prefix("localhost").unwrap_or(rest);
+            return Ok(PathBuf::from(path));
+        },
+        Some(_) => return Err(LoadError::Unsupported(href.to_string())),
+        None => Path::new(href),
+    };
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(match base.and_then(Path::parent) {
+        Some(dir) => dir.join(path),
+        None => path.to_path_buf(),
+    })
+}
+
+/// `Loader` loads stylesheets and the ones they import.
+/// Errors are collected in `errors` instead of failing the whole load,
+/// and the parse errors of the loaded stylesheets are collected in `diagnostics`.
+#[derive(Debug, Default)]
+pub struct Loader {
+    // 読み込み中のスタイルシート。循環した`@import`の検出に使う
+    loading: Vec<PathBuf>,
+    pub errors: Vec<LoadError>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader::default()
+    }
+
+    /// `load` reads the stylesheet `href` refers to.
+    pub fn load(&mut self, base: Option<&Path>, href: &str) -> Option<Stylesheet> {
+        match resolve(base, href) {
+            Ok(path) => self.load_path(path),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            },
+        }
+    }
+
+    /// `load_path` reads the stylesheet at the path as it is, without resolving it as a URL.
+    pub fn load_path(&mut self, path: PathBuf) -> Option<Stylesheet> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.loading.contains(&canonical) {
+            self.errors.push(LoadError::Cycle(path));
+            return None;
+        }
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.errors.push(LoadError::Io(path, e));
+                return None;
+            },
+        };
+
+        self.loading.push(canonical);
+        let mut stylesheet = self.load_str(&raw, Some(&path));
+        stylesheet.locate(Some(&path.display().to_string()), None);
+        self.loading.pop();
+        Some(stylesheet)
+    }
+
+    /// `load_str` parses the stylesheet and replaces its `@import` rules with the imported rules.
+    /// The imported rules come before the rules of the stylesheet, in the order of the `@import` rules.
+    ///
+    /// NOTE: `@media` rules in a stylesheet imported with media queries are ignored,
+    /// since nested conditions are not supported.
+    pub fn load_str(&mut self, raw: &str, base: Option<&Path>) -> Stylesheet {
+        let (mut stylesheet, diagnostics) = parse_with_diagnostics(raw);
+        self.diagnostics.extend(diagnostics);
+        let mut imported = Stylesheet::new(vec![]);
+        imported.origin = stylesheet.origin;
+
+        let (imports, at_rules) = stylesheet
+            .at_rules
+            .drain(..)
+            .partition::<Vec<_>, _>(|at_rule| at_rule.name.eq_ignore_ascii_case("import"));
+        stylesheet.at_rules = at_rules;
+
+        for import in imports {
+            // 不正な`@import`はルールごと無視する
+            let prelude = (
+                component_value().skip(whitespaces()),
+                media_query_list(),
+                eof(),
+            )
+                .parse(import.prelude.as_str());
+            let (href, media) = match prelude {
+                Ok(((CSSValue::Url(href), media, _), _)) | Ok(((CSSValue::String(href), media, _), _)) => (href, media),
+                _ => continue,
+            };
+            let sheet = match self.load(base, &href) {
+                Some(sheet) => sheet,
+                None => continue,
+            };
+            append(&mut imported, apply_media(sheet, media));
+        }
+
+        append(&mut imported, stylesheet);
+        imported
+    }
+}
+
+/// `apply_media` makes the rules of the stylesheet apply only when any of the media queries matches,
+/// as `@import "a.css" print` and `<link rel="stylesheet" media="print">` do. An empty list leaves the stylesheet as it is.
+///
+/// NOTE: `@media` rules in the stylesheet are ignored, since nested conditions are not supported.
+pub fn apply_media(stylesheet: Stylesheet, media: MediaQueryList) -> Stylesheet {
+    if media.is_empty() {
+        return stylesheet;
+    }
+    Stylesheet {
+        media_rules: vec![MediaRule {
+            media,
+            rules: stylesheet.rules,
+            position: 0,
+        }],
+        rules: vec![],
+        ..stylesheet
+    }
+}
+
+/// `append` moves the rules of `source` after the ones of `target`.
+fn append(target: &mut Stylesheet, source: Stylesheet) {
+    let offset = target.rules.len();
+    target.rules.extend(source.rules);
+    target.media_rules.extend(source.media_rules.into_iter().map(|media_rule| MediaRule {
+        position: media_rule.position + offset,
+        ..media_rule
+    }));
+    target.at_rules.extend(source.at_rules);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{self, Viewport};
+
+    /// `fixture` writes the files into a new temporary directory.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("css-loader-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for (path, content) in files {
+            fs::write(dir.join(path), content).unwrap();
+        }
+        dir
+    }
+
+    fn colors(stylesheet: &Stylesheet) -> Vec<String> {
+        stylesheet
+            .rules
+            .iter()
+            .map(|rule| rule.declarations[0].value.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = Path::new("/doc/index.html");
+        assert_eq!(resolve(Some(base), "a.css").unwrap(), PathBuf::from("/doc/a.css"));
+        assert_eq!(resolve(Some(base), "../b/a.css?v=1#x").unwrap(), PathBuf::from("/doc/../b/a.css"));
+        assert_eq!(resolve(Some(base), "/abs/a.css").unwrap(), PathBuf::from("/abs/a.css"));
+        assert_eq!(resolve(Some(base), "file:///abs/a.css").unwrap(), PathBuf::from("/abs/a.css"));
+        assert_eq!(resolve(Some(base), "file://localhost/abs/a.css").unwrap(), PathBuf::from("/abs/a.css"));
+        assert_eq!(resolve(None, "a.css").unwrap(), PathBuf::from("a.css"));
+        assert_eq!(matches!(resolve(Some(base), "https://example.com/a.css"), Err(LoadError::Unsupported(_))), true);
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = fixture(
+            "import",
+            &[
+                ("main.css", r#"@import "sub/a.css"; @import url(b.css) (max-width: 80ch); p { color: main; }"#),
+                ("sub/a.css", "@import '../c.css'; p { color: a; }"),
+                ("b.css", "p { color: b; }"),
+                ("c.css", "p { color: c; } @media (min-width: 1px) { p { color: c2; } }"),
+            ],
+        );
+        let mut loader = Loader::new();
+        let stylesheet = loader.load(None, dir.join("main.css").to_str().unwrap()).unwrap();
+        assert_eq!(loader.errors.len(), 0);
+        assert_eq!(stylesheet.at_rules, vec![]);
+
+        let narrow = stylesheet.resolve_media(&Viewport { width: 40, height: 24 });
+        assert_eq!(colors(&narrow), vec!["c", "c2", "a", "b", "main"]);
+        let wide = stylesheet.resolve_media(&Viewport { width: 100, height: 24 });
+        assert_eq!(colors(&wide), vec!["c", "c2", "a", "main"]);
+
+        // rules know the stylesheets they are written in
+        let sources = narrow.rules.iter().map(|rule| rule.source.clone().unwrap()).collect::<Vec<_>>();
+        assert_eq!(sources[0], dir.join("sub/../c.css").display().to_string());
+        assert_eq!(sources[4], dir.join("main.css").display().to_string());
+    }
+
+    #[test]
+    fn test_load_path() {
+        let dir = fixture("path", &[("a#1.css", "p { color: a; }")]);
+        let mut loader = Loader::new();
+        // a path is read as it is, whereas `#` starts the fragment of a URL
+        assert_eq!(loader.load_path(dir.join("a#1.css")).map(|sheet| colors(&sheet)), Some(vec!["a".to_string()]));
+        assert_eq!(loader.load(Some(&dir.join("index.html")), "a#1.css").is_none(), true);
+        assert_eq!(matches!(loader.errors[..], [LoadError::Io(..)]), true);
+    }
+
+    #[test]
+    fn test_apply_media() {
+        let stylesheet = css::parse("p { color: a; } @media (min-width: 1px) { p { color: b; } }");
+        assert_eq!(apply_media(stylesheet.clone(), vec![]), stylesheet);
+
+        let print = apply_media(stylesheet, css::parse_media("print").unwrap());
+        assert_eq!(colors(&print.resolve_media(&Viewport { width: 80, height: 24 })), Vec::<String>::new());
+        assert_eq!(print.media_rules.len(), 1);
+        assert_eq!(print.media_rules[0].rules.len(), 1);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = fixture(
+            "errors",
+            &[
+                ("a.css", "@import 'b.css'; @import 'missing.css'; @import 'http://example.com/x.css'; p { color: a; }"),
+                ("b.css", "@import 'a.css'; p { color: b;\ncolor }"),
+            ],
+        );
+        let mut loader = Loader::new();
+        let stylesheet = loader.load(Some(&dir.join("index.html")), "a.css").unwrap();
+        assert_eq!(colors(&stylesheet), vec!["b", "a"]);
+        assert_eq!(
+            loader.errors.iter().map(|e| matches!(e, LoadError::Cycle(_))).collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert_eq!(matches!(loader.errors[1], LoadError::Io(..)), true);
+        assert_eq!(matches!(loader.errors[2], LoadError::Unsupported(_)), true);
+        // parse errors in imported stylesheets are collected as well
+        assert_eq!(loader.diagnostics.iter().map(|d| d.line).collect::<Vec<_>>(), vec![2]);
+    }
+}
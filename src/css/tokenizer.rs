@@ -0,0 +1,556 @@
+//! This module implements the tokenization stage of the CSS parser.
+//!
+//! The algorithm follows [CSS Syntax Module Level 3](https://www.w3.org/TR/css-syntax-3/#tokenization).
+//! Comments are consumed by the tokenizer and never appear as tokens.
+
+use std::ops::Range;
+
+/// `Token` represents a token emitted by `Tokenizer`.
+/// See https://www.w3.org/TR/css-syntax-3/#tokenizer-definitions
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Ident(String),
+    // `name(`
+    Function(String),
+    // `@name`
+    AtKeyword(String),
+    // `#name`
+    Hash(String),
+    String(String),
+    // a string containing an unescaped newline
+    BadString,
+    // `url(foo.png)` without quotes
+    Url(String),
+    BadUrl,
+    Delim(char),
+    Number(f64),
+    Percentage(f64),
+    Dimension(f64, String),
+    Whitespace,
+    // `<!--`
+    Cdo,
+    // `-->`
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+    Eof,
+}
+
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer { input, pos: 0 }
+    }
+
+    /// `tokenize` consumes the whole input and returns tokens with their byte ranges in the input.
+    /// The trailing `Token::Eof` is not included.
+    pub fn tokenize(mut self) -> Vec<(Token, Range<usize>)> {
+        let mut tokens = vec![];
+        loop {
+            self.consume_comments();
+            let start = self.pos;
+            match self.next_token() {
+                Token::Eof => return tokens,
+                token => tokens.push((token, start..self.pos)),
+            }
+        }
+    }
+
+    /// `next_token` implements "consume a token".
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-token
+    pub fn next_token(&mut self) -> Token {
+        self.consume_comments();
+        let c = match self.consume() {
+            Some(c) => c,
+            None => return Token::Eof,
+        };
+        match c {
+            c if is_whitespace(c) => {
+                while self.peek(0).is_some_and(is_whitespace) {
+                    self.consume();
+                }
+                Token::Whitespace
+            },
+            '"' | '\'' => self.consume_string(c),
+            '#' => {
+                if self.peek(0).is_some_and(is_name) || self.starts_with_escape(0) {
+                    Token::Hash(self.consume_name())
+                } else {
+                    Token::Delim(c)
+                }
+            },
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenSquare,
+            ']' => Token::CloseSquare,
+            '{' => Token::OpenCurly,
+            '}' => Token::CloseCurly,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            ';' => Token::Semicolon,
+            // 数値や識別子の判定は、いま消費した文字から始める
+            '+' | '.' => {
+                self.reconsume(c);
+                if self.starts_number(0) {
+                    self.consume_numeric()
+                } else {
+                    self.consume();
+                    Token::Delim(c)
+                }
+            },
+            '-' => {
+                self.reconsume(c);
+                if self.starts_number(0) {
+                    self.consume_numeric()
+                } else if self.input[self.pos..].starts_with("-->") {
+                    self.pos += 3;
+                    Token::Cdc
+                } else if self.starts_identifier(0) {
+                    self.consume_ident_like()
+                } else {
+                    self.consume();
+                    Token::Delim(c)
+                }
+            },
+            '<' => {
+                if self.input[self.pos..].starts_with("!--") {
+                    self.pos += 3;
+                    Token::Cdo
+                } else {
+                    Token::Delim(c)
+                }
+            },
+            '@' => {
+                if self.starts_identifier(0) {
+                    Token::AtKeyword(self.consume_name())
+                } else {
+                    Token::Delim(c)
+                }
+            },
+            '\\' => {
+                self.reconsume(c);
+                if self.starts_with_escape(0) {
+                    self.consume_ident_like()
+                } else {
+                    self.consume();
+                    Token::Delim(c)
+                }
+            },
+            c if c.is_ascii_digit() => {
+                self.reconsume(c);
+                self.consume_numeric()
+            },
+            c if is_name_start(c) => {
+                self.reconsume(c);
+                self.consume_ident_like()
+            },
+            c => Token::Delim(c),
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn reconsume(&mut self, c: char) {
+        self.pos -= c.len_utf8();
+    }
+
+    // `/* ... */`
+    // 閉じられていないコメントは入力の終わりまで続く
+    fn consume_comments(&mut self) {
+        while self.input[self.pos..].starts_with("/*") {
+            match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos += 2 + end + 2,
+                None => self.pos = self.input.len(),
+            }
+        }
+    }
+
+    /// `starts_with_escape` checks whether the `n`-th next character and the one after it form a valid escape.
+    /// See https://www.w3.org/TR/css-syntax-3/#starts-with-a-valid-escape
+    fn starts_with_escape(&self, n: usize) -> bool {
+        self.peek(n) == Some('\\') && !matches!(self.peek(n + 1), Some('\n') | Some('\r') | Some('\x0C'))
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#would-start-an-identifier
+    fn starts_identifier(&self, n: usize) -> bool {
+        match self.peek(n) {
+            Some('-') => {
+                self.peek(n + 1).is_some_and(|c| is_name_start(c) || c == '-') || self.starts_with_escape(n + 1)
+            },
+            Some('\\') => self.starts_with_escape(n),
+            Some(c) => is_name_start(c),
+            None => false,
+        }
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#starts-with-a-number
+    fn starts_number(&self, n: usize) -> bool {
+        let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+        match self.peek(n) {
+            Some('+') | Some('-') => {
+                is_digit(self.peek(n + 1)) || (self.peek(n + 1) == Some('.') && is_digit(self.peek(n + 2)))
+            },
+            Some('.') => is_digit(self.peek(n + 1)),
+            c => is_digit(c),
+        }
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-string-token
+    fn consume_string(&mut self, quote: char) -> Token {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => return Token::String(value),
+                Some(c) if c == quote => {
+                    self.consume();
+                    return Token::String(value);
+                },
+                // 改行は文字列に含められない。改行自体は消費しない
+                Some('\n') | Some('\r') | Some('\x0C') => return Token::BadString,
+                Some('\\') => {
+                    self.consume();
+                    match self.peek(0) {
+                        None => {},
+                        Some('\n') | Some('\r') | Some('\x0C') => {
+                            self.consume();
+                        },
+                        Some(_) => value.push(self.consume_escape()),
+                    }
+                },
+                Some(c) => {
+                    self.consume();
+                    value.push(c);
+                },
+            }
+        }
+    }
+
+    /// `consume_escape` consumes an escaped code point after `\`.
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+    fn consume_escape(&mut self) -> char {
+        let mut hex = String::new();
+        while hex.len() < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.consume().unwrap());
+        }
+        if hex.is_empty() {
+            return self.consume().unwrap_or('\u{FFFD}');
+        }
+        if self.peek(0).is_some_and(is_whitespace) {
+            self.consume();
+        }
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|&code| code != 0)
+            .and_then(std::char::from_u32)
+            .unwrap_or('\u{FFFD}')
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-name
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name(c) => {
+                    self.consume();
+                    name.push(c);
+                },
+                Some('\\') if self.starts_with_escape(0) => {
+                    self.consume();
+                    name.push(self.consume_escape());
+                },
+                _ => return name,
+            }
+        }
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-numeric-token
+    fn consume_numeric(&mut self) -> Token {
+        let number = self.consume_number();
+        if self.starts_identifier(0) {
+            Token::Dimension(number, self.consume_name())
+        } else if self.peek(0) == Some('%') {
+            self.consume();
+            Token::Percentage(number)
+        } else {
+            Token::Number(number)
+        }
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-number
+    fn consume_number(&mut self) -> f64 {
+        let start = self.pos;
+        let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+        if matches!(self.peek(0), Some('+') | Some('-')) {
+            self.consume();
+        }
+        while is_digit(self.peek(0)) {
+            self.consume();
+        }
+        if self.peek(0) == Some('.') && is_digit(self.peek(1)) {
+            self.consume();
+            while is_digit(self.peek(0)) {
+                self.consume();
+            }
+        }
+        if matches!(self.peek(0), Some('e') | Some('E')) {
+            let sign = usize::from(matches!(self.peek(1), Some('+') | Some('-')));
+            if is_digit(self.peek(1 + sign)) {
+                for _ in 0..=sign {
+                    self.consume();
+                }
+                while is_digit(self.peek(0)) {
+                    self.consume();
+                }
+            }
+        }
+        self.input[start..self.pos].parse().unwrap_or(0.0)
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+    fn consume_ident_like(&mut self) -> Token {
+        let name = self.consume_name();
+        if self.peek(0) != Some('(') {
+            return Token::Ident(name);
+        }
+        self.consume();
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+
+        // 引用符で囲まれたURLは関数と文字列として扱う
+        let rest = self.input[self.pos..].trim_start_matches(is_whitespace);
+        if rest.starts_with('"') || rest.starts_with('\'') {
+            return Token::Function(name);
+        }
+        self.consume_url()
+    }
+
+    /// See https://www.w3.org/TR/css-syntax-3/#consume-url-token
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        while self.peek(0).is_some_and(is_whitespace) {
+            self.consume();
+        }
+        loop {
+            match self.consume() {
+                None | Some(')') => return Token::Url(url),
+                Some(c) if is_whitespace(c) => {
+                    while self.peek(0).is_some_and(is_whitespace) {
+                        self.consume();
+                    }
+                    return match self.peek(0) {
+                        None => Token::Url(url),
+                        Some(')') => {
+                            self.consume();
+                            Token::Url(url)
+                        },
+                        Some(_) => self.consume_bad_url(),
+                    };
+                },
+                Some('"') | Some('\'') | Some('(') => return self.consume_bad_url(),
+                Some('\\') => {
+                    if self.starts_with_escape(0) || self.peek(0).is_none() {
+                        url.push(self.consume_escape());
+                    } else {
+                        return self.consume_bad_url();
+                    }
+                },
+                Some(c) => url.push(c),
+            }
+        }
+    }
+
+    // 壊れたURLの残りを`)`まで読み飛ばす
+    fn consume_bad_url(&mut self) -> Token {
+        loop {
+            match self.consume() {
+                None | Some(')') => return Token::BadUrl,
+                Some('\\') if self.peek(0).is_some() => {
+                    self.consume();
+                },
+                Some(_) => {},
+            }
+        }
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C')
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(raw: &str) -> Vec<Token> {
+        Tokenizer::new(raw).tokenize().into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn test_rule() {
+        assert_eq!(
+            tokens("p.note > a:hover{color:red;}"),
+            vec![
+                Token::Ident("p".to_string()),
+                Token::Delim('.'),
+                Token::Ident("note".to_string()),
+                Token::Whitespace,
+                Token::Delim('>'),
+                Token::Whitespace,
+                Token::Ident("a".to_string()),
+                Token::Colon,
+                Token::Ident("hover".to_string()),
+                Token::OpenCurly,
+                Token::Ident("color".to_string()),
+                Token::Colon,
+                Token::Ident("red".to_string()),
+                Token::Semicolon,
+                Token::CloseCurly,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            tokens("-webkit-box _private --custom-prop a1 日本 \\31 0"),
+            vec![
+                Token::Ident("-webkit-box".to_string()),
+                Token::Whitespace,
+                Token::Ident("_private".to_string()),
+                Token::Whitespace,
+                Token::Ident("--custom-prop".to_string()),
+                Token::Whitespace,
+                Token::Ident("a1".to_string()),
+                Token::Whitespace,
+                Token::Ident("日本".to_string()),
+                Token::Whitespace,
+                Token::Ident("10".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens("rgb(#fff @media @-x-foo #1"),
+            vec![
+                Token::Function("rgb".to_string()),
+                Token::Hash("fff".to_string()),
+                Token::Whitespace,
+                Token::AtKeyword("media".to_string()),
+                Token::Whitespace,
+                Token::AtKeyword("-x-foo".to_string()),
+                Token::Whitespace,
+                Token::Hash("1".to_string()),
+            ]
+        );
+        assert_eq!(tokens("@ 1"), vec![Token::Delim('@'), Token::Whitespace, Token::Number(1.0)]);
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(
+            tokens("10px -.5em +3 50% 1e3 1e 2E-1"),
+            vec![
+                Token::Dimension(10.0, "px".to_string()),
+                Token::Whitespace,
+                Token::Dimension(-0.5, "em".to_string()),
+                Token::Whitespace,
+                Token::Number(3.0),
+                Token::Whitespace,
+                Token::Percentage(50.0),
+                Token::Whitespace,
+                Token::Number(1000.0),
+                Token::Whitespace,
+                Token::Dimension(1.0, "e".to_string()),
+                Token::Whitespace,
+                Token::Number(0.2),
+            ]
+        );
+        assert_eq!(
+            tokens("a-1 -a"),
+            vec![
+                Token::Ident("a-1".to_string()),
+                Token::Whitespace,
+                Token::Ident("-a".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings_and_urls() {
+        assert_eq!(
+            tokens(r#""a\"b" 'c\
+d' "e
+"#),
+            vec![
+                Token::String("a\"b".to_string()),
+                Token::Whitespace,
+                Token::String("cd".to_string()),
+                Token::Whitespace,
+                Token::BadString,
+                Token::Whitespace,
+            ]
+        );
+        assert_eq!(
+            tokens(r#"url( a.png ) url("b.png") url(a b) url(c"#),
+            vec![
+                Token::Url("a.png".to_string()),
+                Token::Whitespace,
+                Token::Function("url".to_string()),
+                Token::String("b.png".to_string()),
+                Token::CloseParen,
+                Token::Whitespace,
+                Token::BadUrl,
+                Token::Whitespace,
+                Token::Url("c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            tokens("/* a */p/**/{/* b"),
+            vec![Token::Ident("p".to_string()), Token::OpenCurly]
+        );
+        assert_eq!(tokens("<!-- p -->"), vec![Token::Cdo, Token::Whitespace, Token::Ident("p".to_string()), Token::Whitespace, Token::Cdc]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(
+            Tokenizer::new("/* x */ a  é").tokenize(),
+            vec![
+                (Token::Whitespace, 7..8),
+                (Token::Ident("a".to_string()), 8..9),
+                (Token::Whitespace, 9..11),
+                (Token::Ident("é".to_string()), 11..13),
+            ]
+        );
+    }
+}
@@ -1,131 +1,656 @@
 use std::collections::HashMap;
-
-use combine::parser::token::Value;
+use std::fmt;
+use std::ops::{Index, IndexMut};
 
 use crate::html;
+use crate::source::SourcePosition;
 pub type AttrMap = HashMap<String, String>;
 
-#[derive(Debug, PartialEq)]
+/// `NodeId` is a handle to a node stored in an `Arena`.
+/// Nodes are never freed while the arena is alive, so a `NodeId` stays valid even after the node is removed from the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// `index` returns the position of the node in the arena.
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    /// `from_index` restores a `NodeId` from the value returned by `index`.
+    pub(crate) fn from_index(index: usize) -> NodeId {
+        NodeId(index)
+    }
+}
+
+/// `Node` is a node stored in an `Arena`.
+/// Links to other nodes are maintained by the arena, and hence they are read-only here.
+#[derive(Debug)]
 pub struct Node {
     pub node_type: NodeType,
-    pub children: Vec<Box<Node>>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    // パーサーが生成したノードの、入力中の位置
+    position: Option<SourcePosition>,
+}
+
+/// Nodes are compared regardless of their positions, so that the same tree parsed from different sources is equal.
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_type == other.node_type
+            && self.parent == other.parent
+            && self.first_child == other.first_child
+            && self.last_child == other.last_child
+            && self.previous_sibling == other.previous_sibling
+            && self.next_sibling == other.next_sibling
+    }
 }
 
 impl Node {
-    pub fn inner_text(&self) -> String {
-        self.children
-            .iter()
-            .clone()
-            .into_iter()
-            .map(|node| match &node.node_type {
-                NodeType::Text(text) => text.data.clone(),
-                _ => node.inner_text(),
+    fn new(node_type: NodeType) -> Node {
+        Node {
+            node_type,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+            position: None,
+        }
+    }
+
+    /// `position` returns where the node starts in the source it was parsed from.
+    /// It is `None` for nodes created by scripts or implied by the parser, such as a missing `<body>`.
+    pub fn position(&self) -> Option<SourcePosition> {
+        self.position
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+}
+
+/// `DomError` represents exceptions thrown by the tree mutation methods of `Arena`.
+/// See https://dom.spec.whatwg.org/#concept-node-ensure-pre-insertion-validity
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DomError {
+    /// The operation would yield an incorrect node tree.
+    HierarchyRequest,
+    /// The reference node is not a child of the parent.
+    NotFound,
+}
+
+impl fmt::Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomError::HierarchyRequest => write!(f, "HierarchyRequestError: the operation would yield an incorrect node tree"),
+            DomError::NotFound => write!(f, "NotFoundError: the object can not be found here"),
+        }
+    }
+}
+
+/// `Arena` owns every node of a document, including ones removed from the tree.
+/// The document node is created together with the arena, and other nodes are created detached from the tree.
+#[derive(Debug, PartialEq)]
+pub struct Arena {
+    nodes: Vec<Node>,
+}
+
+const DOCUMENT: NodeId = NodeId(0);
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            nodes: vec![Node::new(NodeType::Document(Document {
+                quirks_mode: QuirksMode::NoQuirks,
+                url: None,
+            }))],
+        }
+    }
+
+    /// `document` returns the document node, which is the root of the tree.
+    pub fn document(&self) -> NodeId {
+        DOCUMENT
+    }
+
+    /// `document_element` returns the first element child of the document, i.e. the `<html>` element.
+    pub fn document_element(&self) -> Option<NodeId> {
+        self.children(DOCUMENT)
+            .find(|&child| matches!(self[child].node_type, NodeType::Element(_)))
+    }
+
+    pub fn quirks_mode(&self) -> QuirksMode {
+        match self[DOCUMENT].node_type {
+            NodeType::Document(ref document) => document.quirks_mode,
+            _ => unreachable!("the first node of an arena is always the document"),
+        }
+    }
+
+    /// `url` returns where the document was loaded from, if it is known.
+    pub fn url(&self) -> Option<&str> {
+        match self[DOCUMENT].node_type {
+            NodeType::Document(ref document) => document.url.as_deref(),
+            _ => unreachable!("the first node of an arena is always the document"),
+        }
+    }
+
+    pub fn set_url(&mut self, url: &str) {
+        match self[DOCUMENT].node_type {
+            NodeType::Document(ref mut document) => document.url = Some(url.to_string()),
+            _ => unreachable!("the first node of an arena is always the document"),
+        }
+    }
+
+    fn create_node(&mut self, node_type: NodeType) -> NodeId {
+        self.nodes.push(Node::new(node_type));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub(crate) fn set_position(&mut self, id: NodeId, position: Option<SourcePosition>) {
+        self[id].position = position;
+    }
+
+    pub fn create_element(&mut self, tag_name: &str, attributes: AttrMap) -> NodeId {
+        self.create_node(NodeType::Element(Element::new(tag_name, attributes)))
+    }
+
+    pub fn create_text(&mut self, data: &str) -> NodeId {
+        self.create_node(NodeType::Text(Text { data: data.to_string() }))
+    }
+
+    pub fn create_comment(&mut self, data: &str) -> NodeId {
+        self.create_node(NodeType::Comment(Comment { data: data.to_string() }))
+    }
+
+    pub fn create_document_type(&mut self, name: &str, public_id: &str, system_id: &str) -> NodeId {
+        self.create_node(NodeType::DocumentType(DocumentType {
+            name: name.to_string(),
+            public_id: public_id.to_string(),
+            system_id: system_id.to_string(),
+        }))
+    }
+
+    /// `children` returns an iterator over the children of the node.
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            arena: self,
+            next: self[id].first_child,
+        }
+    }
+
+    /// `descendants` returns an iterator over the descendants of the node in tree order.
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants {
+            arena: self,
+            root: id,
+            next: self[id].first_child,
+        }
+    }
+
+    /// `ancestors` returns an iterator over the ancestors of the node, from its parent to the root.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self[id].parent, move |&node| self[node].parent)
+    }
+
+    /// `previous_siblings` returns an iterator over the preceding siblings of the node, from the nearest one.
+    pub fn previous_siblings(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self[id].previous_sibling, move |&node| self[node].previous_sibling)
+    }
+
+    /// `is_inclusive_ancestor` returns whether `ancestor` is `id` itself or one of its ancestors.
+    pub fn is_inclusive_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self[node].parent;
+        }
+        false
+    }
+
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<NodeId, DomError> {
+        self.insert_before(parent, child, None)
+    }
+
+    /// `insert_before` inserts `child` into `parent` before `reference`, or at the end if `reference` is `None`.
+    /// `child` is removed from its current parent first.
+    /// See https://dom.spec.whatwg.org/#concept-node-pre-insert
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> Result<NodeId, DomError> {
+        if let Some(reference) = reference {
+            if self[reference].parent != Some(parent) {
+                return Err(DomError::NotFound);
+            }
+        }
+        self.ensure_insertion_validity(parent, child)?;
+
+        let reference = if reference == Some(child) {
+            self[child].next_sibling
+        } else {
+            reference
+        };
+        self.detach(child);
+        self.link(parent, child, reference);
+        Ok(child)
+    }
+
+    /// `remove_child` removes `child` from `parent`. The removed node and its subtree stay in the arena.
+    /// See https://dom.spec.whatwg.org/#concept-node-pre-remove
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<NodeId, DomError> {
+        if self[child].parent != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.detach(child);
+        Ok(child)
+    }
+
+    /// `replace_child` replaces `old_child` of `parent` with `new_child`, and returns `old_child`.
+    /// See https://dom.spec.whatwg.org/#concept-node-replace
+    pub fn replace_child(&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) -> Result<NodeId, DomError> {
+        if self[old_child].parent != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.ensure_insertion_validity(parent, new_child)?;
+
+        let mut reference = self[old_child].next_sibling;
+        if reference == Some(new_child) {
+            reference = self[new_child].next_sibling;
+        }
+        self.detach(old_child);
+        self.detach(new_child);
+        self.link(parent, new_child, reference);
+        Ok(old_child)
+    }
+
+    /// `ensure_insertion_validity` checks whether `child` can be a child of `parent`.
+    fn ensure_insertion_validity(&self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        let parent_type = &self[parent].node_type;
+        if !matches!(parent_type, NodeType::Document(_) | NodeType::Element(_)) {
+            return Err(DomError::HierarchyRequest);
+        }
+        if self.is_inclusive_ancestor(child, parent) {
+            return Err(DomError::HierarchyRequest);
+        }
+        match (parent_type, &self[child].node_type) {
+            (_, NodeType::Document(_)) => Err(DomError::HierarchyRequest),
+            (NodeType::Document(_), NodeType::Text(_)) => Err(DomError::HierarchyRequest),
+            (NodeType::Element(_), NodeType::DocumentType(_)) => Err(DomError::HierarchyRequest),
+            _ => Ok(()),
+        }
+    }
+
+    /// `detach` unlinks the node from its parent and siblings if any.
+    fn detach(&mut self, id: NodeId) {
+        let (parent, previous, next) = {
+            let node = &self[id];
+            (node.parent, node.previous_sibling, node.next_sibling)
+        };
+        if let Some(parent) = parent {
+            match previous {
+                Some(previous) => self[previous].next_sibling = next,
+                None => self[parent].first_child = next,
+            }
+            match next {
+                Some(next) => self[next].previous_sibling = previous,
+                None => self[parent].last_child = previous,
+            }
+        }
+        let node = &mut self[id];
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    /// `link` inserts the detached node `child` into `parent` before `reference`.
+    fn link(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        let previous = match reference {
+            Some(reference) => self[reference].previous_sibling,
+            None => self[parent].last_child,
+        };
+        {
+            let node = &mut self[child];
+            node.parent = Some(parent);
+            node.previous_sibling = previous;
+            node.next_sibling = reference;
+        }
+        match previous {
+            Some(previous) => self[previous].next_sibling = Some(child),
+            None => self[parent].first_child = Some(child),
+        }
+        match reference {
+            Some(reference) => self[reference].previous_sibling = Some(child),
+            None => self[parent].last_child = Some(child),
+        }
+    }
+
+    pub fn inner_text(&self, id: NodeId) -> String {
+        self.descendants(id)
+            .filter_map(|node| match self[node].node_type {
+                NodeType::Text(ref text) => Some(text.data.as_str()),
+                _ => None,
             })
-            .collect::<Vec<String>>()
+            .collect::<Vec<&str>>()
             .join("")
     }
 
-    pub fn inner_html(&self) -> String {
-        self.children
-            .iter()
-            .clone()
-            .into_iter()
-            .map(|node| node.to_string())
+    pub fn inner_html(&self, id: NodeId) -> String {
+        let raw_text = match self[id].node_type {
+            NodeType::Element(ref el) => el.is_raw_text(),
+            _ => false,
+        };
+        self.children(id)
+            .map(|child| self.serialize(child, raw_text))
             .collect::<Vec<String>>()
             .join("")
     }
 
-    pub fn set_inner_html(&mut self, html: &str) {
-        let nodes = html::parse_raw(html.into());
-        self.children = nodes;
+    /// `outer_html` serializes the node itself and its descendants.
+    pub fn outer_html(&self, id: NodeId) -> String {
+        self.serialize(id, false)
     }
 
-    pub fn get_element_by_id<'a>(self: &'a mut Box<Node>, id: &str) -> Option<&'a mut Box<Node>> {
-        match self.node_type {
-            // HTML要素の場合
+    /// `serialize` implements the HTML fragment serialization algorithm for a single node.
+    /// Text is escaped unless the parent is a raw text element like `<style>`.
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+    fn serialize(&self, id: NodeId, raw_text: bool) -> String {
+        match self[id].node_type {
             NodeType::Element(ref el) => {
-                // 指定したidがある場合はその要素を返す
-                let has_element_id = el.id().map(|element_id| element_id.to_string() == id).unwrap_or(false);
-                if has_element_id {
-                    return Some(self);
-                }
-            },
-            // テキストの場合は何もしない
-            _ => (),
-        };
-        self.children
-            .iter_mut()
-            .find_map(|child| child.get_element_by_id(id))
-    }
-}
-
-// Implement to_string for Node
-impl ToString for Node {
-    fn to_string(&self) -> String {
-        match self.node_type {
-            // html要素の場合は再帰的に処理する
-            NodeType::Element(ref el) => {
-                // attributes to string
                 let attrs = el.attributes
                     .iter()
-                    .clone()
-                    .into_iter()
                     .map(|(key, value)| {
-                        format!("{}=\"{}\"", key, value)
+                        format!("{}=\"{}\"", key, escape_attribute(value))
                     })
                     .collect::<Vec<_>>()
                     .join(" ");
 
-                let children = self.children
-                    .iter()
-                    .clone()
-                    .into_iter()
-                    .map(|node| node.to_string())
-                    .collect::<Vec<_>>()
-                    .join("");
-
-                // attributesがない時
-                if attrs.is_empty() {
-                    // <tag_name>children</tag_name>
-                    format!("<{}>{}</{}>", el.tag_name, children, el.tag_name)
+                let open_tag = if attrs.is_empty() {
+                    format!("<{}>", el.tag_name)
                 } else {
-                    // <tag_name attributes>children</tag_name>
-                    format!("<{} {}>{}</{}>", el.tag_name, attrs, children, el.tag_name)
+                    format!("<{} {}>", el.tag_name, attrs)
+                };
+
+                // void elements have neither children nor an end tag
+                if el.is_void() {
+                    return open_tag;
                 }
+                format!("{}{}</{}>", open_tag, self.inner_html(id), el.tag_name)
             },
-            // テキストの場合はそのまま文字列を返す
-            NodeType::Text(ref text) => text.data.clone()
+            NodeType::Text(ref text) if raw_text => text.data.clone(),
+            NodeType::Text(ref text) => escape_text(&text.data),
+            NodeType::Comment(ref comment) => format!("<!--{}-->", comment.data),
+            NodeType::DocumentType(ref doctype) => format!("<!DOCTYPE {}>", doctype.name),
+            NodeType::Document(_) => self.inner_html(id),
+        }
+    }
+
+    /// `set_inner_html` replaces the children of the element or the document with the result of parsing `html`.
+    /// The other nodes cannot have children, so `DomError::HierarchyRequest` is returned for them.
+    ///
+    /// NOTE: the old children are detached but never freed, as no node is removed from the arena;
+    /// scripts may still refer to them. Every call leaves the old subtree in the arena.
+    pub fn set_inner_html(&mut self, id: NodeId, html: &str) -> Result<(), DomError> {
+        let context = match self[id].node_type {
+            NodeType::Element(ref el) => el.tag_name.clone(),
+            NodeType::Document(_) => "body".to_string(),
+            _ => return Err(DomError::HierarchyRequest),
+        };
+        let nodes = html::parse_fragment(self, html, &context);
+
+        while let Some(child) = self[id].first_child {
+            self.detach(child);
         }
+        for node in nodes {
+            self.link(id, node, None);
+        }
+        Ok(())
+    }
+
+    /// `get_element_by_id` returns the first element in the document whose id is `id`.
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.descendants(DOCUMENT).find(|&node| match self[node].node_type {
+            // 指定したidがある場合はその要素を返す
+            NodeType::Element(ref el) => el.id().map(|element_id| element_id == id).unwrap_or(false),
+            // テキストの場合は何もしない
+            _ => false,
+        })
     }
 }
 
+impl Index<NodeId> for Arena {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+impl IndexMut<NodeId> for Arena {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+}
+
+/// `Children` is an iterator over the children of a node.
+pub struct Children<'a> {
+    arena: &'a Arena,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.arena[current].next_sibling;
+        Some(current)
+    }
+}
+
+/// `Descendants` is an iterator over the descendants of a node in tree order.
+pub struct Descendants<'a> {
+    arena: &'a Arena,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.arena[current].first_child.or_else(|| {
+            // 子がなければ、兄弟か祖先の兄弟に進む
+            let mut node = current;
+            while node != self.root {
+                if let Some(sibling) = self.arena[node].next_sibling {
+                    return Some(sibling);
+                }
+                node = self.arena[node].parent?;
+            }
+            None
+        });
+        Some(current)
+    }
+}
+
+/// `escape_text` escapes `&`, `<`, `>` and U+00A0 so that text nodes can be parsed back to the same data.
+fn escape_text(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `escape_attribute` escapes `&`, `"` and U+00A0 in a double-quoted attribute value.
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 #[derive(Debug, PartialEq)]
 pub enum NodeType {
+    Document(Document),
+    DocumentType(DocumentType),
     Element(Element),
     Text(Text),
+    Comment(Comment),
 }
 
+/// `QuirksMode` is the mode of a document decided by its DOCTYPE.
+/// See https://dom.spec.whatwg.org/#concept-document-quirks
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// `Document` represents the root of a parsed HTML document.
+/// See https://dom.spec.whatwg.org/#interface-document
 #[derive(Debug, PartialEq)]
+pub struct Document {
+    pub quirks_mode: QuirksMode,
+    // 文書のパスまたは`file://`のURL。相対URLの解決に使う
+    pub url: Option<String>,
+}
+
+/// `DocumentType` represents a DOCTYPE like `<!DOCTYPE html>`.
+/// Missing public and system identifiers are represented by empty strings as in the DOM.
+/// See https://dom.spec.whatwg.org/#interface-documenttype
+#[derive(Debug, PartialEq)]
+pub struct DocumentType {
+    pub name: String,
+    pub public_id: String,
+    pub system_id: String,
+}
+
+/// `Comment` represents a comment like `<!-- ... -->`.
+/// Processing instructions such as `<?xml ... ?>` are parsed into comments in HTML as well.
+/// See https://dom.spec.whatwg.org/#interface-comment
+#[derive(Debug, PartialEq)]
+pub struct Comment {
+    pub data: String,
+}
+
+/// `Element` represents an element node.
+/// Attributes are only mutable through `set_attribute` so that the cached class list stays in sync.
+#[derive(Debug)]
 pub struct Element {
     pub tag_name: String,
-    pub attributes: AttrMap,
+    attributes: AttrMap,
+    // `class`属性を空白で区切ったもの。セレクタのマッチのたびに分割しないようにキャッシュする
+    class_list: Vec<String>,
+    // 属性名の入力中の位置
+    attribute_positions: HashMap<String, SourcePosition>,
+}
+
+/// Elements are compared regardless of the positions of their attributes, as `Node` is.
+impl PartialEq for Element {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag_name == other.tag_name && self.attributes == other.attributes
+    }
 }
 
 impl Element {
-    pub fn new(name: String, attributes: AttrMap, children: Vec<Box<Node>>) -> Box<Node> {
-        Box::new(Node {
-            node_type: NodeType::Element(Element {
-                tag_name: name,
-                attributes: attributes,
-            }),
-            children,
-        })
+    pub fn new(tag_name: &str, attributes: AttrMap) -> Element {
+        let class_list = split_class_list(attributes.get("class").map(String::as_str));
+        Element {
+            tag_name: tag_name.to_string(),
+            attributes,
+            class_list,
+            attribute_positions: HashMap::new(),
+        }
+    }
+
+    /// `is_void` returns whether the element is a void element, which never has children.
+    pub fn is_void(&self) -> bool {
+        matches!(
+            self.tag_name.as_str(),
+            "area" | "base" | "basefont" | "bgsound" | "br" | "col" | "embed" | "frame" | "hr" | "img" | "input"
+                | "keygen" | "link" | "meta" | "param" | "source" | "track" | "wbr"
+        )
+    }
+
+    /// `is_raw_text` returns whether the text content of the element is serialized without escaping.
+    pub fn is_raw_text(&self) -> bool {
+        matches!(
+            self.tag_name.as_str(),
+            "style" | "script" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext" | "noscript"
+        )
     }
 
     pub fn id(&self) -> Option<&String> {
         self.attributes.get("id")
     }
 
+    pub fn attribute(&self, name: &str) -> Option<&String> {
+        self.attributes.get(name)
+    }
+
+    /// `set_attribute` also forgets where the attribute was in the source, as the value no longer comes from there.
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.attributes.insert(name.to_string(), value.to_string());
+        self.attribute_positions.remove(name);
+        if name == "class" {
+            self.class_list = split_class_list(Some(value));
+        }
+    }
+
+    /// `attribute_position` returns where the name of the attribute starts in the source it was parsed from.
+    pub fn attribute_position(&self, name: &str) -> Option<SourcePosition> {
+        self.attribute_positions.get(name).copied()
+    }
+
+    pub(crate) fn set_attribute_position(&mut self, name: &str, position: SourcePosition) {
+        self.attribute_positions.insert(name.to_string(), position);
+    }
+
+    /// `class_list` returns the tokens of the `class` attribute.
+    /// See https://dom.spec.whatwg.org/#dom-element-classlist
+    pub fn class_list(&self) -> &[String] {
+        &self.class_list
+    }
+
     pub fn attributes(&self) -> Vec<(String, String)> {
         self.attributes
             .iter()
@@ -136,16 +661,180 @@ impl Element {
     }
 }
 
+// 重複したクラス名は最初のものだけを残す (ordered set parser)
+fn split_class_list(class: Option<&str>) -> Vec<String> {
+    let mut class_list: Vec<String> = vec![];
+    for token in class.map(|class| class.split_ascii_whitespace()).into_iter().flatten() {
+        if !class_list.iter().any(|c| c == token) {
+            class_list.push(token.to_string());
+        }
+    }
+    class_list
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Text {
     pub data: String,
 }
 
-impl Text {
-    pub fn new(text: String) -> Box<Node> {
-        Box::new(Node {
-            node_type: NodeType::Text(Text { data: text }),
-            children: vec![],
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outer_html_escapes() {
+        let mut arena = Arena::new();
+        let p = arena.create_element(
+            "p",
+            [("title".to_string(), "a \"b\" & c".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        let text = arena.create_text("1 < 2 && 3 > 2\u{a0}");
+        arena.append_child(p, text).unwrap();
+        assert_eq!(
+            arena.outer_html(p),
+            r#"<p title="a &quot;b&quot; &amp; c">1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;</p>"#
+        );
+    }
+
+    #[test]
+    fn test_outer_html_raw_text_and_void_elements() {
+        let mut arena = Arena::new();
+        let div = arena.create_element("div", AttrMap::new());
+        let script = arena.create_element("script", AttrMap::new());
+        let text = arena.create_text("a && b");
+        let br = arena.create_element("br", AttrMap::new());
+        arena.append_child(script, text).unwrap();
+        arena.append_child(div, script).unwrap();
+        arena.append_child(div, br).unwrap();
+        assert_eq!(arena.outer_html(div), "<div><script>a && b</script><br></div>");
+    }
+
+    #[test]
+    fn test_inner_html_round_trip() {
+        let html = r#"<p title="&quot;&amp;&quot;">&lt;p&gt; &amp;amp; &nbsp;</p><img alt="&lt;">"#;
+        let mut arena = Arena::new();
+        let div = arena.create_element("div", AttrMap::new());
+        arena.set_inner_html(div, html).unwrap();
+        assert_eq!(arena.inner_text(div), "<p> &amp; \u{a0}");
+        assert_eq!(arena.inner_html(div), r#"<p title="&quot;&amp;&quot;">&lt;p&gt; &amp;amp; &nbsp;</p><img alt="<">"#);
+
+        let serialized = arena.inner_html(div);
+        let reparsed = arena.create_element("div", AttrMap::new());
+        arena.set_inner_html(reparsed, &serialized).unwrap();
+        assert_eq!(arena.inner_html(reparsed), serialized);
+
+        // leaf nodes cannot have children
+        let text = arena.create_text("a");
+        let comment = arena.create_comment("b");
+        assert_eq!(arena.set_inner_html(text, "<p>c</p>"), Err(DomError::HierarchyRequest));
+        assert_eq!(arena.set_inner_html(comment, "<p>c</p>"), Err(DomError::HierarchyRequest));
+        assert_eq!(arena[text].first_child(), None);
+        assert_eq!(arena[comment].first_child(), None);
+    }
+
+    #[test]
+    fn test_tree_mutation() {
+        let mut arena = Arena::new();
+        let ul = arena.create_element("ul", AttrMap::new());
+        let items = ["a", "b", "c"]
+            .iter()
+            .map(|s| {
+                let li = arena.create_element("li", AttrMap::new());
+                let text = arena.create_text(s);
+                arena.append_child(li, text).unwrap();
+                li
+            })
+            .collect::<Vec<_>>();
+        let (a, b, c) = (items[0], items[1], items[2]);
+
+        arena.append_child(ul, a).unwrap();
+        arena.append_child(ul, c).unwrap();
+        arena.insert_before(ul, b, Some(c)).unwrap();
+        assert_eq!(arena.children(ul).collect::<Vec<_>>(), vec![a, b, c]);
+        assert_eq!(arena[b].parent(), Some(ul));
+        assert_eq!(arena[b].previous_sibling(), Some(a));
+        assert_eq!(arena[b].next_sibling(), Some(c));
+
+        // re-inserting moves the node
+        arena.append_child(ul, a).unwrap();
+        assert_eq!(arena.outer_html(ul), "<ul><li>b</li><li>c</li><li>a</li></ul>");
+
+        assert_eq!(arena.replace_child(ul, a, b), Ok(b));
+        assert_eq!(arena.outer_html(ul), "<ul><li>a</li><li>c</li></ul>");
+        assert_eq!(arena[b].parent(), None);
+
+        assert_eq!(arena.remove_child(ul, c), Ok(c));
+        assert_eq!(arena.outer_html(ul), "<ul><li>a</li></ul>");
+        assert_eq!(arena[ul].first_child(), Some(a));
+        assert_eq!(arena[ul].last_child(), Some(a));
+
+        // removed nodes are still accessible through their ids
+        assert_eq!(arena.outer_html(c), "<li>c</li>");
+    }
+
+    #[test]
+    fn test_tree_mutation_errors() {
+        let mut arena = Arena::new();
+        let div = arena.create_element("div", AttrMap::new());
+        let p = arena.create_element("p", AttrMap::new());
+        let text = arena.create_text("text");
+        arena.append_child(div, p).unwrap();
+
+        // a node cannot be inserted into itself or its descendants
+        assert_eq!(arena.append_child(p, div), Err(DomError::HierarchyRequest));
+        assert_eq!(arena.append_child(div, div), Err(DomError::HierarchyRequest));
+        // text nodes cannot have children
+        assert_eq!(arena.append_child(text, div), Err(DomError::HierarchyRequest));
+        // the document cannot be inserted anywhere
+        let document = arena.document();
+        assert_eq!(arena.append_child(div, document), Err(DomError::HierarchyRequest));
+        // the reference node must be a child of the parent
+        assert_eq!(arena.insert_before(div, text, Some(div)), Err(DomError::NotFound));
+        assert_eq!(arena.remove_child(p, div), Err(DomError::NotFound));
+        assert_eq!(arena.replace_child(p, text, div), Err(DomError::NotFound));
+
+        assert_eq!(arena.outer_html(div), "<div><p></p></div>");
+    }
+
+    #[test]
+    fn test_get_element_by_id() {
+        let mut arena = Arena::new();
+        let html = arena.create_element("html", AttrMap::new());
+        let document = arena.document();
+        arena.append_child(document, html).unwrap();
+        arena.set_inner_html(html, r#"<p id="a">a</p><div><p id="b">b</p></div>"#).unwrap();
+
+        let b = arena.get_element_by_id("b").unwrap();
+        assert_eq!(arena.outer_html(b), r#"<p id="b">b</p>"#);
+        assert_eq!(arena.get_element_by_id("c"), None);
+
+        // detached nodes are not found
+        let parent = arena[b].parent().unwrap();
+        arena.remove_child(parent, b).unwrap();
+        assert_eq!(arena.get_element_by_id("b"), None);
+    }
+
+    #[test]
+    fn test_class_list() {
+        let mut element = Element::new(
+            "p",
+            [("class".to_string(), "  inline\tbig\ninline ".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        assert_eq!(element.class_list(), ["inline", "big"]);
+
+        element.set_attribute("class", "note");
+        assert_eq!(element.class_list(), ["note"]);
+        assert_eq!(element.attribute("class"), Some(&"note".to_string()));
+
+        element.set_attribute("id", "a");
+        assert_eq!(element.class_list(), ["note"]);
+
+        assert!(Element::new("p", AttrMap::new()).class_list().is_empty());
     }
 }
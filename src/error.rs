@@ -0,0 +1,111 @@
+//! This module defines the errors which are reported to applications embedding the renderer.
+
+use std::{error, fmt};
+
+use crate::{css, css::loader::LoadError, html};
+
+/// `Error` represents a problem found while loading or rendering a page.
+///
+/// The HTML and CSS parsers recover from every parse error as the specifications require,
+/// so `Html` and `Css` are reported for diagnosis by `Renderer::diagnostics`, whereas `Load` and `Script` may abort an operation.
+#[derive(Debug)]
+pub enum Error {
+    Html(html::ParseError),
+    Css(css::Diagnostic),
+    Load(LoadError),
+    Script(ScriptError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Html(e) => write!(f, "HTML parse error at {}", e),
+            Error::Css(e) => write!(f, "CSS parse error at {}", e),
+            Error::Load(e) => write!(f, "{}", e),
+            Error::Script(e) => write!(f, "uncaught exception at {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Load(LoadError::Io(_, e)) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<html::ParseError> for Error {
+    fn from(e: html::ParseError) -> Self {
+        Error::Html(e)
+    }
+}
+
+impl From<css::Diagnostic> for Error {
+    fn from(e: css::Diagnostic) -> Self {
+        Error::Css(e)
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        Error::Load(e)
+    }
+}
+
+impl From<ScriptError> for Error {
+    fn from(e: ScriptError) -> Self {
+        Error::Script(e)
+    }
+}
+
+/// `ScriptError` represents an exception thrown by a script and not caught, including a syntax error.
+/// The position is the one where the exception was thrown, or 0 if V8 does not know it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScriptError {
+    pub filename: String,
+    pub line: usize,
+    pub column: usize,
+    // 例外を文字列に変換したもの。例えば`ReferenceError: x is not defined`
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.filename, self.line, self.column, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{error::Error as _, io, path::PathBuf};
+
+    #[test]
+    fn test_display() {
+        let (_, errors) = html::parse_with_errors("<p a a>");
+        assert_eq!(Error::from(errors[0].clone()).to_string(), "HTML parse error at 1:7: duplicate-attribute");
+
+        let (_, diagnostics) = css::parse_with_diagnostics("p { color red; }");
+        assert_eq!(
+            Error::from(diagnostics[0].clone()).to_string(),
+            format!("CSS parse error at {}", diagnostics[0])
+        );
+
+        let script = ScriptError {
+            filename: "(inline)".to_string(),
+            line: 2,
+            column: 5,
+            message: "ReferenceError: x is not defined".to_string(),
+        };
+        assert_eq!(
+            Error::from(script).to_string(),
+            "uncaught exception at (inline):2:5: ReferenceError: x is not defined"
+        );
+
+        let e = Error::from(LoadError::Io(PathBuf::from("a.css"), io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(e.to_string().starts_with("failed to read `a.css`"), true);
+        assert_eq!(e.source().is_some(), true);
+    }
+}
@@ -0,0 +1,70 @@
+//! This module renders documents without a terminal, so that the output can be used in scripts and tests.
+
+use std::{rc::Rc, str::FromStr};
+
+use cursive::reexports::crossbeam_channel;
+
+use crate::{
+    css::{Stylesheet, Viewport},
+    dom::Arena,
+    error::Error,
+    html,
+    paint::canvas::Canvas,
+    renderer::Renderer,
+};
+
+/// `OutputFormat` is how a rendered page is printed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    // 色や装飾のないテキスト
+    Text,
+    // ANSIエスケープシーケンスで色付けしたテキスト
+    Ansi,
+    // セルの配列
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "ansi" => Ok(OutputFormat::Ansi),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format `{}`; expected `text`, `ansi` or `json`", s)),
+        }
+    }
+}
+
+/// `render` runs the inline scripts if `scripts` is set, and then style, layout and paint only once,
+/// so the result reflects the changes of the scripts to the DOM.
+/// The renderer is returned, whose canvas is as wide as the viewport and as tall as the page, up to a fixed number of rows,
+/// and whose `diagnostics` include `parse_errors`.
+/// An uncaught exception in the scripts is an error, as the page may not be rendered as intended.
+pub fn render(
+    document: Arena,
+    parse_errors: Vec<html::ParseError>,
+    user_stylesheet: Option<Stylesheet>,
+    viewport: Viewport,
+    scripts: bool,
+) -> Result<Renderer, Error> {
+    // 再描画の要求は受け取るだけで処理しない
+    let (ui_cb_sink, _ui_cb_receiver) = crossbeam_channel::unbounded();
+    let mut renderer = Renderer::new(Rc::new(ui_cb_sink), document, viewport, user_stylesheet)?;
+    renderer.set_parse_errors(parse_errors);
+    if scripts {
+        renderer.execute_inline_scripts()?;
+    }
+    renderer.rerender();
+    Ok(renderer)
+}
+
+/// `format` prints the canvas in the format. Every format but JSON ends with a newline.
+pub fn format(canvas: &Canvas, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => canvas.to_string(),
+        OutputFormat::Ansi => canvas.to_ansi(),
+        OutputFormat::Json => canvas.to_json(),
+    }
+}
@@ -1,274 +1,224 @@
-use crate::dom::{AttrMap, Element, Node, Text};
-use combine::{
-    attempt,
-    error::StreamError,
-    many,
-    parser::char::{newline, space},
-};
-use combine::{between, many1, parser, sep_by, Parser, Stream};
-use combine::{choice, error::ParseError};
-use combine::{
-    parser::char::{char, letter},
-    satisfy,
-};
-
-fn whitespaces<Input>() -> impl Parser<Input, Output = String>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    many::<String, _, _>(space().or(newline()))
-}
-
-// `nodes_` (and `nodes`) tries to parse input as Element or Text.
-fn nodes_<Input>() -> impl Parser<Input, Output = Vec<Box<Node>>>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    attempt(many(
-        choice((attempt(element()), attempt(text()))).skip(whitespaces()),
-    ))
+//! This module includes an HTML parser compliant to [the WHATWG HTML Living Standard](https://html.spec.whatwg.org/multipage/parsing.html).
+//!
+//! Parsing is done in two stages: `tokenizer` splits the input into tokens,
+//! and `tree_builder` constructs a DOM tree from them.
+
+use crate::dom::{Arena, NodeId};
+
+pub mod entities;
+pub mod tokenizer;
+pub mod tree_builder;
+pub use tokenizer::ParseError;
+use tree_builder::TreeBuilder;
+
+/// `parse` parses `raw` as a whole HTML document and returns an arena holding the document.
+/// The quirks mode of the document is decided by its DOCTYPE.
+pub fn parse(raw: &str) -> Arena {
+    parse_with_errors(raw).0
 }
 
-/// `text` consumes input until `<` comes.
-fn text<Input>() -> impl Parser<Input, Output = Box<Node>>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    many1(satisfy(|c: char| c != '<')).map(|t| Text::new(t))
+/// `parse_with_errors` parses `raw` as `parse` does, and also returns the parse errors the parser recovered from.
+pub fn parse_with_errors(raw: &str) -> (Arena, Vec<ParseError>) {
+    let mut builder = TreeBuilder::new(raw);
+    let arena = builder.run_document();
+    (arena, builder.errors().to_vec())
 }
 
-/// `element` consumes `<tag_name attr_name="attr_value" ...>(children)</tag_name>`.
-fn element<Input>() -> impl Parser<Input, Output = Box<Node>>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    (
-        open_tag().skip(whitespaces()),
-        nodes().skip(whitespaces()),
-        close_tag(),
-    )
-        .and_then(|((open_tag_name, attributes), children, close_tag_name)| {
-            if open_tag_name == close_tag_name {
-                Ok(Element::new(open_tag_name, attributes, children))
-            } else {
-                Err(<Input::Error as combine::error::ParseError<
-                    char,
-                    Input::Range,
-                    Input::Position,
-                >>::StreamError::message_static_message(
-                    "tag name of open tag and close tag mismatched",
-                ))
-            }
-        })
+/// `parse_raw` parses `raw` as an HTML fragment in the context of a `<body>` element.
+/// The resulting nodes are created in `arena` but not inserted into the tree.
+pub fn parse_raw(arena: &mut Arena, raw: &str) -> Vec<NodeId> {
+    parse_fragment(arena, raw, "body")
 }
 
-/// `open_tag` consumes `<tag_name attr_name="attr_value" ...>`.
-fn open_tag<Input>() -> impl Parser<Input, Output = (String, AttrMap)>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    let open_tag_name = many1::<String, _, _>(letter());
-    let open_tag_content = (
-        open_tag_name,
-        many::<String, _, _>(space().or(newline())),
-        attributes(),
-    )
-        .map(|v: (String, _, AttrMap)| (v.0, v.2));
-    between(char('<'), char('>'), open_tag_content)
-}
-
-/// close_tag consumes `</tag_name>`.
-fn close_tag<Input>() -> impl Parser<Input, Output = String>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    let close_tag_name = many1::<String, _, _>(letter());
-    let close_tag_content = (char('/'), close_tag_name).map(|v| v.1);
-    between(char('<'), char('>'), close_tag_content)
-}
-
-/// `attribute` consumes `name="value"`.
-fn attribute<Input>() -> impl Parser<Input, Output = (String, String)>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    let attribute_name = many1::<String, _, _>(letter());
-    let attribute_inner_value = many1::<String, _, _>(satisfy(|c: char| c != '"'));
-    let attribute_value = between(char('"'), char('"'), attribute_inner_value);
-    (
-        attribute_name,
-        many::<String, _, _>(space().or(newline())),
-        char('='),
-        many::<String, _, _>(space().or(newline())),
-        attribute_value,
-    )
-        .map(|v| (v.0, v.4))
-}
-
-/// `attributes` consumes `name1="value1" name2="value2" ... name="value"`
-fn attributes<Input>() -> impl Parser<Input, Output = AttrMap>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    sep_by::<Vec<(String, String)>, _, _, _>(
-        attribute(),
-        many::<String, _, _>(space().or(newline())),
-    )
-    .map(|attrs: Vec<(String, String)>| {
-        let m: AttrMap = attrs.into_iter().collect();
-        m
-    })
-}
-
-parser! {
-    fn nodes[Input]()(Input) -> Vec<Box<Node>>
-    where [Input: Stream<Token = char>]
-    {
-        nodes_()
-    }
-}
-
-pub fn parse(raw: &str) -> Box<Node> {
-    let mut nodes = parse_raw(raw);
-    if nodes.len() == 1 {
-        nodes.pop().unwrap()
-    } else {
-        Element::new("html".to_string(), AttrMap::new(), nodes)
-    }
-}
-
-pub fn parse_raw(raw: &str) -> Vec<Box<Node>> {
-    let (nodes, _) = nodes().parse(raw).unwrap();
-    nodes
+/// `parse_fragment` parses `raw` as an HTML fragment in the context of an element named `context`.
+/// This is used by `innerHTML`; e.g. the content of `<textarea>` is not parsed as markup.
+pub fn parse_fragment(arena: &mut Arena, raw: &str, context: &str) -> Vec<NodeId> {
+    TreeBuilder::new_fragment(raw, context).run(arena)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dom::{NodeType, QuirksMode};
+
+    /// `fragment` parses `raw` in the context of `context` and serializes the result.
+    fn fragment(raw: &str, context: &str) -> String {
+        let mut arena = Arena::new();
+        parse_fragment(&mut arena, raw, context)
+            .into_iter()
+            .map(|node| arena.outer_html(node))
+            .collect()
+    }
 
-    // parsing tests of attributes
     #[test]
-    fn test_parse_attribute() {
+    fn test_parse_document() {
+        let arena = parse("<!DOCTYPE html><title>a</title><p>hello</p>");
+        let document = arena.document();
         assert_eq!(
-            attribute().parse("test=\"foobar\""),
-            Ok((("test".to_string(), "foobar".to_string()), ""))
+            arena.outer_html(document),
+            "<!DOCTYPE html><html><head><title>a</title></head><body><p>hello</p></body></html>"
         );
 
-        assert_eq!(
-            attribute().parse("test = \"foobar\""),
-            Ok((("test".to_string(), "foobar".to_string()), ""))
-        )
+        let children = arena.children(document).collect::<Vec<_>>();
+        assert_eq!(children.len(), 2);
+        match arena[children[0]].node_type {
+            NodeType::DocumentType(ref doctype) => assert_eq!(doctype.name, "html"),
+            ref node_type => panic!("unexpected node: {:?}", node_type),
+        }
+        assert_eq!(arena.document_element(), Some(children[1]));
+        assert_eq!(arena[children[1]].parent(), Some(document));
+
+        // an empty document still has html, head and body
+        let arena = parse("");
+        assert_eq!(arena.outer_html(arena.document()), "<html><head></head><body></body></html>");
     }
 
     #[test]
-    fn test_parse_attributes() {
-        let mut expected_map = AttrMap::new();
-        expected_map.insert("test".to_string(), "foobar".to_string());
-        expected_map.insert("abc".to_string(), "def".to_string());
-        assert_eq!(
-            attributes().parse("test=\"foobar\" abc=\"def\""),
-            Ok((expected_map, ""))
-        );
+    fn test_parse_raw() {
+        let mut arena = Arena::new();
+        let nodes = parse_raw(&mut arena, r#"<div><p id="test">hello world</p></div>"#);
+        assert_eq!(nodes.len(), 1);
+        // the top-level nodes are not inserted yet
+        assert_eq!(arena[nodes[0]].parent(), None);
+
+        let p = arena[nodes[0]].first_child().unwrap();
+        assert_eq!(arena[p].parent(), Some(nodes[0]));
+        match arena[p].node_type {
+            NodeType::Element(ref el) => {
+                assert_eq!(el.tag_name, "p");
+                assert_eq!(el.id(), Some(&"test".to_string()));
+            }
+            ref node_type => panic!("unexpected node: {:?}", node_type),
+        }
+        assert_eq!(arena.inner_text(p), "hello world");
 
-        assert_eq!(attributes().parse(""), Ok((AttrMap::new(), "")))
+        // mismatched end tags are ignored instead of failing
+        assert_eq!(fragment("<p>hello world</div>", "body"), "<p>hello world</p>");
     }
 
     #[test]
-    fn test_parse_open_tag() {
-        {
-            assert_eq!(
-                open_tag().parse("<p>aaaa"),
-                Ok((("p".to_string(), AttrMap::new()), "aaaa"))
-            );
-        }
-        {
-            let mut attributes = AttrMap::new();
-            attributes.insert("id".to_string(), "test".to_string());
-            assert_eq!(
-                open_tag().parse("<p id=\"test\">"),
-                Ok((("p".to_string(), attributes), ""))
-            )
+    fn test_void_elements_and_attributes() {
+        let mut arena = Arena::new();
+        let nodes = parse_raw(&mut arena, r#"a<br>b<img src=a.png alt='x'><input disabled>"#);
+        assert_eq!(nodes.len(), 5);
+        match arena[nodes[3]].node_type {
+            NodeType::Element(ref el) => {
+                assert_eq!(el.tag_name, "img");
+                assert_eq!(el.attribute("src"), Some(&"a.png".to_string()));
+                assert_eq!(el.attribute("alt"), Some(&"x".to_string()));
+            }
+            ref node_type => panic!("unexpected node: {:?}", node_type),
         }
+        assert_eq!(arena.outer_html(nodes[4]), r#"<input disabled="">"#);
+    }
 
-        {
-            let result = open_tag().parse("<p id=\"test\" class=\"sample\">");
-            let mut attributes = AttrMap::new();
-            attributes.insert("id".to_string(), "test".to_string());
-            attributes.insert("class".to_string(), "sample".to_string());
-            assert_eq!(result, Ok((("p".to_string(), attributes), "")));
+    #[test]
+    fn test_comments() {
+        let mut arena = Arena::new();
+        let nodes = parse_raw(&mut arena, "<p>a<!-- comment -->b</p>");
+        let children = arena.children(nodes[0]).collect::<Vec<_>>();
+        assert_eq!(children.len(), 3);
+        match arena[children[1]].node_type {
+            NodeType::Comment(ref comment) => assert_eq!(comment.data, " comment "),
+            ref node_type => panic!("unexpected node: {:?}", node_type),
         }
 
-        {
-            assert!(open_tag().parse("<p id>").is_err());
-        }
+        // processing instructions are parsed as bogus comments
+        assert_eq!(fragment("<?xml version=\"1.0\"?>", "body"), "<!--?xml version=\"1.0\"?-->");
     }
 
-    // parsing tests of close tags
     #[test]
-    fn test_parse_close_tag() {
-        let result = close_tag().parse("</p>");
-        assert_eq!(result, Ok(("p".to_string(), "")))
+    fn test_document_round_trip() {
+        let html = "<!DOCTYPE html><!-- a --><html><head><title>t</title></head><body><p>x<!--b--></p></body></html><!--c-->";
+        let arena = parse(html);
+        assert_eq!(arena.outer_html(arena.document()), html);
+        assert_eq!(parse(&arena.outer_html(arena.document())), arena);
     }
 
     #[test]
-    fn test_parse_element() {
+    fn test_parse_with_errors() {
+        let (arena, errors) = parse_with_errors("<!DOCTYPE html>\n<p id=a id=b>x</p a>");
+        assert_eq!(arena, parse("<!DOCTYPE html>\n<p id=a id=b>x</p a>"));
         assert_eq!(
-            element().parse("<p></p>"),
-            Ok((Element::new("p".to_string(), AttrMap::new(), vec![]), ""))
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["2:13: duplicate-attribute", "2:20: end-tag-with-attributes"]
         );
+        assert_eq!(parse_with_errors("<!DOCTYPE html><p>ok</p>").1, vec![]);
+    }
 
+    #[test]
+    fn test_positions() {
+        let arena = parse("<!DOCTYPE html>\r\n<b class=x>a<p>b</b>c</p><!--d-->");
+        let position = |id: NodeId| arena[id].position().map(|p| (p.offset, p.to_string()));
+        let children = |id| arena.children(id).collect::<Vec<_>>();
+
+        let document = children(arena.document());
+        assert_eq!(position(document[0]), Some((0, "1:1".to_string())));
+        // elements implied by the parser have no position
+        let html = arena.document_element().unwrap();
+        let body = children(html)[1];
+        assert_eq!(position(html), None);
+        assert_eq!(position(body), None);
+
+        let body_children = children(body);
+        let (b, p) = (body_children[0], body_children[1]);
+        assert_eq!(position(b), Some((17, "2:1".to_string())));
+        match arena[b].node_type {
+            NodeType::Element(ref el) => {
+                assert_eq!(el.attribute_position("class").map(|p| p.to_string()), Some("2:4".to_string()));
+            },
+            ref node_type => panic!("unexpected node: {:?}", node_type),
+        }
+        assert_eq!(position(children(b)[0]), Some((28, "2:12".to_string())));
+        assert_eq!(position(p), Some((29, "2:13".to_string())));
+        // an element cloned by the adoption agency algorithm is located at the original
+        assert_eq!(position(children(p)[0]), Some((17, "2:1".to_string())));
+        assert_eq!(position(children(p)[1]), Some((37, "2:21".to_string())));
+        assert_eq!(position(body_children[2]), Some((42, "2:26".to_string())));
+
+        assert_eq!(format!("{:?}", arena[b]).contains("position: Some(SourcePosition { offset: 17, line: 2, column: 1 })"), true);
+    }
+
+    #[test]
+    fn test_quirks_mode() {
+        assert_eq!(parse("<!DOCTYPE html><p>").quirks_mode(), QuirksMode::NoQuirks);
+        assert_eq!(parse("<p>").quirks_mode(), QuirksMode::Quirks);
         assert_eq!(
-            element().parse("<p>hello world</p>"),
-            Ok((
-                Element::new(
-                    "p".to_string(),
-                    AttrMap::new(),
-                    vec![Text::new("hello world".to_string())]
-                ),
-                ""
-            ))
+            parse(r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd">"#)
+                .quirks_mode(),
+            QuirksMode::LimitedQuirks
         );
+    }
 
+    #[test]
+    fn test_implied_end_tags() {
+        assert_eq!(fragment("<p>one<p>two", "body"), "<p>one</p><p>two</p>");
+        assert_eq!(fragment("<ul><li>one<li>two</ul>", "body"), "<ul><li>one</li><li>two</li></ul>");
+        assert_eq!(fragment("<p>text<div>block</div>", "body"), "<p>text</p><div>block</div>");
+    }
+
+    #[test]
+    fn test_adoption_agency() {
+        assert_eq!(fragment("<b>1<p>2</b>3</p>", "body"), "<b>1</b><p><b>2</b>3</p>");
+        assert_eq!(fragment("<a>1<a>2</a>", "body"), "<a>1</a><a>2</a>");
+    }
+
+    #[test]
+    fn test_tables() {
+        // implied tbody and foster parenting
         assert_eq!(
-            element().parse("<div><p>hello world</p></div>"),
-            Ok((
-                Element::new(
-                    "div".to_string(),
-                    AttrMap::new(),
-                    vec![Element::new(
-                        "p".to_string(),
-                        AttrMap::new(),
-                        vec![Text::new("hello world".to_string())]
-                    )],
-                ),
-                ""
-            ))
+            fragment("<table>oops<tr><td>cell</table>", "body"),
+            "oops<table><tbody><tr><td>cell</td></tr></tbody></table>"
         );
-
-        assert!(element().parse("<p>hello world</div>").is_err());
     }
 
     #[test]
-    fn test_parse_text() {
-        {
-            assert_eq!(
-                text().parse("Hello World"),
-                Ok((Text::new("Hello World".to_string()), ""))
-            );
-        }
-        {
-            assert_eq!(
-                text().parse("Hello World<"),
-                Ok((Text::new("Hello World".to_string()), "<"))
-            );
-        }
+    fn test_raw_text_elements() {
+        assert_eq!(
+            fragment("<style>p > a { }</style><script>if (a < b) {}</script>", "body"),
+            "<style>p > a { }</style><script>if (a < b) {}</script>"
+        );
+        assert_eq!(fragment("<p>not a tag</p>", "textarea"), "&lt;p&gt;not a tag&lt;/p&gt;");
+        assert_eq!(fragment("<pre>\nline</pre>", "body"), "<pre>line</pre>");
     }
 }
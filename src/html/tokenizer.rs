@@ -0,0 +1,1887 @@
+//! This module implements the tokenization stage of the HTML parser.
+//!
+//! The state machine follows [the WHATWG HTML Living Standard](https://html.spec.whatwg.org/multipage/parsing.html#tokenization).
+//! States which are only reachable from foreign content (SVG / MathML), such as CDATA sections, are not implemented.
+
+use std::{collections::VecDeque, fmt};
+
+use crate::{
+    html::entities,
+    source::{LineIndex, SourcePosition},
+};
+
+/// `Token` represents a token emitted by `Tokenizer`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Character(char),
+    Eof,
+}
+
+/// `ParseError` represents a parse error at a position in the input.
+/// The parser recovers from every parse error, so it is only recorded for diagnosis.
+/// See https://html.spec.whatwg.org/multipage/parsing.html#parse-errors
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    // 仕様で定義されたエラーコード。例えば`duplicate-attribute`
+    pub code: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.code)
+    }
+}
+
+/// `Tag` represents a start tag or an end tag token.
+///
+/// Attributes are kept in source order; duplicated attributes are dropped by the tokenizer.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Tag {
+    pub tag_name: String,
+    pub attributes: Vec<(String, String)>,
+    pub self_closing: bool,
+}
+
+impl Tag {
+    pub fn new(tag_name: &str) -> Self {
+        Tag {
+            tag_name: tag_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// `Doctype` represents a DOCTYPE token.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
+}
+
+/// `State` is a state of the tokenizer.
+/// See https://html.spec.whatwg.org/multipage/parsing.html#tokenization for the definition of each state.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum State {
+    Data,
+    RcData,
+    RawText,
+    ScriptData,
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcDataLessThanSign,
+    RcDataEndTagOpen,
+    RcDataEndTagName,
+    RawTextLessThanSign,
+    RawTextEndTagOpen,
+    RawTextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+/// `Tokenizer` splits an HTML source into `Token`s.
+///
+/// The tree builder drives the tokenizer and switches its state (e.g. to `State::RawText` for `<style>`).
+pub struct Tokenizer {
+    input: Vec<char>,
+    pos: usize,
+    state: State,
+    return_state: State,
+
+    current_tag: Tag,
+    current_tag_is_end: bool,
+    current_attribute: Option<(String, String)>,
+    current_comment: String,
+    current_doctype: Doctype,
+    temporary_buffer: String,
+    character_reference_code: u32,
+    last_start_tag_name: Option<String>,
+
+    pending: VecDeque<(Token, TokenSource)>,
+    pub errors: Vec<ParseError>,
+
+    // 位置の計算のために、改行を正規化する前の入力と、各文字の元のバイトオフセットを持つ
+    source: String,
+    offsets: Vec<usize>,
+    lines: LineIndex,
+    // 生成中のトークンの開始位置と、その属性の開始位置 (文字単位)
+    token_start: usize,
+    attribute_starts: Vec<usize>,
+    current_attribute_start: usize,
+    // 直前に`next_token`が返したトークンの位置
+    last_source: TokenSource,
+}
+
+/// `TokenSource` records where a token and its attributes start, as indices into the input characters.
+#[derive(Debug, Default)]
+struct TokenSource {
+    start: usize,
+    attributes: Vec<usize>,
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+impl Tokenizer {
+    pub fn new(input: &str) -> Tokenizer {
+        // preprocess the input stream: normalize newlines
+        // https://html.spec.whatwg.org/multipage/parsing.html#preprocessing-the-input-stream
+        let mut chars = vec![];
+        let mut offsets = vec![];
+        let mut iter = input.char_indices().peekable();
+        while let Some((offset, c)) = iter.next() {
+            if c == '\r' && iter.peek().map(|&(_, next)| next) == Some('\n') {
+                continue;
+            }
+            chars.push(if c == '\r' { '\n' } else { c });
+            offsets.push(offset);
+        }
+        offsets.push(input.len());
+
+        Tokenizer {
+            input: chars,
+            pos: 0,
+            state: State::Data,
+            return_state: State::Data,
+            current_tag: Tag::default(),
+            current_tag_is_end: false,
+            current_attribute: None,
+            current_comment: String::new(),
+            current_doctype: Doctype::default(),
+            temporary_buffer: String::new(),
+            character_reference_code: 0,
+            last_start_tag_name: None,
+            pending: VecDeque::new(),
+            errors: vec![],
+            source: input.to_string(),
+            offsets,
+            lines: LineIndex::new(input),
+            token_start: 0,
+            attribute_starts: vec![],
+            current_attribute_start: 0,
+            last_source: TokenSource::default(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    /// `set_last_start_tag` is used by the fragment parsing algorithm,
+    /// where the end tag of the context element has to be recognized as an appropriate end tag.
+    pub fn set_last_start_tag(&mut self, tag_name: &str) {
+        self.last_start_tag_name = Some(tag_name.to_string());
+    }
+
+    pub fn next_token(&mut self) -> Token {
+        loop {
+            if let Some((token, source)) = self.pending.pop_front() {
+                self.last_source = source;
+                return token;
+            }
+            self.step();
+        }
+    }
+
+    /// `token_position` returns where the token last returned by `next_token` starts.
+    /// A character reference is located at its `&`, and a tag at its `<`.
+    pub fn token_position(&self) -> SourcePosition {
+        self.position(self.last_source.start)
+    }
+
+    /// `attribute_positions` returns where the names of the attributes of the tag last returned by `next_token` start,
+    /// in the same order as the attributes.
+    pub fn attribute_positions(&self) -> Vec<SourcePosition> {
+        self.last_source.attributes.iter().map(|&i| self.position(i)).collect()
+    }
+
+    /// `position` converts an index into the input characters into the position in the original input.
+    fn position(&self, index: usize) -> SourcePosition {
+        let offset = self.offsets[index.min(self.input.len())];
+        self.lines.position(&self.source, offset)
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        let c = self.input.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    fn reconsume_in(&mut self, state: State) {
+        self.pos -= 1;
+        self.state = state;
+    }
+
+    /// `consume_if_matches` checks whether the next input characters are `s`, and consumes them if so.
+    fn consume_if_matches(&mut self, s: &str, case_insensitive: bool) -> bool {
+        let len = s.chars().count();
+        if self.pos + len > self.input.len() {
+            return false;
+        }
+        let matched = self.input[self.pos..self.pos + len]
+            .iter()
+            .zip(s.chars())
+            .all(|(a, b)| {
+                if case_insensitive {
+                    a.eq_ignore_ascii_case(&b)
+                } else {
+                    *a == b
+                }
+            });
+        if matched {
+            self.pos += len;
+        }
+        matched
+    }
+
+    /// `error` records a parse error at the character consumed last.
+    fn error(&mut self, code: &'static str) {
+        let SourcePosition { line, column, .. } = self.position(self.pos.saturating_sub(1));
+        self.errors.push(ParseError { line, column, code });
+    }
+
+    fn emit(&mut self, token: Token) {
+        let attributes = match token {
+            Token::StartTag(_) | Token::EndTag(_) => std::mem::take(&mut self.attribute_starts),
+            _ => vec![],
+        };
+        let source = TokenSource {
+            start: self.token_start,
+            attributes,
+        };
+        self.pending.push_back((token, source));
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.emit(Token::Character(c));
+    }
+
+    fn emit_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.emit_char(c);
+        }
+    }
+
+    fn emit_eof(&mut self) {
+        self.emit(Token::Eof);
+    }
+
+    fn new_tag(&mut self, is_end: bool) {
+        self.current_tag = Tag::default();
+        self.current_tag_is_end = is_end;
+        self.current_attribute = None;
+        self.attribute_starts.clear();
+    }
+
+    /// `start_attribute` is called right after the first character of the attribute is consumed.
+    fn start_attribute(&mut self, name: String) {
+        self.finish_attribute();
+        self.current_attribute = Some((name, String::new()));
+        self.current_attribute_start = self.pos - 1;
+    }
+
+    fn finish_attribute(&mut self) {
+        if let Some((name, value)) = self.current_attribute.take() {
+            if self.current_tag.attributes.iter().any(|(key, _)| *key == name) {
+                self.error("duplicate-attribute");
+            } else {
+                self.current_tag.attributes.push((name, value));
+                self.attribute_starts.push(self.current_attribute_start);
+            }
+        }
+    }
+
+    fn push_attribute_name(&mut self, c: char) {
+        if let Some((ref mut name, _)) = self.current_attribute {
+            name.push(c);
+        }
+    }
+
+    fn push_attribute_value(&mut self, c: char) {
+        if let Some((_, ref mut value)) = self.current_attribute {
+            value.push(c);
+        }
+    }
+
+    fn emit_current_tag(&mut self) {
+        self.finish_attribute();
+        let tag = std::mem::take(&mut self.current_tag);
+        if self.current_tag_is_end {
+            if !tag.attributes.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            if tag.self_closing {
+                self.error("end-tag-with-trailing-solidus");
+            }
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag_name = Some(tag.tag_name.clone());
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    fn emit_current_comment(&mut self) {
+        let comment = std::mem::take(&mut self.current_comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_current_doctype(&mut self) {
+        let doctype = std::mem::take(&mut self.current_doctype);
+        self.emit(Token::Doctype(doctype));
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.last_start_tag_name.as_deref() == Some(self.current_tag.tag_name.as_str())
+    }
+
+    /// `end_tag_name` implements the shared logic of the "RCDATA end tag name state", the "RAWTEXT end tag name state"
+    /// and the "script data (escaped) end tag name state". `fallback` is the state to return to when the end tag is not appropriate.
+    fn end_tag_name(&mut self, c: Option<char>, fallback: State) {
+        match c {
+            Some(c) if is_whitespace(c) && self.is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName;
+            }
+            Some('/') if self.is_appropriate_end_tag() => {
+                self.state = State::SelfClosingStartTag;
+            }
+            Some('>') if self.is_appropriate_end_tag() => {
+                self.state = State::Data;
+                self.emit_current_tag();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.current_tag.tag_name.push(c.to_ascii_lowercase());
+                self.temporary_buffer.push(c);
+            }
+            _ => {
+                self.emit_str("</");
+                let buffer = std::mem::take(&mut self.temporary_buffer);
+                self.emit_str(&buffer);
+                self.reconsume_in(fallback);
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        if self.state == State::MarkupDeclarationOpen {
+            if self.consume_if_matches("--", false) {
+                self.current_comment = String::new();
+                self.state = State::CommentStart;
+            } else if self.consume_if_matches("DOCTYPE", true) {
+                self.state = State::Doctype;
+            } else if self.consume_if_matches("[CDATA[", false) {
+                // CDATA sections are only allowed in foreign content, which is not supported.
+                self.error("cdata-in-html-content");
+                self.current_comment = "[CDATA[".to_string();
+                self.state = State::BogusComment;
+            } else {
+                self.error("incorrectly-opened-comment");
+                self.current_comment = String::new();
+                self.state = State::BogusComment;
+            }
+            return;
+        }
+
+        // テキストの状態では、次の文字からトークンが始まる
+        // タグや文字参照のトークンは、それらを読み終えるまで`<`や`&`の位置を開始位置とする
+        if matches!(
+            self.state,
+            State::Data
+                | State::RcData
+                | State::RawText
+                | State::ScriptData
+                | State::PlainText
+                | State::ScriptDataEscaped
+                | State::ScriptDataDoubleEscaped
+        ) {
+            self.token_start = self.pos;
+        }
+
+        let c = self.consume();
+        match self.state {
+            State::Data => match c {
+                Some('&') => self.start_character_reference(),
+                Some('<') => self.state = State::TagOpen,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\0');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::RcData => match c {
+                Some('&') => self.start_character_reference(),
+                Some('<') => self.state = State::RcDataLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::RawText => match c {
+                Some('<') => self.state = State::RawTextLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match c {
+                Some('<') => self.state = State::ScriptDataLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::PlainText => match c {
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('?') => {
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.current_comment = String::new();
+                    self.reconsume_in(State::BogusComment);
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.emit_char('<');
+                    self.reconsume_in(State::Data);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = State::Data;
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.current_comment = String::new();
+                    self.reconsume_in(State::BogusComment);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+            },
+            State::TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_tag.tag_name.push('\u{FFFD}');
+                }
+                Some(c) => self.current_tag.tag_name.push(c.to_ascii_lowercase()),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::RcDataLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::RcDataEndTagOpen;
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::RcData);
+                }
+            },
+            State::RcDataEndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::RcDataEndTagName);
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume_in(State::RcData);
+                }
+            },
+            State::RcDataEndTagName => self.end_tag_name(c, State::RcData),
+            State::RawTextLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::RawTextEndTagOpen;
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::RawText);
+                }
+            },
+            State::RawTextEndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::RawTextEndTagName);
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume_in(State::RawText);
+                }
+            },
+            State::RawTextEndTagName => self.end_tag_name(c, State::RawText),
+            State::ScriptDataLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::ScriptDataEndTagOpen;
+                }
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.emit_str("<!");
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::ScriptDataEndTagName);
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume_in(State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagName => self.end_tag_name(c, State::ScriptData),
+            State::ScriptDataEscapeStart => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscapeStartDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temporary_buffer = String::new();
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataDoubleEscapeStart);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::ScriptDataEscapedEndTagName);
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume_in(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagName => self.end_tag_name(c, State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.state = if self.temporary_buffer == "script" {
+                        State::ScriptDataDoubleEscaped
+                    } else {
+                        State::ScriptDataEscaped
+                    };
+                    self.emit_char(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temporary_buffer.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                }
+                _ => self.reconsume_in(State::ScriptDataEscaped),
+            },
+            State::ScriptDataDoubleEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.emit_char('/');
+                }
+                _ => self.reconsume_in(State::ScriptDataDoubleEscaped),
+            },
+            State::ScriptDataDoubleEscapeEnd => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.state = if self.temporary_buffer == "script" {
+                        State::ScriptDataEscaped
+                    } else {
+                        State::ScriptDataDoubleEscaped
+                    };
+                    self.emit_char(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temporary_buffer.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                }
+                _ => self.reconsume_in(State::ScriptDataDoubleEscaped),
+            },
+            State::BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') | Some('>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.start_attribute("=".to_string());
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attribute(String::new());
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if is_whitespace(c) => self.reconsume_in(State::AfterAttributeName),
+                Some('/') | Some('>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.push_attribute_name('\u{FFFD}');
+                }
+                Some(c) => {
+                    if c == '"' || c == '\'' || c == '<' {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.push_attribute_name(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                Some(_) => {
+                    self.start_attribute(String::new());
+                    self.reconsume_in(State::AttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                _ => self.reconsume_in(State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted => match c {
+                Some('"') => self.state = State::AfterAttributeValueQuoted,
+                Some('&') => self.start_character_reference(),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.push_attribute_value('\u{FFFD}');
+                }
+                Some(c) => self.push_attribute_value(c),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::AttributeValueSingleQuoted => match c {
+                Some('\'') => self.state = State::AfterAttributeValueQuoted,
+                Some('&') => self.start_character_reference(),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.push_attribute_value('\u{FFFD}');
+                }
+                Some(c) => self.push_attribute_value(c),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::AttributeValueUnquoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => self.start_character_reference(),
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.push_attribute_value('\u{FFFD}');
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error("unexpected-character-in-unquoted-attribute-value");
+                    }
+                    self.push_attribute_value(c);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                Some(_) => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.current_tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_current_tag();
+                }
+                Some(_) => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_comment();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_comment.push('\u{FFFD}');
+                }
+                Some(c) => self.current_comment.push(c),
+                None => {
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => unreachable!(),
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_current_comment();
+                }
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_current_comment();
+                }
+                Some(_) => {
+                    self.current_comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::Comment => match c {
+                Some('<') => {
+                    self.current_comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                }
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_comment.push('\u{FFFD}');
+                }
+                Some(c) => self.current_comment.push(c),
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentLessThanSign => match c {
+                Some('!') => {
+                    self.current_comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                }
+                Some('<') => self.current_comment.push('<'),
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentLessThanSignBang => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDash,
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentLessThanSignBangDash => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                _ => self.reconsume_in(State::CommentEndDash),
+            },
+            State::CommentLessThanSignBangDashDash => match c {
+                Some('>') | None => self.reconsume_in(State::CommentEnd),
+                Some(_) => {
+                    self.error("nested-comment");
+                    self.reconsume_in(State::CommentEnd);
+                }
+            },
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some(_) => {
+                    self.current_comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.current_comment.push('-'),
+                Some(_) => {
+                    self.current_comment.push_str("--");
+                    self.reconsume_in(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.current_comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = State::Data;
+                    self.emit_current_comment();
+                }
+                Some(_) => {
+                    self.current_comment.push_str("--!");
+                    self.reconsume_in(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_current_comment();
+                    self.emit_eof();
+                }
+            },
+            State::Doctype => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeName,
+                Some('>') => self.reconsume_in(State::BeforeDoctypeName),
+                Some(_) => {
+                    self.error("missing-whitespace-before-doctype-name");
+                    self.reconsume_in(State::BeforeDoctypeName);
+                }
+                None => {
+                    self.error("eof-in-doctype");
+                    self.current_doctype = Doctype {
+                        force_quirks: true,
+                        ..Default::default()
+                    };
+                    self.emit_current_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::BeforeDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_doctype = Doctype {
+                        name: Some('\u{FFFD}'.to_string()),
+                        ..Default::default()
+                    };
+                    self.state = State::DoctypeName;
+                }
+                Some('>') => {
+                    self.error("missing-doctype-name");
+                    self.current_doctype = Doctype {
+                        force_quirks: true,
+                        ..Default::default()
+                    };
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(c) => {
+                    self.current_doctype = Doctype {
+                        name: Some(c.to_ascii_lowercase().to_string()),
+                        ..Default::default()
+                    };
+                    self.state = State::DoctypeName;
+                }
+                None => {
+                    self.error("eof-in-doctype");
+                    self.current_doctype = Doctype {
+                        force_quirks: true,
+                        ..Default::default()
+                    };
+                    self.emit_current_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::DoctypeName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.push_doctype_name('\u{FFFD}');
+                }
+                Some(c) => self.push_doctype_name(c.to_ascii_lowercase()),
+                None => self.eof_in_doctype(),
+            },
+            State::AfterDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.pos -= 1;
+                    if self.consume_if_matches("PUBLIC", true) {
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if self.consume_if_matches("SYSTEM", true) {
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        self.error("invalid-character-sequence-after-doctype-name");
+                        self.current_doctype.force_quirks = true;
+                        self.state = State::BogusDoctype;
+                    }
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::AfterDoctypePublicKeyword => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypePublicIdentifier,
+                Some('"') => {
+                    self.error("missing-whitespace-after-doctype-public-keyword");
+                    self.current_doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.error("missing-whitespace-after-doctype-public-keyword");
+                    self.current_doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierSingleQuoted;
+                }
+                Some('>') => {
+                    self.error("missing-doctype-public-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-public-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::BeforeDoctypePublicIdentifier => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => {
+                    self.current_doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.current_doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierSingleQuoted;
+                }
+                Some('>') => {
+                    self.error("missing-doctype-public-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-public-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypePublicIdentifierDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterDoctypePublicIdentifier,
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.push_doctype_public_id('\u{FFFD}');
+                    }
+                    Some('>') => {
+                        self.error("abrupt-doctype-public-identifier");
+                        self.current_doctype.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_current_doctype();
+                    }
+                    Some(c) => self.push_doctype_public_id(c),
+                    None => self.eof_in_doctype(),
+                }
+            }
+            State::AfterDoctypePublicIdentifier => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BetweenDoctypePublicAndSystemIdentifiers,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some('"') => {
+                    self.error("missing-whitespace-between-doctype-public-and-system-identifiers");
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.error("missing-whitespace-between-doctype-public-and-system-identifiers");
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some('"') => {
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::AfterDoctypeSystemKeyword => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeSystemIdentifier,
+                Some('"') => {
+                    self.error("missing-whitespace-after-doctype-system-keyword");
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.error("missing-whitespace-after-doctype-system-keyword");
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                Some('>') => {
+                    self.error("missing-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::BeforeDoctypeSystemIdentifier => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => {
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.current_doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                Some('>') => {
+                    self.error("missing-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-system-identifier");
+                    self.current_doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::DoctypeSystemIdentifierDoubleQuoted | State::DoctypeSystemIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypeSystemIdentifierDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterDoctypeSystemIdentifier,
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.push_doctype_system_id('\u{FFFD}');
+                    }
+                    Some('>') => {
+                        self.error("abrupt-doctype-system-identifier");
+                        self.current_doctype.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_current_doctype();
+                    }
+                    Some(c) => self.push_doctype_system_id(c),
+                    None => self.eof_in_doctype(),
+                }
+            }
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some(_) => {
+                    self.error("unexpected-character-after-doctype-system-identifier");
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.eof_in_doctype(),
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_current_doctype();
+                }
+                Some('\0') => self.error("unexpected-null-character"),
+                Some(_) => {}
+                None => {
+                    self.emit_current_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::CharacterReference => {
+                self.temporary_buffer = "&".to_string();
+                match c {
+                    Some(c) if c.is_ascii_alphanumeric() => self.reconsume_in(State::NamedCharacterReference),
+                    Some('#') => {
+                        self.temporary_buffer.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    _ => {
+                        self.flush_character_reference();
+                        let return_state = self.return_state;
+                        self.reconsume_in(return_state);
+                    }
+                }
+            }
+            State::NamedCharacterReference => {
+                self.pos -= 1;
+                self.named_character_reference();
+            }
+            State::AmbiguousAmpersand => match c {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.is_in_attribute() {
+                        self.push_attribute_value(c);
+                    } else {
+                        self.emit_char(c);
+                    }
+                }
+                Some(';') => {
+                    self.error("unknown-named-character-reference");
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+                _ => {
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+            },
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                match c {
+                    Some(c) if c == 'x' || c == 'X' => {
+                        self.temporary_buffer.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    _ => self.reconsume_in(State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_hexdigit() => self.reconsume_in(State::HexadecimalCharacterReference),
+                _ => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+            },
+            State::DecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_digit() => self.reconsume_in(State::DecimalCharacterReference),
+                _ => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+            },
+            State::HexadecimalCharacterReference => match c {
+                Some(c) if c.is_ascii_hexdigit() => self.push_character_reference_digit(c, 16),
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => {
+                    self.error("missing-semicolon-after-character-reference");
+                    self.reconsume_in(State::NumericCharacterReferenceEnd);
+                }
+            },
+            State::DecimalCharacterReference => match c {
+                Some(c) if c.is_ascii_digit() => self.push_character_reference_digit(c, 10),
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => {
+                    self.error("missing-semicolon-after-character-reference");
+                    self.reconsume_in(State::NumericCharacterReferenceEnd);
+                }
+            },
+            State::NumericCharacterReferenceEnd => {
+                self.pos -= 1;
+                let c = self.numeric_character_reference_end();
+                self.temporary_buffer = c.to_string();
+                self.flush_character_reference();
+                self.state = self.return_state;
+            }
+        }
+    }
+
+    fn start_character_reference(&mut self) {
+        self.return_state = self.state;
+        self.state = State::CharacterReference;
+    }
+
+    fn is_in_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted | State::AttributeValueUnquoted
+        )
+    }
+
+    /// `flush_character_reference` implements "flush code points consumed as a character reference".
+    fn flush_character_reference(&mut self) {
+        let buffer = std::mem::take(&mut self.temporary_buffer);
+        if self.is_in_attribute() {
+            for c in buffer.chars() {
+                self.push_attribute_value(c);
+            }
+        } else {
+            self.emit_str(&buffer);
+        }
+    }
+
+    /// `named_character_reference` consumes the longest name in the named character references table.
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#named-character-reference-state
+    fn named_character_reference(&mut self) {
+        const MAX_NAME_LENGTH: usize = 32;
+        let candidate: String = self.input[self.pos..]
+            .iter()
+            .take(MAX_NAME_LENGTH)
+            .enumerate()
+            .take_while(|(i, c)| c.is_ascii_alphanumeric() || (**c == ';' && *i > 0))
+            .map(|(_, c)| *c)
+            .collect();
+        let candidate = match candidate.find(';') {
+            Some(i) => &candidate[..=i],
+            None => candidate.as_str(),
+        };
+        let matched = (1..=candidate.len())
+            .rev()
+            .find_map(|len| entities::lookup(&candidate[..len]).map(|chars| (&candidate[..len], chars)));
+
+        match matched {
+            Some((name, chars)) => {
+                self.pos += name.len();
+                self.temporary_buffer.push_str(name);
+                let next = self.input.get(self.pos).copied();
+                let historical = self.is_in_attribute()
+                    && !name.ends_with(';')
+                    && next.map(|c| c == '=' || c.is_ascii_alphanumeric()).unwrap_or(false);
+                if !historical {
+                    if !name.ends_with(';') {
+                        self.error("missing-semicolon-after-character-reference");
+                    }
+                    self.temporary_buffer = chars.to_string();
+                }
+                self.flush_character_reference();
+                self.state = self.return_state;
+            }
+            None => {
+                self.flush_character_reference();
+                self.state = State::AmbiguousAmpersand;
+            }
+        }
+    }
+
+    fn push_character_reference_digit(&mut self, c: char, radix: u32) {
+        let digit = c.to_digit(radix).unwrap();
+        // saturate to keep the value out of the range of Unicode without overflowing
+        self.character_reference_code = self
+            .character_reference_code
+            .saturating_mul(radix)
+            .saturating_add(digit)
+            .min(0x110000);
+    }
+
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+    fn numeric_character_reference_end(&mut self) -> char {
+        let code = self.character_reference_code;
+        match code {
+            0 => {
+                self.error("null-character-reference");
+                return '\u{FFFD}';
+            }
+            0x110000.. => {
+                self.error("character-reference-outside-unicode-range");
+                return '\u{FFFD}';
+            }
+            0xD800..=0xDFFF => {
+                self.error("surrogate-character-reference");
+                return '\u{FFFD}';
+            }
+            0xFDD0..=0xFDEF => self.error("noncharacter-character-reference"),
+            _ if code & 0xFFFE == 0xFFFE => self.error("noncharacter-character-reference"),
+            0x0D | 0x80..=0x9F => self.error("control-character-reference"),
+            _ if code < 0x20 && !matches!(code, 0x09 | 0x0A | 0x0C) || (0x7F..0xA0).contains(&code) => {
+                self.error("control-character-reference")
+            }
+            _ => {}
+        }
+        let replaced = match code {
+            0x80 => 0x20AC,
+            0x82 => 0x201A,
+            0x83 => 0x0192,
+            0x84 => 0x201E,
+            0x85 => 0x2026,
+            0x86 => 0x2020,
+            0x87 => 0x2021,
+            0x88 => 0x02C6,
+            0x89 => 0x2030,
+            0x8A => 0x0160,
+            0x8B => 0x2039,
+            0x8C => 0x0152,
+            0x8E => 0x017D,
+            0x91 => 0x2018,
+            0x92 => 0x2019,
+            0x93 => 0x201C,
+            0x94 => 0x201D,
+            0x95 => 0x2022,
+            0x96 => 0x2013,
+            0x97 => 0x2014,
+            0x98 => 0x02DC,
+            0x99 => 0x2122,
+            0x9A => 0x0161,
+            0x9B => 0x203A,
+            0x9C => 0x0153,
+            0x9E => 0x017E,
+            0x9F => 0x0178,
+            code => code,
+        };
+        char::from_u32(replaced).unwrap_or('\u{FFFD}')
+    }
+
+    fn eof_in_doctype(&mut self) {
+        self.error("eof-in-doctype");
+        self.current_doctype.force_quirks = true;
+        self.emit_current_doctype();
+        self.emit_eof();
+    }
+
+    fn push_doctype_name(&mut self, c: char) {
+        self.current_doctype.name.get_or_insert_with(String::new).push(c);
+    }
+
+    fn push_doctype_public_id(&mut self, c: char) {
+        self.current_doctype.public_id.get_or_insert_with(String::new).push(c);
+    }
+
+    fn push_doctype_system_id(&mut self, c: char) {
+        self.current_doctype.system_id.get_or_insert_with(String::new).push(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(input: &str) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new(input);
+        let mut tokens = vec![];
+        loop {
+            match tokenizer.next_token() {
+                Token::Eof => break,
+                token => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let mut tokenizer = Tokenizer::new("<p\r\n  id=a hidden>&amp;b</p>");
+        let mut next = || {
+            let token = tokenizer.next_token();
+            let position = tokenizer.token_position();
+            let attributes = tokenizer
+                .attribute_positions()
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+            (token, position.offset, position.to_string(), attributes)
+        };
+        assert_eq!(
+            next(),
+            (
+                start_tag("p", &[("id", "a"), ("hidden", "")], false),
+                0,
+                "1:1".to_string(),
+                vec!["2:3".to_string(), "2:8".to_string()]
+            )
+        );
+        // a character reference is located at `&`
+        assert_eq!(next(), (Token::Character('&'), 18, "2:15".to_string(), vec![]));
+        assert_eq!(next(), (Token::Character('b'), 23, "2:20".to_string(), vec![]));
+        assert_eq!(next().1, 24);
+        assert_eq!(next().1, 28);
+    }
+
+    fn start_tag(tag_name: &str, attributes: &[(&str, &str)], self_closing: bool) -> Token {
+        Token::StartTag(Tag {
+            tag_name: tag_name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            self_closing,
+        })
+    }
+
+    fn end_tag(tag_name: &str) -> Token {
+        Token::EndTag(Tag::new(tag_name))
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(
+            tokenize("<P>a</p>"),
+            vec![start_tag("p", &[], false), Token::Character('a'), end_tag("p")]
+        );
+        assert_eq!(tokenize("<br/>"), vec![start_tag("br", &[], true)]);
+        assert_eq!(tokenize("a<"), vec![Token::Character('a'), Token::Character('<')]);
+        assert_eq!(tokenize("</>"), vec![]);
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            tokenize(r#"<a href="x" target='_blank' data-x=1 hidden>"#),
+            vec![start_tag(
+                "a",
+                &[("href", "x"), ("target", "_blank"), ("data-x", "1"), ("hidden", "")],
+                false
+            )]
+        );
+
+        // the first attribute wins
+        let mut tokenizer = Tokenizer::new(r#"<p id="a" ID="b">"#);
+        assert_eq!(tokenizer.next_token(), start_tag("p", &[("id", "a")], false));
+        assert_eq!(
+            tokenizer.errors,
+            vec![ParseError {
+                line: 1,
+                column: 17,
+                code: "duplicate-attribute"
+            }]
+        );
+
+        // positions are counted in characters, after newlines are normalized
+        let mut tokenizer = Tokenizer::new("<p>\r\n日本<p a=1 a=2>\r<p>\0");
+        while tokenizer.next_token() != Token::Eof {}
+        assert_eq!(
+            tokenizer.errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["2:13: duplicate-attribute", "3:4: unexpected-null-character"]
+        );
+
+        // unquoted value followed by the end of a tag
+        assert_eq!(
+            tokenize("<img src=a.png/>"),
+            vec![start_tag("img", &[("src", "a.png/")], false)]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(tokenize("<!-- hello -->"), vec![Token::Comment(" hello ".into())]);
+        assert_eq!(tokenize("<!---->"), vec![Token::Comment("".into())]);
+        assert_eq!(tokenize("<!-->"), vec![Token::Comment("".into())]);
+        assert_eq!(tokenize("<!-- a -- b -->"), vec![Token::Comment(" a -- b ".into())]);
+        assert_eq!(tokenize("<?xml version?>"), vec![Token::Comment("?xml version?".into())]);
+        assert_eq!(tokenize("<!-- unclosed"), vec![Token::Comment(" unclosed".into())]);
+    }
+
+    #[test]
+    fn test_doctype() {
+        assert_eq!(
+            tokenize("<!DOCTYPE html>"),
+            vec![Token::Doctype(Doctype {
+                name: Some("html".into()),
+                ..Default::default()
+            })]
+        );
+        assert_eq!(
+            tokenize(r#"<!doctype HTML PUBLIC "-//W3C//DTD HTML 4.01//EN" 'http://www.w3.org/TR/html4/strict.dtd'>"#),
+            vec![Token::Doctype(Doctype {
+                name: Some("html".into()),
+                public_id: Some("-//W3C//DTD HTML 4.01//EN".into()),
+                system_id: Some("http://www.w3.org/TR/html4/strict.dtd".into()),
+                force_quirks: false,
+            })]
+        );
+        assert_eq!(
+            tokenize("<!DOCTYPE>"),
+            vec![Token::Doctype(Doctype {
+                force_quirks: true,
+                ..Default::default()
+            })]
+        );
+    }
+
+    #[test]
+    fn test_raw_text() {
+        let mut tokenizer = Tokenizer::new("<style>a < b</p></style>");
+        assert_eq!(tokenizer.next_token(), start_tag("style", &[], false));
+        tokenizer.set_state(State::RawText);
+        let mut text = String::new();
+        loop {
+            match tokenizer.next_token() {
+                Token::Character(c) => text.push(c),
+                token => {
+                    assert_eq!(token, end_tag("style"));
+                    break;
+                }
+            }
+        }
+        assert_eq!(text, "a < b</p>");
+    }
+
+    #[test]
+    fn test_script_data_escaped() {
+        let mut tokenizer = Tokenizer::new("<script><!--<script></script>--></script>");
+        assert_eq!(tokenizer.next_token(), start_tag("script", &[], false));
+        tokenizer.set_state(State::ScriptData);
+        let mut text = String::new();
+        loop {
+            match tokenizer.next_token() {
+                Token::Character(c) => text.push(c),
+                token => {
+                    assert_eq!(token, end_tag("script"));
+                    break;
+                }
+            }
+        }
+        assert_eq!(text, "<!--<script></script>-->");
+    }
+
+    fn text(input: &str) -> String {
+        tokenize(input)
+            .into_iter()
+            .map(|token| match token {
+                Token::Character(c) => c,
+                token => panic!("unexpected token: {:?}", token),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_character_references_in_text() {
+        assert_eq!(text("&amp;&lt;&gt;&quot;&nbsp;"), "&<>\"\u{a0}");
+        assert_eq!(text("&#60;&#x3c;&#X3C;&#x1F600;"), "<<<\u{1F600}");
+        // legacy forms without a semicolon
+        assert_eq!(text("&amp &copy2020 &notit;"), "& \u{a9}2020 \u{ac}it;");
+        // multiple code points
+        assert_eq!(text("&NotEqualTilde;"), "\u{2242}\u{338}");
+        // not a character reference
+        assert_eq!(text("& &; &unknown; &#; &#x;"), "& &; &unknown; &#; &#x;");
+        // numeric references with replacement
+        assert_eq!(text("&#0;&#x80;&#xD800;&#x110000;&#65"), "\u{FFFD}\u{20AC}\u{FFFD}\u{FFFD}A");
+    }
+
+    #[test]
+    fn test_character_references_in_attributes() {
+        assert_eq!(
+            tokenize(r#"<a href="?a=1&amp;b=2&copy=3&copy;" title='&lt;x&gt;' alt=&quot;>"#),
+            vec![start_tag(
+                "a",
+                &[("href", "?a=1&b=2&copy=3\u{a9}"), ("title", "<x>"), ("alt", "\"")],
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn test_character_references_in_raw_text() {
+        let mut tokenizer = Tokenizer::new("&amp;");
+        tokenizer.set_state(State::RawText);
+        assert_eq!(tokenizer.next_token(), Token::Character('&'));
+
+        let mut tokenizer = Tokenizer::new("&amp;");
+        tokenizer.set_state(State::RcData);
+        assert_eq!(tokenizer.next_token(), Token::Character('&'));
+        assert_eq!(tokenizer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_newline_normalization() {
+        assert_eq!(
+            tokenize("a\r\nb\rc"),
+            "a\nb\nc".chars().map(Token::Character).collect::<Vec<_>>()
+        );
+    }
+}
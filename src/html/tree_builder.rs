@@ -0,0 +1,2002 @@
+//! This module implements the tree construction stage of the HTML parser.
+//!
+//! The algorithm follows [the WHATWG HTML Living Standard](https://html.spec.whatwg.org/multipage/parsing.html#tree-construction),
+//! including insertion modes, implied end tags, the adoption agency algorithm and foster parenting.
+//! For simplicity, the following parts are not supported:
+//! - foreign content; `<svg>` and `<math>` are treated as ordinary HTML elements
+//! - template contents as a separated document fragment; children of `<template>` are appended to the element itself
+//! - form owners and script execution during parsing
+
+use crate::dom::{Arena, AttrMap, NodeId, NodeType, QuirksMode};
+use crate::html::tokenizer::{Doctype, ParseError, State, Tag, Token, Tokenizer};
+use crate::source::SourcePosition;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// `NodeKind` is the payload of a node under construction.
+#[derive(Debug)]
+enum NodeKind {
+    Document,
+    Doctype(Doctype),
+    Element(Tag),
+    Text(String),
+    Comment(String),
+}
+
+/// `TreeNode` is a node under construction.
+/// Nodes are stored in an arena so that the stack of open elements can point into the tree.
+#[derive(Debug)]
+struct TreeNode {
+    kind: NodeKind,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // 入力中の位置。暗黙に補われた要素はNone
+    position: Option<SourcePosition>,
+    attribute_positions: Vec<(String, SourcePosition)>,
+}
+
+#[derive(Debug, Clone)]
+enum FormattingEntry {
+    Marker,
+    Element(usize, Tag),
+}
+
+const DOCUMENT: usize = 0;
+
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote", "body", "br",
+    "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div", "dl", "dt", "embed",
+    "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6",
+    "head", "header", "hgroup", "hr", "html", "iframe", "img", "input", "keygen", "li", "link", "listing", "main",
+    "marquee", "menu", "meta", "nav", "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext",
+    "pre", "script", "search", "section", "select", "source", "style", "summary", "table", "tbody", "td",
+    "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp",
+];
+
+const FORMATTING_ELEMENTS: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+const IMPLIED_END_TAGS: &[&str] = &["dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc"];
+
+const IMPLIED_END_TAGS_THOROUGHLY: &[&str] = &[
+    "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody", "td",
+    "tfoot", "th", "thead", "tr",
+];
+
+const DEFAULT_SCOPE: &[&str] = &["applet", "caption", "html", "table", "td", "th", "marquee", "object", "template"];
+
+const QUIRKY_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19970916::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// `quirks_mode_of` decides the quirks mode of a document from its DOCTYPE.
+/// See https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+pub fn quirks_mode_of(doctype: &Doctype) -> QuirksMode {
+    let public_id = doctype.public_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let system_id = doctype.system_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let public_starts_with = |prefix: &str| public_id.as_ref().map(|id| id.starts_with(prefix)).unwrap_or(false);
+
+    if doctype.force_quirks
+        || doctype.name.as_deref() != Some("html")
+        || matches!(
+            public_id.as_deref(),
+            Some("-//w3o//dtd w3 html strict 3.0//en//") | Some("-/w3c/dtd html 4.0 transitional/en") | Some("html")
+        )
+        || system_id.as_deref() == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || QUIRKY_PUBLIC_ID_PREFIXES.iter().any(|prefix| public_starts_with(prefix))
+        || (system_id.is_none()
+            && (public_starts_with("-//w3c//dtd html 4.01 frameset//")
+                || public_starts_with("-//w3c//dtd html 4.01 transitional//")))
+    {
+        QuirksMode::Quirks
+    } else if public_starts_with("-//w3c//dtd xhtml 1.0 frameset//")
+        || public_starts_with("-//w3c//dtd xhtml 1.0 transitional//")
+        || (system_id.is_some()
+            && (public_starts_with("-//w3c//dtd html 4.01 frameset//")
+                || public_starts_with("-//w3c//dtd html 4.01 transitional//")))
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+/// `TreeBuilder` consumes tokens from `Tokenizer` and constructs a DOM tree.
+pub struct TreeBuilder {
+    tokenizer: Tokenizer,
+    nodes: Vec<TreeNode>,
+
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    template_modes: Vec<InsertionMode>,
+    open_elements: Vec<usize>,
+    active_formatting_elements: Vec<FormattingEntry>,
+    head: Option<usize>,
+    form: Option<usize>,
+    context: Option<usize>,
+
+    frameset_ok: bool,
+    foster_parenting: bool,
+    ignore_next_line_feed: bool,
+    pending_table_characters: Vec<char>,
+    pub quirks_mode: QuirksMode,
+    stopped: bool,
+
+    // 処理中のトークンの位置と、それが開始タグの場合はそのタグ名
+    token_position: Option<SourcePosition>,
+    start_tag_name: Option<String>,
+}
+
+impl TreeBuilder {
+    pub fn new(input: &str) -> TreeBuilder {
+        TreeBuilder {
+            tokenizer: Tokenizer::new(input),
+            nodes: vec![TreeNode {
+                kind: NodeKind::Document,
+                parent: None,
+                children: vec![],
+                position: None,
+                attribute_positions: vec![],
+            }],
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            template_modes: vec![],
+            open_elements: vec![],
+            active_formatting_elements: vec![],
+            head: None,
+            form: None,
+            context: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            ignore_next_line_feed: false,
+            pending_table_characters: vec![],
+            quirks_mode: QuirksMode::NoQuirks,
+            stopped: false,
+            token_position: None,
+            start_tag_name: None,
+        }
+    }
+
+    /// `new_fragment` prepares the HTML fragment parsing algorithm with a context element named `context`.
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+    pub fn new_fragment(input: &str, context: &str) -> TreeBuilder {
+        let mut builder = TreeBuilder::new(input);
+
+        let context_tag = Tag::new(context);
+        let context_node = builder.create_node(NodeKind::Element(context_tag));
+        builder.context = Some(context_node);
+
+        builder.tokenizer.set_state(match context {
+            "title" | "textarea" => State::RcData,
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" | "noscript" => State::RawText,
+            "script" => State::ScriptData,
+            "plaintext" => State::PlainText,
+            _ => State::Data,
+        });
+        builder.tokenizer.set_last_start_tag(context);
+
+        let root = builder.create_node(NodeKind::Element(Tag::new("html")));
+        builder.append(DOCUMENT, root);
+        builder.open_elements.push(root);
+        if context == "template" {
+            builder.template_modes.push(InsertionMode::InTemplate);
+        }
+        builder.reset_insertion_mode();
+        builder
+    }
+
+    /// `run` parses the whole input and creates the top-level nodes in `arena`.
+    /// The returned nodes are not inserted into the tree yet.
+    /// For the fragment case, it returns the children of the root `html` element instead.
+    pub fn run(&mut self, arena: &mut Arena) -> Vec<NodeId> {
+        self.build();
+
+        let top_level = if self.context.is_some() {
+            self.nodes[DOCUMENT].children[0]
+        } else {
+            DOCUMENT
+        };
+        self.to_dom_nodes(arena, &self.nodes[top_level].children)
+    }
+
+    /// `run_document` parses the whole input and returns a new arena holding the document.
+    pub fn run_document(&mut self) -> Arena {
+        self.build();
+
+        let mut arena = Arena::new();
+        let document = arena.document();
+        if let NodeType::Document(ref mut doc) = arena[document].node_type {
+            doc.quirks_mode = self.quirks_mode;
+        }
+        for node in self.to_dom_nodes(&mut arena, &self.nodes[DOCUMENT].children) {
+            arena
+                .append_child(document, node)
+                .expect("the tree builder should construct a valid tree");
+        }
+        arena
+    }
+
+    /// `errors` returns the parse errors found so far.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.tokenizer.errors
+    }
+
+    fn build(&mut self) {
+        while !self.stopped {
+            let token = self.tokenizer.next_token();
+            self.token_position = Some(self.tokenizer.token_position());
+            self.start_tag_name = match token {
+                Token::StartTag(ref tag) => Some(tag.tag_name.clone()),
+                _ => None,
+            };
+            if self.ignore_next_line_feed {
+                self.ignore_next_line_feed = false;
+                if token == Token::Character('\n') {
+                    continue;
+                }
+            }
+            self.process(token);
+        }
+    }
+
+    /// `to_dom_nodes` creates `dom::Node`s in `arena` from nodes under construction.
+    fn to_dom_nodes(&self, arena: &mut Arena, ids: &[usize]) -> Vec<NodeId> {
+        let mut nodes: Vec<NodeId> = vec![];
+        for &id in ids {
+            match self.nodes[id].kind {
+                NodeKind::Element(ref tag) => {
+                    let attributes: AttrMap = tag.attributes.iter().cloned().collect();
+                    let element = arena.create_element(&tag.tag_name, attributes);
+                    arena.set_position(element, self.nodes[id].position);
+                    if let NodeType::Element(ref mut el) = arena[element].node_type {
+                        for (name, position) in &self.nodes[id].attribute_positions {
+                            el.set_attribute_position(name, *position);
+                        }
+                    }
+                    for child in self.to_dom_nodes(arena, &self.nodes[id].children) {
+                        arena
+                            .append_child(element, child)
+                            .expect("the tree builder should construct a valid tree");
+                    }
+                    nodes.push(element);
+                }
+                NodeKind::Text(ref data) => match nodes.last().map(|&node| &mut arena[node].node_type) {
+                    Some(NodeType::Text(text)) => text.data.push_str(data),
+                    _ => {
+                        let text = arena.create_text(data);
+                        arena.set_position(text, self.nodes[id].position);
+                        nodes.push(text);
+                    }
+                },
+                NodeKind::Doctype(ref doctype) => {
+                    let doctype = arena.create_document_type(
+                        doctype.name.as_deref().unwrap_or_default(),
+                        doctype.public_id.as_deref().unwrap_or_default(),
+                        doctype.system_id.as_deref().unwrap_or_default(),
+                    );
+                    arena.set_position(doctype, self.nodes[id].position);
+                    nodes.push(doctype);
+                }
+                NodeKind::Comment(ref data) => {
+                    let comment = arena.create_comment(data);
+                    arena.set_position(comment, self.nodes[id].position);
+                    nodes.push(comment);
+                }
+                NodeKind::Document => {}
+            }
+        }
+        nodes
+    }
+
+    // --- tree manipulation ---
+
+    /// `create_node` creates a node located at the current token.
+    /// An element is located only if it is created for the current start tag; otherwise it is implied by the parser.
+    fn create_node(&mut self, kind: NodeKind) -> usize {
+        let (position, attribute_positions) = match kind {
+            NodeKind::Element(ref tag) if self.start_tag_name.as_ref() != Some(&tag.tag_name) => (None, vec![]),
+            NodeKind::Element(ref tag) => {
+                let names = tag.attributes.iter().map(|(name, _)| name.clone());
+                (self.token_position, names.zip(self.tokenizer.attribute_positions()).collect())
+            }
+            _ => (self.token_position, vec![]),
+        };
+        self.nodes.push(TreeNode {
+            kind,
+            parent: None,
+            children: vec![],
+            position,
+            attribute_positions,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// `copy_position` locates a clone of an element, which is created by the adoption agency algorithm
+    /// or the reconstruction of the active formatting elements, at the original element.
+    fn copy_position(&mut self, from: usize, to: usize) {
+        self.nodes[to].position = self.nodes[from].position;
+        self.nodes[to].attribute_positions = self.nodes[from].attribute_positions.clone();
+    }
+
+    fn detach(&mut self, id: usize) {
+        if let Some(parent) = self.nodes[id].parent.take() {
+            self.nodes[parent].children.retain(|&child| child != id);
+        }
+    }
+
+    fn append(&mut self, parent: usize, child: usize) {
+        self.insert_at(parent, None, child);
+    }
+
+    fn insert_at(&mut self, parent: usize, before: Option<usize>, child: usize) {
+        self.detach(child);
+        let index = before
+            .and_then(|before| self.nodes[parent].children.iter().position(|&c| c == before))
+            .unwrap_or(self.nodes[parent].children.len());
+        self.nodes[parent].children.insert(index, child);
+        self.nodes[child].parent = Some(parent);
+    }
+
+    fn tag_name(&self, id: usize) -> &str {
+        match self.nodes[id].kind {
+            NodeKind::Element(ref tag) => tag.tag_name.as_str(),
+            _ => "",
+        }
+    }
+
+    fn is(&self, id: usize, names: &[&str]) -> bool {
+        names.contains(&self.tag_name(id))
+    }
+
+    fn current_node(&self) -> usize {
+        *self.open_elements.last().unwrap_or(&DOCUMENT)
+    }
+
+    fn current_is(&self, names: &[&str]) -> bool {
+        self.is(self.current_node(), names)
+    }
+
+    // --- insertion ---
+
+    /// `appropriate_place` returns the appropriate place for inserting a node as `(parent, before)`.
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+    fn appropriate_place(&self, override_target: Option<usize>) -> (usize, Option<usize>) {
+        let target = override_target.unwrap_or_else(|| self.current_node());
+        if self.foster_parenting && self.is(target, &["table", "tbody", "tfoot", "thead", "tr"]) {
+            let last_template = self.open_elements.iter().rposition(|&id| self.is(id, &["template"]));
+            let last_table = self.open_elements.iter().rposition(|&id| self.is(id, &["table"]));
+            match (last_template, last_table) {
+                (Some(template), table) if table.map(|table| template > table).unwrap_or(true) => {
+                    (self.open_elements[template], None)
+                }
+                (_, None) => (self.open_elements[0], None),
+                (_, Some(table)) => {
+                    let table_node = self.open_elements[table];
+                    match self.nodes[table_node].parent {
+                        Some(parent) => (parent, Some(table_node)),
+                        None => (self.open_elements[table - 1], None),
+                    }
+                }
+            }
+        } else {
+            (target, None)
+        }
+    }
+
+    fn insert_element(&mut self, tag: Tag) -> usize {
+        let (parent, before) = self.appropriate_place(None);
+        let id = self.create_node(NodeKind::Element(tag));
+        self.insert_at(parent, before, id);
+        self.open_elements.push(id);
+        id
+    }
+
+    fn insert_void_element(&mut self, tag: Tag) {
+        self.insert_element(tag);
+        self.open_elements.pop();
+    }
+
+    fn insert_character(&mut self, c: char) {
+        let (parent, before) = self.appropriate_place(None);
+        if parent == DOCUMENT {
+            return;
+        }
+        let previous = match before {
+            Some(before) => {
+                let index = self.nodes[parent].children.iter().position(|&id| id == before).unwrap();
+                index.checked_sub(1).map(|i| self.nodes[parent].children[i])
+            }
+            None => self.nodes[parent].children.last().copied(),
+        };
+        if let Some(previous) = previous {
+            if let NodeKind::Text(ref mut data) = self.nodes[previous].kind {
+                data.push(c);
+                return;
+            }
+        }
+        let id = self.create_node(NodeKind::Text(c.to_string()));
+        self.insert_at(parent, before, id);
+    }
+
+    fn insert_comment(&mut self, data: String, parent: Option<usize>) {
+        let (parent, before) = match parent {
+            Some(parent) => (parent, None),
+            None => self.appropriate_place(None),
+        };
+        let id = self.create_node(NodeKind::Comment(data));
+        self.insert_at(parent, before, id);
+    }
+
+    /// `parse_generic_text` implements the generic raw text / RCDATA element parsing algorithm.
+    fn parse_generic_text(&mut self, tag: Tag, state: State) {
+        self.insert_element(tag);
+        self.tokenizer.set_state(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // --- the stack of open elements ---
+
+    fn in_specific_scope(&self, names: &[&str], boundary: &[&str]) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            if self.is(id, names) {
+                return true;
+            }
+            if self.is(id, boundary) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn in_scope(&self, name: &str) -> bool {
+        self.in_specific_scope(&[name], DEFAULT_SCOPE)
+    }
+
+    fn in_list_item_scope(&self, name: &str) -> bool {
+        let boundary: Vec<&str> = DEFAULT_SCOPE.iter().copied().chain(["ol", "ul"]).collect();
+        self.in_specific_scope(&[name], &boundary)
+    }
+
+    fn in_button_scope(&self, name: &str) -> bool {
+        let boundary: Vec<&str> = DEFAULT_SCOPE.iter().copied().chain(["button"]).collect();
+        self.in_specific_scope(&[name], &boundary)
+    }
+
+    fn in_table_scope(&self, names: &[&str]) -> bool {
+        self.in_specific_scope(names, &["html", "table", "template"])
+    }
+
+    fn in_select_scope(&self, name: &str) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            if self.is(id, &[name]) {
+                return true;
+            }
+            if !self.is(id, &["optgroup", "option"]) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn has_open(&self, name: &str) -> bool {
+        self.open_elements.iter().any(|&id| self.is(id, &[name]))
+    }
+
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(id) = self.open_elements.pop() {
+            if self.is(id, names) {
+                break;
+            }
+        }
+    }
+
+    fn remove_from_open_elements(&mut self, node: usize) {
+        self.open_elements.retain(|&id| id != node);
+    }
+
+    fn generate_implied_end_tags_except(&mut self, except: Option<&str>) {
+        while IMPLIED_END_TAGS.contains(&self.tag_name(self.current_node()))
+            && Some(self.tag_name(self.current_node())) != except
+        {
+            self.open_elements.pop();
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self) {
+        self.generate_implied_end_tags_except(None);
+    }
+
+    fn generate_implied_end_tags_thoroughly(&mut self) {
+        while IMPLIED_END_TAGS_THOROUGHLY.contains(&self.tag_name(self.current_node())) {
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags_except(Some("p"));
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_element_if_in_button_scope(&mut self) {
+        if self.in_button_scope("p") {
+            self.close_p_element();
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while !self.current_is(names) {
+            self.open_elements.pop();
+        }
+    }
+
+    fn clear_stack_back_to_table_context(&mut self) {
+        self.clear_stack_back_to(&["table", "template", "html"]);
+    }
+
+    fn clear_stack_back_to_table_body_context(&mut self) {
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+    }
+
+    fn clear_stack_back_to_table_row_context(&mut self) {
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+    }
+
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#reset-the-insertion-mode-appropriately
+    fn reset_insertion_mode(&mut self) {
+        for (i, &id) in self.open_elements.iter().enumerate().rev() {
+            let last = i == 0;
+            let node = match self.context {
+                Some(context) if last => context,
+                _ => id,
+            };
+            let mode = match self.tag_name(node) {
+                "select" => {
+                    if !last {
+                        for &ancestor in self.open_elements[..i].iter().rev() {
+                            if self.is(ancestor, &["template"]) {
+                                break;
+                            }
+                            if self.is(ancestor, &["table"]) {
+                                self.mode = InsertionMode::InSelectInTable;
+                                return;
+                            }
+                        }
+                    }
+                    Some(InsertionMode::InSelect)
+                }
+                "td" | "th" if !last => Some(InsertionMode::InCell),
+                "tr" => Some(InsertionMode::InRow),
+                "tbody" | "thead" | "tfoot" => Some(InsertionMode::InTableBody),
+                "caption" => Some(InsertionMode::InCaption),
+                "colgroup" => Some(InsertionMode::InColumnGroup),
+                "table" => Some(InsertionMode::InTable),
+                "template" => self.template_modes.last().copied(),
+                "head" if !last => Some(InsertionMode::InHead),
+                "body" => Some(InsertionMode::InBody),
+                "frameset" => Some(InsertionMode::InFrameset),
+                "html" => Some(if self.head.is_none() {
+                    InsertionMode::BeforeHead
+                } else {
+                    InsertionMode::AfterHead
+                }),
+                _ if last => Some(InsertionMode::InBody),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                self.mode = mode;
+                return;
+            }
+        }
+    }
+
+    // --- the list of active formatting elements ---
+
+    fn push_active_formatting_element(&mut self, id: usize, tag: Tag) {
+        // Noah's Ark clause
+        let same: Vec<usize> = self
+            .active_formatting_elements
+            .iter()
+            .enumerate()
+            .rev()
+            .take_while(|(_, entry)| !matches!(entry, FormattingEntry::Marker))
+            .filter_map(|(i, entry)| match entry {
+                FormattingEntry::Element(_, t) if t.tag_name == tag.tag_name && attributes_equal(t, &tag) => Some(i),
+                _ => None,
+            })
+            .collect();
+        if same.len() >= 3 {
+            self.active_formatting_elements.remove(*same.last().unwrap());
+        }
+        self.active_formatting_elements.push(FormattingEntry::Element(id, tag));
+    }
+
+    fn formatting_position(&self, node: usize) -> Option<usize> {
+        self.active_formatting_elements
+            .iter()
+            .position(|entry| matches!(entry, FormattingEntry::Element(id, _) if *id == node))
+    }
+
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let is_open_or_marker = |builder: &Self, entry: &FormattingEntry| match entry {
+            FormattingEntry::Marker => true,
+            FormattingEntry::Element(id, _) => builder.open_elements.contains(id),
+        };
+        let last = match self.active_formatting_elements.last() {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+        if is_open_or_marker(self, &last) {
+            return;
+        }
+
+        let mut start = self.active_formatting_elements.len() - 1;
+        while start > 0 {
+            if is_open_or_marker(self, &self.active_formatting_elements[start - 1]) {
+                break;
+            }
+            start -= 1;
+        }
+
+        for i in start..self.active_formatting_elements.len() {
+            if let FormattingEntry::Element(original, tag) = self.active_formatting_elements[i].clone() {
+                let id = self.insert_element(tag.clone());
+                self.copy_position(original, id);
+                self.active_formatting_elements[i] = FormattingEntry::Element(id, tag);
+            }
+        }
+    }
+
+    fn clear_active_formatting_elements_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting_elements.pop() {
+            if let FormattingEntry::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    fn adoption_agency(&mut self, subject: &str) {
+        let current = self.current_node();
+        if self.tag_name(current) == subject && self.formatting_position(current).is_none() {
+            self.open_elements.pop();
+            return;
+        }
+
+        for _ in 0..8 {
+            let formatting_index = self
+                .active_formatting_elements
+                .iter()
+                .rposition(|entry| match entry {
+                    FormattingEntry::Marker => true,
+                    FormattingEntry::Element(_, tag) => tag.tag_name == subject,
+                })
+                .filter(|&i| !matches!(self.active_formatting_elements[i], FormattingEntry::Marker));
+            let (formatting_element, formatting_tag) = match formatting_index {
+                Some(i) => match self.active_formatting_elements[i].clone() {
+                    FormattingEntry::Element(id, tag) => (id, tag),
+                    FormattingEntry::Marker => unreachable!(),
+                },
+                None => {
+                    self.any_other_end_tag(subject);
+                    return;
+                }
+            };
+            let formatting_index = formatting_index.unwrap();
+
+            let stack_index = match self.open_elements.iter().position(|&id| id == formatting_element) {
+                Some(index) => index,
+                None => {
+                    self.active_formatting_elements.remove(formatting_index);
+                    return;
+                }
+            };
+            if !self.in_scope(subject) {
+                return;
+            }
+
+            let furthest_block = self.open_elements[stack_index + 1..]
+                .iter()
+                .position(|&id| self.is(id, SPECIAL_ELEMENTS))
+                .map(|i| stack_index + 1 + i);
+            let furthest_block_index = match furthest_block {
+                Some(index) => index,
+                None => {
+                    self.open_elements.truncate(stack_index);
+                    self.active_formatting_elements.remove(formatting_index);
+                    return;
+                }
+            };
+            let furthest_block = self.open_elements[furthest_block_index];
+            let common_ancestor = self.open_elements[stack_index - 1];
+            let mut bookmark = formatting_index;
+
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut position = self.formatting_position(node);
+                if inner_loop_counter > 3 {
+                    if let Some(p) = position {
+                        self.active_formatting_elements.remove(p);
+                        if p < bookmark {
+                            bookmark -= 1;
+                        }
+                        position = None;
+                    }
+                }
+                let position = match position {
+                    Some(p) => p,
+                    None => {
+                        self.open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+
+                let (original, tag) = match self.active_formatting_elements[position].clone() {
+                    FormattingEntry::Element(original, tag) => (original, tag),
+                    FormattingEntry::Marker => unreachable!(),
+                };
+                let new_node = self.create_node(NodeKind::Element(tag.clone()));
+                self.copy_position(original, new_node);
+                self.active_formatting_elements[position] = FormattingEntry::Element(new_node, tag);
+                self.open_elements[node_index] = new_node;
+                if last_node == furthest_block {
+                    bookmark = position + 1;
+                }
+                self.append(new_node, last_node);
+                last_node = new_node;
+            }
+
+            let (parent, before) = self.appropriate_place(Some(common_ancestor));
+            self.insert_at(parent, before, last_node);
+
+            let new_element = self.create_node(NodeKind::Element(formatting_tag.clone()));
+            self.copy_position(formatting_element, new_element);
+            let children = std::mem::take(&mut self.nodes[furthest_block].children);
+            for child in children {
+                self.nodes[child].parent = None;
+                self.append(new_element, child);
+            }
+            self.append(furthest_block, new_element);
+
+            let formatting_index = self.formatting_position(formatting_element).unwrap();
+            self.active_formatting_elements.remove(formatting_index);
+            if formatting_index < bookmark {
+                bookmark -= 1;
+            }
+            self.active_formatting_elements
+                .insert(bookmark, FormattingEntry::Element(new_element, formatting_tag));
+
+            self.remove_from_open_elements(formatting_element);
+            let furthest_block_index = self.open_elements.iter().position(|&id| id == furthest_block).unwrap();
+            self.open_elements.insert(furthest_block_index + 1, new_element);
+        }
+    }
+
+    fn any_other_end_tag(&mut self, tag_name: &str) {
+        for i in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[i];
+            if self.tag_name(node) == tag_name {
+                self.generate_implied_end_tags_except(Some(tag_name));
+                self.open_elements.truncate(i);
+                return;
+            }
+            if self.is(node, SPECIAL_ELEMENTS) {
+                return;
+            }
+        }
+    }
+
+    // --- token dispatching ---
+
+    fn process(&mut self, token: Token) {
+        self.process_in(self.mode, token);
+    }
+
+    fn process_in(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process(token);
+    }
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data, Some(DOCUMENT)),
+            Token::Doctype(doctype) => {
+                self.quirks_mode = quirks_mode_of(&doctype);
+                let id = self.create_node(NodeKind::Doctype(doctype));
+                self.append(DOCUMENT, id);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                self.quirks_mode = QuirksMode::Quirks;
+                self.reprocess_in(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => {}
+            Token::Comment(data) => self.insert_comment(data, Some(DOCUMENT)),
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::StartTag(tag) if tag.tag_name == "html" => {
+                let id = self.create_node(NodeKind::Element(tag));
+                self.append(DOCUMENT, id);
+                self.open_elements.push(id);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(ref tag) if !matches!(tag.tag_name.as_str(), "head" | "body" | "html" | "br") => {}
+            token => {
+                let id = self.create_node(NodeKind::Element(Tag::new("html")));
+                self.append(DOCUMENT, id);
+                self.open_elements.push(id);
+                self.reprocess_in(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.tag_name == "head" => {
+                self.head = Some(self.insert_element(tag));
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag(ref tag) if !matches!(tag.tag_name.as_str(), "head" | "body" | "html" | "br") => {}
+            token => {
+                self.head = Some(self.insert_element(Tag::new("head")));
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(tag) => match tag.tag_name.as_str() {
+                "base" | "basefont" | "bgsound" | "link" | "meta" => self.insert_void_element(tag),
+                "title" => self.parse_generic_text(tag, State::RcData),
+                // the scripting flag is enabled since we have a JavaScript runtime
+                "noscript" | "noframes" | "style" => self.parse_generic_text(tag, State::RawText),
+                "script" => self.parse_generic_text(tag, State::ScriptData),
+                "template" => {
+                    self.insert_element(tag);
+                    self.active_formatting_elements.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => {}
+                _ => self.in_head_anything_else(Token::StartTag(tag)),
+            },
+            Token::EndTag(ref tag) => match tag.tag_name.as_str() {
+                "head" => {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "body" | "html" | "br" => self.in_head_anything_else(token),
+                "template" => {
+                    if !self.has_open("template") {
+                        return;
+                    }
+                    self.generate_implied_end_tags_thoroughly();
+                    self.pop_until(&["template"]);
+                    self.clear_active_formatting_elements_to_last_marker();
+                    self.template_modes.pop();
+                    self.reset_insertion_mode();
+                }
+                _ => {}
+            },
+            token => self.in_head_anything_else(token),
+        }
+    }
+
+    fn in_head_anything_else(&mut self, token: Token) {
+        self.open_elements.pop();
+        self.reprocess_in(InsertionMode::AfterHead, token);
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.tag_name == "body" => {
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag(tag) if tag.tag_name == "frameset" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template" | "title"
+                ) =>
+            {
+                let head = self.head.unwrap();
+                self.open_elements.push(head);
+                self.in_head(token);
+                self.remove_from_open_elements(head);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => self.in_head(token),
+            Token::StartTag(ref tag) if tag.tag_name == "head" => {}
+            Token::EndTag(ref tag) if !matches!(tag.tag_name.as_str(), "body" | "html" | "br") => {}
+            token => {
+                self.insert_element(Tag::new("body"));
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_character(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::Eof => {
+                if !self.template_modes.is_empty() {
+                    self.in_template(Token::Eof);
+                } else {
+                    self.stopped = true;
+                }
+            }
+        }
+    }
+
+    fn add_missing_attributes(&mut self, id: usize, tag: Tag) {
+        if let NodeKind::Element(ref mut element) = self.nodes[id].kind {
+            for (name, value) in tag.attributes {
+                if element.attribute(&name).is_none() {
+                    element.attributes.push((name, value));
+                }
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: Tag) {
+        match tag.tag_name.as_str() {
+            "html" => {
+                if !self.has_open("template") {
+                    let html = self.open_elements[0];
+                    self.add_missing_attributes(html, tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template"
+            | "title" => self.in_head(Token::StartTag(tag)),
+            "body" => {
+                if self.open_elements.len() == 1 || !self.is(self.open_elements[1], &["body"]) || self.has_open("template") {
+                    return;
+                }
+                self.frameset_ok = false;
+                let body = self.open_elements[1];
+                self.add_missing_attributes(body, tag);
+            }
+            "frameset" => {
+                if self.open_elements.len() == 1 || !self.is(self.open_elements[1], &["body"]) || !self.frameset_ok {
+                    return;
+                }
+                let body = self.open_elements[1];
+                self.detach(body);
+                self.open_elements.truncate(1);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog" | "dir" | "div" | "dl"
+            | "fieldset" | "figcaption" | "figure" | "footer" | "header" | "hgroup" | "main" | "menu" | "nav" | "ol"
+            | "p" | "search" | "section" | "summary" | "ul" => {
+                self.close_p_element_if_in_button_scope();
+                self.insert_element(tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_element_if_in_button_scope();
+                if self.current_is(HEADINGS) {
+                    self.open_elements.pop();
+                }
+                self.insert_element(tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_element_if_in_button_scope();
+                self.insert_element(tag);
+                self.ignore_next_line_feed = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let has_template = self.has_open("template");
+                if self.form.is_some() && !has_template {
+                    return;
+                }
+                self.close_p_element_if_in_button_scope();
+                let id = self.insert_element(tag);
+                if !has_template {
+                    self.form = Some(id);
+                }
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let closing: &[&str] = if tag.tag_name == "li" { &["li"] } else { &["dd", "dt"] };
+                for i in (0..self.open_elements.len()).rev() {
+                    let node = self.open_elements[i];
+                    if self.is(node, closing) {
+                        let name = self.tag_name(node).to_string();
+                        self.generate_implied_end_tags_except(Some(&name));
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if self.is(node, SPECIAL_ELEMENTS) && !self.is(node, &["address", "div", "p"]) {
+                        break;
+                    }
+                }
+                self.close_p_element_if_in_button_scope();
+                self.insert_element(tag);
+            }
+            "plaintext" => {
+                self.close_p_element_if_in_button_scope();
+                self.insert_element(tag);
+                self.tokenizer.set_state(State::PlainText);
+            }
+            "button" => {
+                if self.in_scope("button") {
+                    self.generate_implied_end_tags();
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let existing = self
+                    .active_formatting_elements
+                    .iter()
+                    .rev()
+                    .take_while(|entry| !matches!(entry, FormattingEntry::Marker))
+                    .find_map(|entry| match entry {
+                        FormattingEntry::Element(id, t) if t.tag_name == "a" => Some(*id),
+                        _ => None,
+                    });
+                if let Some(existing) = existing {
+                    self.adoption_agency("a");
+                    if let Some(position) = self.formatting_position(existing) {
+                        self.active_formatting_elements.remove(position);
+                    }
+                    self.remove_from_open_elements(existing);
+                }
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_element(tag.clone());
+                self.push_active_formatting_element(id, tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong" | "tt" | "u" => {
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_element(tag.clone());
+                self.push_active_formatting_element(id, tag);
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting_elements();
+                if self.in_scope("nobr") {
+                    self.adoption_agency("nobr");
+                    self.reconstruct_active_formatting_elements();
+                }
+                let id = self.insert_element(tag.clone());
+                self.push_active_formatting_element(id, tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag);
+                self.active_formatting_elements.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if self.quirks_mode != QuirksMode::Quirks {
+                    self.close_p_element_if_in_button_scope();
+                }
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_void_element(tag);
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_active_formatting_elements();
+                let hidden = tag
+                    .attribute("type")
+                    .map(|t| t.eq_ignore_ascii_case("hidden"))
+                    .unwrap_or(false);
+                self.insert_void_element(tag);
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => self.insert_void_element(tag),
+            "hr" => {
+                self.close_p_element_if_in_button_scope();
+                self.insert_void_element(tag);
+                self.frameset_ok = false;
+            }
+            "image" => {
+                let tag = Tag {
+                    tag_name: "img".to_string(),
+                    ..tag
+                };
+                self.in_body_start_tag(tag);
+            }
+            "textarea" => {
+                self.insert_element(tag);
+                self.ignore_next_line_feed = true;
+                self.tokenizer.set_state(State::RcData);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_element_if_in_button_scope();
+                self.reconstruct_active_formatting_elements();
+                self.frameset_ok = false;
+                self.parse_generic_text(tag, State::RawText);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_generic_text(tag, State::RawText);
+            }
+            "noembed" | "noscript" => self.parse_generic_text(tag, State::RawText),
+            "select" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_is(&["option"]) {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag);
+            }
+            "rb" | "rtc" => {
+                if self.in_scope("ruby") {
+                    self.generate_implied_end_tags();
+                }
+                self.insert_element(tag);
+            }
+            "rp" | "rt" => {
+                if self.in_scope("ruby") {
+                    self.generate_implied_end_tags_except(Some("rtc"));
+                }
+                self.insert_element(tag);
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => {}
+            _ => {
+                self.reconstruct_active_formatting_elements();
+                let self_closing = tag.self_closing;
+                let is_foreign = matches!(tag.tag_name.as_str(), "svg" | "math");
+                self.insert_element(tag);
+                if self_closing && is_foreign {
+                    self.open_elements.pop();
+                }
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        match tag.tag_name.as_str() {
+            "template" => self.in_head(Token::EndTag(tag)),
+            "body" => {
+                if self.in_scope("body") {
+                    self.mode = InsertionMode::AfterBody;
+                }
+            }
+            "html" => {
+                if self.in_scope("body") {
+                    self.reprocess_in(InsertionMode::AfterBody, Token::EndTag(tag));
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details" | "dialog" | "dir"
+            | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header" | "hgroup" | "listing"
+            | "main" | "menu" | "nav" | "ol" | "pre" | "search" | "section" | "summary" | "ul" => {
+                if !self.in_scope(&tag.tag_name) {
+                    return;
+                }
+                self.generate_implied_end_tags();
+                self.pop_until(&[&tag.tag_name]);
+            }
+            "form" => {
+                if !self.has_open("template") {
+                    let node = self.form.take();
+                    match node {
+                        Some(node) if self.in_scope("form") => {
+                            self.generate_implied_end_tags();
+                            self.remove_from_open_elements(node);
+                        }
+                        _ => {}
+                    }
+                } else {
+                    if !self.in_scope("form") {
+                        return;
+                    }
+                    self.generate_implied_end_tags();
+                    self.pop_until(&["form"]);
+                }
+            }
+            "p" => {
+                if !self.in_button_scope("p") {
+                    self.insert_element(Tag::new("p"));
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if !self.in_list_item_scope("li") {
+                    return;
+                }
+                self.generate_implied_end_tags_except(Some("li"));
+                self.pop_until(&["li"]);
+            }
+            "dd" | "dt" => {
+                if !self.in_scope(&tag.tag_name) {
+                    return;
+                }
+                self.generate_implied_end_tags_except(Some(&tag.tag_name));
+                self.pop_until(&[&tag.tag_name]);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.in_specific_scope(HEADINGS, DEFAULT_SCOPE) {
+                    return;
+                }
+                self.generate_implied_end_tags();
+                self.pop_until(HEADINGS);
+            }
+            name if FORMATTING_ELEMENTS.contains(&name) => self.adoption_agency(name),
+            "applet" | "marquee" | "object" => {
+                if !self.in_scope(&tag.tag_name) {
+                    return;
+                }
+                self.generate_implied_end_tags();
+                self.pop_until(&[&tag.tag_name]);
+                self.clear_active_formatting_elements_to_last_marker();
+            }
+            "br" => self.in_body_start_tag(Tag::new("br")),
+            name => self.any_other_end_tag(name),
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_character(c),
+            Token::Eof => {
+                self.open_elements.pop();
+                let mode = self.original_mode;
+                self.reprocess_in(mode, Token::Eof);
+            }
+            Token::EndTag(_) => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+            }
+            _ => {}
+        }
+    }
+
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Character(_) if self.current_is(&["table", "tbody", "template", "tfoot", "thead", "tr"]) => {
+                self.pending_table_characters = vec![];
+                self.original_mode = self.mode;
+                self.reprocess_in(InsertionMode::InTableText, token);
+            }
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) => match tag.tag_name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to_table_context();
+                    self.active_formatting_elements.push(FormattingEntry::Marker);
+                    self.insert_element(tag);
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(tag);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(Tag::new("colgroup"));
+                    self.reprocess_in(InsertionMode::InColumnGroup, Token::StartTag(tag));
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(tag);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(Tag::new("tbody"));
+                    self.reprocess_in(InsertionMode::InTableBody, Token::StartTag(tag));
+                }
+                "table" => {
+                    if !self.in_table_scope(&["table"]) {
+                        return;
+                    }
+                    self.pop_until(&["table"]);
+                    self.reset_insertion_mode();
+                    self.process(Token::StartTag(tag));
+                }
+                "style" | "script" | "template" => self.in_head(Token::StartTag(tag)),
+                "input"
+                    if tag
+                        .attribute("type")
+                        .map(|t| t.eq_ignore_ascii_case("hidden"))
+                        .unwrap_or(false) =>
+                {
+                    self.insert_void_element(tag);
+                }
+                "form" => {
+                    if self.has_open("template") || self.form.is_some() {
+                        return;
+                    }
+                    self.form = Some(self.insert_element(tag));
+                    self.open_elements.pop();
+                }
+                _ => self.in_table_anything_else(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.tag_name.as_str() {
+                "table" => {
+                    if !self.in_table_scope(&["table"]) {
+                        return;
+                    }
+                    self.pop_until(&["table"]);
+                    self.reset_insertion_mode();
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => {}
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.in_table_anything_else(Token::EndTag(tag)),
+            },
+            Token::Eof => self.in_body(Token::Eof),
+            token => self.in_table_anything_else(token),
+        }
+    }
+
+    fn in_table_anything_else(&mut self, token: Token) {
+        self.foster_parenting = true;
+        self.in_body(token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => self.pending_table_characters.push(c),
+            token => {
+                let characters = std::mem::take(&mut self.pending_table_characters);
+                if characters.iter().any(|&c| !is_whitespace(c)) {
+                    for c in characters {
+                        self.in_table_anything_else(Token::Character(c));
+                    }
+                } else {
+                    for c in characters {
+                        self.insert_character(c);
+                    }
+                }
+                let mode = self.original_mode;
+                self.reprocess_in(mode, token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.tag_name == "caption" => {
+                self.close_caption();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => {
+                if self.close_caption() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) => {}
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_caption(&mut self) -> bool {
+        if !self.in_table_scope(&["caption"]) {
+            return false;
+        }
+        self.generate_implied_end_tags();
+        self.pop_until(&["caption"]);
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.tag_name == "col" => self.insert_void_element(tag),
+            Token::EndTag(ref tag) if tag.tag_name == "colgroup" => {
+                if self.current_is(&["colgroup"]) {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "col" => {}
+            Token::StartTag(ref tag) if tag.tag_name == "template" => self.in_head(token),
+            Token::EndTag(ref tag) if tag.tag_name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            token => {
+                if !self.current_is(&["colgroup"]) {
+                    return;
+                }
+                self.open_elements.pop();
+                self.reprocess_in(InsertionMode::InTable, token);
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if tag.tag_name == "tr" => {
+                self.clear_stack_back_to_table_body_context();
+                self.insert_element(tag);
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag(tag) if tag.tag_name == "th" || tag.tag_name == "td" => {
+                self.clear_stack_back_to_table_body_context();
+                self.insert_element(Tag::new("tr"));
+                self.reprocess_in(InsertionMode::InRow, Token::StartTag(tag));
+            }
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_table_scope(&[&tag.tag_name]) {
+                    return;
+                }
+                self.clear_stack_back_to_table_body_context();
+                self.open_elements.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.tag_name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") =>
+            {
+                self.close_table_body(token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => self.close_table_body(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) => {}
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_table_body(&mut self, token: Token) {
+        if !self.in_table_scope(&["tbody", "thead", "tfoot"]) {
+            return;
+        }
+        self.clear_stack_back_to_table_body_context();
+        self.open_elements.pop();
+        self.reprocess_in(InsertionMode::InTable, token);
+    }
+
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if tag.tag_name == "th" || tag.tag_name == "td" => {
+                self.clear_stack_back_to_table_row_context();
+                self.insert_element(tag);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting_elements.push(FormattingEntry::Marker);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "tr" => {
+                self.close_row();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => {
+                if self.close_row() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_table_scope(&[&tag.tag_name]) {
+                    return;
+                }
+                if self.close_row() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) => {}
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_row(&mut self) -> bool {
+        if !self.in_table_scope(&["tr"]) {
+            return false;
+        }
+        self.clear_stack_back_to_table_row_context();
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.tag_name == "td" || tag.tag_name == "th" => {
+                if !self.in_table_scope(&[&tag.tag_name]) {
+                    return;
+                }
+                self.generate_implied_end_tags();
+                self.pop_until(&[&tag.tag_name]);
+                self.clear_active_formatting_elements_to_last_marker();
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if !self.in_table_scope(&["td", "th"]) {
+                    return;
+                }
+                self.close_cell();
+                self.process(token);
+            }
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {}
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if !self.in_table_scope(&[&tag.tag_name]) {
+                    return;
+                }
+                self.close_cell();
+                self.process(token);
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags();
+        self.pop_until(&["td", "th"]);
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) => match tag.tag_name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "option" => {
+                    if self.current_is(&["option"]) {
+                        self.open_elements.pop();
+                    }
+                    self.insert_element(tag);
+                }
+                "optgroup" => {
+                    if self.current_is(&["option"]) {
+                        self.open_elements.pop();
+                    }
+                    if self.current_is(&["optgroup"]) {
+                        self.open_elements.pop();
+                    }
+                    self.insert_element(tag);
+                }
+                "hr" => {
+                    if self.current_is(&["option"]) {
+                        self.open_elements.pop();
+                    }
+                    if self.current_is(&["optgroup"]) {
+                        self.open_elements.pop();
+                    }
+                    self.insert_void_element(tag);
+                }
+                "select" => {
+                    if !self.in_select_scope("select") {
+                        return;
+                    }
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+                "input" | "keygen" | "textarea" => {
+                    if !self.in_select_scope("select") {
+                        return;
+                    }
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                    self.process(Token::StartTag(tag));
+                }
+                "script" | "template" => self.in_head(Token::StartTag(tag)),
+                _ => {}
+            },
+            Token::EndTag(tag) => match tag.tag_name.as_str() {
+                "optgroup" => {
+                    let len = self.open_elements.len();
+                    if self.current_is(&["option"]) && len >= 2 && self.is(self.open_elements[len - 2], &["optgroup"]) {
+                        self.open_elements.pop();
+                    }
+                    if self.current_is(&["optgroup"]) {
+                        self.open_elements.pop();
+                    }
+                }
+                "option" if self.current_is(&["option"]) => {
+                    self.open_elements.pop();
+                }
+                "select" => {
+                    if !self.in_select_scope("select") {
+                        return;
+                    }
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => {}
+            },
+            Token::Eof => self.in_body(Token::Eof),
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token) {
+        const TABLE_ELEMENTS: &[&str] = &["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+        match token {
+            Token::StartTag(ref tag) if TABLE_ELEMENTS.contains(&tag.tag_name.as_str()) => {
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                self.process(token);
+            }
+            Token::EndTag(ref tag) if TABLE_ELEMENTS.contains(&tag.tag_name.as_str()) => {
+                if !self.in_table_scope(&[&tag.tag_name]) {
+                    return;
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                self.process(token);
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    fn in_template(&mut self, token: Token) {
+        match token {
+            Token::Character(_) | Token::Comment(_) | Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(ref tag) => {
+                let mode = match tag.tag_name.as_str() {
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+                    | "template" | "title" => {
+                        self.in_head(token);
+                        return;
+                    }
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody,
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.reprocess_in(mode, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => self.in_head(token),
+            Token::EndTag(_) => {}
+            Token::Eof => {
+                if !self.has_open("template") {
+                    self.stopped = true;
+                    return;
+                }
+                self.pop_until(&["template"]);
+                self.clear_active_formatting_elements_to_last_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                self.process(Token::Eof);
+            }
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::Comment(data) => {
+                let html = self.open_elements[0];
+                self.insert_comment(data, Some(html));
+            }
+            Token::Doctype(_) => {}
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.tag_name == "html" => {
+                if self.context.is_none() {
+                    self.mode = InsertionMode::AfterAfterBody;
+                }
+            }
+            Token::Eof => self.stopped = true,
+            token => self.reprocess_in(InsertionMode::InBody, token),
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.tag_name == "frameset" => {
+                self.insert_element(tag);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "frameset" => {
+                if self.current_is(&["html"]) {
+                    return;
+                }
+                self.open_elements.pop();
+                if self.context.is_none() && !self.current_is(&["frameset"]) {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::StartTag(tag) if tag.tag_name == "frame" => self.insert_void_element(tag),
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => self.in_head(token),
+            Token::Eof => self.stopped = true,
+            _ => {}
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.tag_name == "html" => self.mode = InsertionMode::AfterAfterFrameset,
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => self.in_head(token),
+            Token::Eof => self.stopped = true,
+            _ => {}
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(data) => self.insert_comment(data, Some(DOCUMENT)),
+            Token::Doctype(_) => self.in_body(token),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::Eof => self.stopped = true,
+            token => self.reprocess_in(InsertionMode::InBody, token),
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(data) => self.insert_comment(data, Some(DOCUMENT)),
+            Token::Doctype(_) => self.in_body(token),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => self.in_head(token),
+            Token::Eof => self.stopped = true,
+            _ => {}
+        }
+    }
+}
+
+fn attributes_equal(a: &Tag, b: &Tag) -> bool {
+    a.attributes.len() == b.attributes.len()
+        && a.attributes.iter().all(|(name, value)| b.attribute(name) == Some(value.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doctype(name: &str, public_id: Option<&str>, system_id: Option<&str>) -> Doctype {
+        Doctype {
+            name: Some(name.to_string()),
+            public_id: public_id.map(|id| id.to_string()),
+            system_id: system_id.map(|id| id.to_string()),
+            force_quirks: false,
+        }
+    }
+
+    #[test]
+    fn test_quirks_mode_of() {
+        assert_eq!(quirks_mode_of(&doctype("html", None, None)), QuirksMode::NoQuirks);
+        assert_eq!(quirks_mode_of(&doctype("svg", None, None)), QuirksMode::Quirks);
+        assert_eq!(
+            quirks_mode_of(&doctype("html", Some("-//W3C//DTD HTML 4.01 Transitional//EN"), None)),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            quirks_mode_of(&doctype(
+                "html",
+                Some("-//W3C//DTD HTML 4.01 Transitional//EN"),
+                Some("http://www.w3.org/TR/html4/loose.dtd")
+            )),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            quirks_mode_of(&doctype("html", Some("-//W3C//DTD XHTML 1.0 Transitional//EN"), None)),
+            QuirksMode::LimitedQuirks
+        );
+    }
+
+    #[test]
+    fn test_quirks_mode_of_document() {
+        let builder = |input: &str| {
+            let mut builder = TreeBuilder::new(input);
+            while !builder.stopped {
+                let token = builder.tokenizer.next_token();
+                builder.process(token);
+            }
+            builder.quirks_mode
+        };
+        assert_eq!(builder("<!DOCTYPE html><p>"), QuirksMode::NoQuirks);
+        assert_eq!(builder("<p>"), QuirksMode::Quirks);
+    }
+}
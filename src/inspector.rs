@@ -0,0 +1,252 @@
+//! This module implements an inspection API like the elements panel of browser developer tools.
+//!
+//! It tells where a node, its attributes and the rules matching it are written,
+//! and which declarations lose the cascade, so that a style which does not apply can be traced back to the source.
+
+use std::fmt;
+
+use crate::{
+    css::{self, Declaration, Origin, Specificity, Stylesheet},
+    dom::{Arena, NodeId, NodeType},
+    source::SourcePosition,
+    style::{cascade, precedence, INLINE_SPECIFICITY},
+};
+
+/// `NodeInspection` describes a node and the rules matching it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NodeInspection {
+    pub node: NodeId,
+    // 要素はタグ名、それ以外は`#text`などのノード名
+    pub name: String,
+    pub position: Option<SourcePosition>,
+    pub attributes: Vec<AttributeInspection>,
+    // カスケードで優先される順。後のものほど優先される
+    pub rules: Vec<MatchedRule>,
+}
+
+/// `AttributeInspection` describes an attribute of an element.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AttributeInspection {
+    pub name: String,
+    pub value: String,
+    pub position: Option<SourcePosition>,
+}
+
+/// `MatchedRule` represents a rule matching the node, or the declarations in its `style` attribute.
+/// `position` is the position in `source`, which is the stylesheet or the document the rule is written in.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchedRule {
+    pub origin: Origin,
+    pub specificity: Specificity,
+    pub inline: bool,
+    pub source: Option<String>,
+    pub position: Option<SourcePosition>,
+    pub declarations: Vec<DeclarationInspection>,
+}
+
+/// `DeclarationInspection` tells whether a declaration sets any property of the node.
+/// A declaration is not applied when it loses the cascade, or when its value is invalid for a shorthand.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeclarationInspection {
+    pub declaration: Declaration,
+    pub applied: bool,
+}
+
+/// `inspect` describes the node with the stylesheets given to `style::to_styled_node`.
+/// `@media` rules are expected to be already resolved.
+pub fn inspect(arena: &Arena, id: NodeId, stylesheets: &[Stylesheet]) -> NodeInspection {
+    let node = &arena[id];
+    let (name, element) = match node.node_type {
+        NodeType::Element(ref el) => (el.tag_name.clone(), Some(el)),
+        NodeType::Text(_) => ("#text".to_string(), None),
+        NodeType::Comment(_) => ("#comment".to_string(), None),
+        NodeType::Document(_) => ("#document".to_string(), None),
+        NodeType::DocumentType(ref doctype) => (doctype.name.clone(), None),
+    };
+
+    let mut attributes = element
+        .map(|el| {
+            el.attributes()
+                .into_iter()
+                .map(|(name, value)| AttributeInspection {
+                    position: el.attribute_position(&name),
+                    name,
+                    value,
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    // 属性はソース順に並べ、スクリプトが追加したものは名前順で最後に置く
+    attributes.sort_by(|a, b| (a.position.is_none(), a.position, &a.name).cmp(&(b.position.is_none(), b.position, &b.name)));
+
+    let mut rules = vec![];
+    if let Some(el) = element {
+        for stylesheet in stylesheets {
+            for rule in &stylesheet.rules {
+                if let Some(specificity) = rule.matching_specificity(arena, id) {
+                    let source = rule.source.clone();
+                    rules.push(matched_rule(stylesheet.origin, specificity, false, source, rule.position, &rule.declarations));
+                }
+            }
+        }
+        if let Some(style) = el.attribute("style") {
+            let (declarations, _) = css::parse_declarations(style);
+            let (source, position) = (arena.url().map(str::to_string), el.attribute_position("style"));
+            rules.push(matched_rule(Origin::Author, INLINE_SPECIFICITY, true, source, position, &declarations));
+        }
+    }
+    // 安定ソートなので、優先度が同じ規則はソース順のまま並ぶ
+    rules.sort_by_key(|rule| (precedence(rule.origin, false), rule.specificity));
+    mark_applied(&mut rules);
+
+    NodeInspection {
+        node: id,
+        name,
+        position: node.position(),
+        attributes,
+        rules,
+    }
+}
+
+fn matched_rule(
+    origin: Origin,
+    specificity: Specificity,
+    inline: bool,
+    source: Option<String>,
+    position: Option<SourcePosition>,
+    declarations: &[Declaration],
+) -> MatchedRule {
+    MatchedRule {
+        origin,
+        specificity,
+        inline,
+        source,
+        position,
+        declarations: declarations
+            .iter()
+            .map(|declaration| DeclarationInspection {
+                declaration: declaration.clone(),
+                applied: false,
+            })
+            .collect(),
+    }
+}
+
+/// `mark_applied` runs the cascade as `style::to_styled_node` does, and marks the declarations which win any property.
+fn mark_applied(rules: &mut [MatchedRule]) {
+    let mut indices = vec![];
+    let mut declarations = vec![];
+    for (i, rule) in rules.iter().enumerate() {
+        for (j, inspection) in rule.declarations.iter().enumerate() {
+            indices.push((i, j));
+            declarations.push((rule.origin, rule.specificity, &inspection.declaration));
+        }
+    }
+
+    let winners = cascade(&declarations).into_values().map(|(_, k)| indices[k]).collect::<Vec<_>>();
+    for (i, j) in winners {
+        rules[i].declarations[j].applied = true;
+    }
+}
+
+fn at(position: Option<SourcePosition>) -> String {
+    position.map(|p| format!(" at {}", p)).unwrap_or_default()
+}
+
+/// `at_source` prefixes the position with the source if it is known, e.g. ` at style.css:1:1`.
+fn at_source(source: &Option<String>, position: Option<SourcePosition>) -> String {
+    match (source, position) {
+        (Some(source), Some(p)) => format!(" at {}:{}", source, p),
+        (Some(source), None) => format!(" in {}", source),
+        (None, position) => at(position),
+    }
+}
+
+/// `NodeInspection` is printed like the elements panel, e.g.
+///
+/// ```text
+/// <p> at 3:1
+///   id="a" at 3:4
+///   author rule at style.css:1:1, specificity (0, 0, 1)
+///     color: red at 1:5 (not applied)
+///   style attribute at page.html:3:11
+///     color: blue at 1:1
+/// ```
+///
+/// Declarations are located in the same source as their rule,
+/// except that the ones in a `style` attribute are located in the value of the attribute.
+impl fmt::Display for NodeInspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.starts_with('#') {
+            writeln!(f, "{}{}", self.name, at(self.position))?;
+        } else {
+            writeln!(f, "<{}>{}", self.name, at(self.position))?;
+        }
+        for attribute in &self.attributes {
+            writeln!(f, "  {}=\"{}\"{}", attribute.name, attribute.value, at(attribute.position))?;
+        }
+        for rule in &self.rules {
+            if rule.inline {
+                writeln!(f, "  style attribute{}", at_source(&rule.source, rule.position))?;
+            } else {
+                let origin = match rule.origin {
+                    Origin::UserAgent => "user-agent",
+                    Origin::User => "user",
+                    Origin::Author => "author",
+                };
+                let (a, b, c) = rule.specificity;
+                let at = at_source(&rule.source, rule.position);
+                writeln!(f, "  {} rule{}, specificity ({}, {}, {})", origin, at, a, b, c)?;
+            }
+            for inspection in &rule.declarations {
+                let note = if inspection.applied { "" } else { " (not applied)" };
+                writeln!(f, "    {}{}{}", inspection.declaration, at(inspection.declaration.position), note)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    #[test]
+    fn test_inspect() {
+        let arena = html::parse("<!DOCTYPE html>\n<p id=a\n   style=\"color: blue\" class=x>hi</p>");
+        let mut user_agent = css::parse("p { display: block; }");
+        user_agent.origin = Origin::UserAgent;
+        // as if written in `<style>` at 4:8 in the document
+        let mut author = css::parse("p { color: red; margin: 1ch }\n.x { margin-top: 2ch; margin: bad bad bad bad bad; }");
+        author.locate(Some("page.html"), Some(SourcePosition { offset: 80, line: 4, column: 8 }));
+        let stylesheets = [user_agent, author];
+
+        let body = arena.children(arena.document_element().unwrap()).nth(1).unwrap();
+        let p = arena[body].first_child().unwrap();
+        assert_eq!(
+            inspect(&arena, p, &stylesheets).to_string(),
+            "<p> at 2:1
+  id=\"a\" at 2:4
+  style=\"color: blue\" at 3:4
+  class=\"x\" at 3:24
+  user-agent rule at 1:1, specificity (0, 0, 1)
+    display: block at 1:5
+  author rule at page.html:4:8, specificity (0, 0, 1)
+    color: red at 4:12 (not applied)
+    margin: 1ch at 4:24
+  author rule at page.html:5:1, specificity (0, 1, 0)
+    margin-top: 2ch at 5:6
+    margin: bad bad bad bad bad at 5:23 (not applied)
+  style attribute at 3:4
+    color: blue at 1:1
+"
+        );
+
+        // implied elements and text have their own positions
+        let inspection = inspect(&arena, body, &stylesheets);
+        assert_eq!(inspection.position, None);
+        let text = arena[p].first_child().unwrap();
+        assert_eq!(inspect(&arena, text, &stylesheets).to_string(), "#text at 3:32\n");
+    }
+}
@@ -1,12 +1,12 @@
 // V8についての解説
 // Qiita: https://qiita.com/komukomo/items/316afadd04f95808f338
 
-use std::{cell::RefCell, f32::consts::E, rc::Rc, sync::Once};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Once};
 
 // use rusty_v8 as v8;
 use v8;
 
-use crate::{dom::Node, javascript::binding::create_document_object, renderer};
+use crate::{dom::{Arena, NodeId}, error::ScriptError, javascript::binding::create_document_object, source::SourcePosition};
 
 pub mod renderapi;
 pub mod binding;
@@ -15,7 +15,12 @@ use renderapi::RendererAPI;
 pub struct JavaScriptRuntimeState {
     pub context: v8::Global<v8::Context>,
     pub renderer_api: Rc<RendererAPI>,
-    pub document_element: Rc<RefCell<Box<Node>>>,
+    pub document: Rc<RefCell<Arena>>,
+    /// `wrappers` maps each DOM node to its JavaScript object.
+    /// The objects are held weakly so that unreachable ones can be garbage-collected.
+    pub wrappers: HashMap<NodeId, v8::Weak<v8::Object>>,
+    /// `style_wrappers` maps each element to the object of its `style` in the same way.
+    pub style_wrappers: HashMap<NodeId, v8::Weak<v8::Object>>,
 }
 
 #[derive(Debug)]
@@ -24,7 +29,7 @@ pub struct JavaScriptRuntime {
 }
 
 impl JavaScriptRuntime {
-    pub fn new(document_element: Rc<RefCell<Box<Node>>>, renderer_api: Rc<RendererAPI>) -> JavaScriptRuntime {
+    pub fn new(document: Rc<RefCell<Arena>>, renderer_api: Rc<RendererAPI>) -> JavaScriptRuntime {
         static PUPPY_INIT : Once = Once::new();
         PUPPY_INIT.call_once(move || {
             // Initialize V8.
@@ -66,7 +71,9 @@ impl JavaScriptRuntime {
         isolate.set_slot(Rc::new(RefCell::new(JavaScriptRuntimeState {
             context,
             renderer_api,
-            document_element
+            document,
+            wrappers: HashMap::new(),
+            style_wrappers: HashMap::new(),
         })));
 
         JavaScriptRuntime {
@@ -74,7 +81,13 @@ impl JavaScriptRuntime {
         }
     }
 
-    pub fn execute(&mut self, filename: &str, source: &str) -> Result<String, String> {
+    pub fn execute(&mut self, filename: &str, source: &str) -> Result<String, ScriptError> {
+        self.execute_at(filename, source, None)
+    }
+
+    /// `execute_at` runs a script which starts at `position` in the file,
+    /// so that the positions in errors refer to the file rather than to the script.
+    pub fn execute_at(&mut self, filename: &str, source: &str, position: Option<SourcePosition>) -> Result<String, ScriptError> {
         // Ok("".to_string())
         let scope = &mut self.get_handle_scope();
 
@@ -84,11 +97,13 @@ impl JavaScriptRuntime {
         let name = v8::String::new(scope, filename).unwrap();
 
         // resource origin
+        // 行と列のオフセットは0始まり
+        let (line_offset, column_offset) = position.map_or((0, 0), |p| (p.line - 1, p.column - 1));
         let origin = v8::ScriptOrigin::new(
             scope,
             name.into(),
-            0,
-            0,
+            line_offset as i32,
+            column_offset as i32,
             false,
             0,
             source_map.into(),
@@ -105,22 +120,20 @@ impl JavaScriptRuntime {
             Some(script) => script,
             None => {
                 assert!(tc_scope.has_caught());
-                return Err(to_pretty_string(tc_scope));
+                return Err(to_script_error(tc_scope));
             }
         };
 
         // run
         match script.run(&mut tc_scope) {
-            Some(result) => {
-                let result_str = result
-                    .to_string(&mut tc_scope)
-                    .unwrap()
-                    .to_rust_string_lossy(&mut tc_scope);
-                return Ok(result_str.to_string());
+            Some(result) => match result.to_string(&mut tc_scope) {
+                Some(result_str) => Ok(result_str.to_rust_string_lossy(&mut tc_scope)),
+                // Symbolのように文字列に変換できない値は、変換時の例外を返す
+                None => Err(to_script_error(tc_scope)),
             },
             None => {
                 assert!(tc_scope.has_caught());
-                return Err(to_pretty_string(tc_scope));
+                Err(to_script_error(tc_scope))
             }
         }
     }
@@ -164,25 +177,41 @@ impl JavaScriptRuntime {
 }
 
 impl JavaScriptRuntime {
-    pub fn document_element(isolate: &v8::Isolate) -> Rc<RefCell<Box<Node>>> {
+    pub fn document(isolate: &v8::Isolate) -> Rc<RefCell<Arena>> {
         let state = Self::state(isolate);
         let state = state.borrow();
-        state.document_element.clone()
+        state.document.clone()
     }
 }
 
-fn to_pretty_string(mut try_catch: v8::TryCatch<v8::HandleScope>) -> String {
-    let exception_string = try_catch.exception().unwrap().to_string(&mut try_catch).unwrap().to_rust_string_lossy(&mut try_catch);
-    let message = try_catch.message().unwrap();
-    let filename = message.get_script_resource_name(&mut try_catch)
+/// `to_script_error` converts the caught exception into `ScriptError`. `column` is 1-origin like `line`.
+/// The position is 0 if it is unknown, and an exception which cannot be converted into a string is `<unprintable exception>`.
+fn to_script_error(mut try_catch: v8::TryCatch<v8::HandleScope>) -> ScriptError {
+    // 例外の文字列化が例外を投げると捕捉中のメッセージが置き換わるので、位置を先に取り出す
+    let (filename, line, column) = match try_catch.message() {
+        Some(message) => {
+            let filename = message
+                .get_script_resource_name(&mut try_catch)
+                .and_then(|s| s.to_string(&mut try_catch))
+                .map_or_else(|| "(unknown)".into(), |s| s.to_rust_string_lossy(&mut try_catch));
+            let line = message.get_line_number(&mut try_catch).unwrap_or_default();
+            (filename, line, message.get_start_column() + 1)
+        },
+        None => ("(unknown)".into(), 0, 0),
+    };
+    let message = try_catch
+        .exception()
+        .and_then(|exception| exception.to_string(&mut try_catch))
         .map_or_else(
-            || "(unknown)".into(),
-            |s| {
-                s.to_string(&mut try_catch).unwrap().to_rust_string_lossy(&mut try_catch)
-            },
+            || "<unprintable exception>".into(),
+            |s| s.to_rust_string_lossy(&mut try_catch),
         );
-    let line_number = message.get_line_number(&mut try_catch).unwrap_or_default();
-    format!("{}:{}: {}", filename, line_number, exception_string)
+    ScriptError {
+        filename,
+        line,
+        column,
+        message,
+    }
 }
 
 #[cfg(test)]
@@ -216,8 +245,16 @@ mod tests {
         }
         {
             // use of undefined variable
-            let r = runtime.execute("", "test");
-            assert!(r.is_err());
+            let r = runtime.execute("(test)", "1;\n  test");
+            assert_eq!(
+                r,
+                Err(ScriptError {
+                    filename: "(test)".into(),
+                    line: 2,
+                    column: 3,
+                    message: "ReferenceError: test is not defined".into(),
+                })
+            );
         }
         {
             // lambda definition
@@ -231,6 +268,25 @@ mod tests {
             assert!(r.is_ok());
             assert_eq!(r.unwrap(), "5");
         }
+        {
+            // errors are located in the file which contains the script
+            let position = SourcePosition { offset: 40, line: 3, column: 9 };
+            let r = runtime.execute_at("page.html", "undefinedFunction()", Some(position));
+            assert_eq!(r.map_err(|e| (e.line, e.column)), Err((3, 9)));
+        }
+        {
+            // a completion value which cannot be converted into a string
+            let r = runtime.execute("", "Symbol('x')");
+            assert_eq!(r.map_err(|e| e.message.starts_with("TypeError")), Err(true));
+        }
+        {
+            // an exception which cannot be converted into a string
+            let r = runtime.execute("(test)", "throw {toString() { throw 1 }}");
+            assert_eq!(
+                r.map_err(|e| (e.line, e.message)),
+                Err((1, "<unprintable exception>".to_string()))
+            );
+        }
     }
 
     #[test]
@@ -265,5 +321,93 @@ mod tests {
             );
             assert_eq!(r, Ok("p".into()));
         }
+        {
+            // accessors called on an object which is not a node throw instead of aborting
+            let r = runtime.execute("", "Object.create(tag).innerHTML");
+            assert_eq!(r.map_err(|e| e.message), Err("TypeError: Illegal invocation".to_string()));
+            let r = runtime.execute("", r#"Object.create(tag).innerHTML = "x""#);
+            assert_eq!(r.map_err(|e| e.message), Err("TypeError: Illegal invocation".to_string()));
+            let r = runtime.execute("", "document.getElementById(Symbol())");
+            assert_eq!(r.map_err(|e| e.message.starts_with("TypeError")), Err(true));
+            assert!(cb_recv.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_style() {
+        let (cb_sink, cb_recv) = crossbeam_channel::unbounded();
+        let mut runtime = JavaScriptRuntime::new(
+            Rc::new(RefCell::new(html::parse(r#"<p id="p" style="color: red">a</p>"#))),
+            Rc::new(RendererAPI::new(Rc::new(cb_sink))),
+        );
+        {
+            // the same element always has the same style object
+            let r = runtime.execute("", r#"let p = document.getElementById("p"); p.style === p.style"#);
+            assert_eq!(r, Ok("true".into()));
+        }
+        {
+            // supported properties are read from the style attribute, and the others are ordinary properties
+            let r = runtime.execute("", "[p.style.color, p.style.marginTop, p.style.unknown, typeof p.style.toString].join()");
+            assert_eq!(r, Ok("red,,,function".into()));
+            let r = runtime.execute("", "p.style.custom = 1; p.style.custom");
+            assert_eq!(r, Ok("1".into()));
+            assert!(cb_recv.try_recv().is_err());
+        }
+        {
+            // property values are written to the style attribute
+            let r = runtime.execute(
+                "",
+                r#"p.style.backgroundColor = "blue"; p.style.setProperty("margin-top", "1ch"); p.style.getPropertyValue("margin-top")"#,
+            );
+            assert_eq!(r, Ok("1ch".into()));
+            assert!(cb_recv.try_recv().is_ok());
+            let r = runtime.execute("", r#"p.style.removeProperty("color") + "|" + p.style.cssText"#);
+            assert_eq!(r, Ok("red|background-color: blue; margin-top: 1ch;".into()));
+        }
+        {
+            // invalid or unchanged values do not request rerendering
+            while cb_recv.try_recv().is_ok() {}
+            let r = runtime.execute("", r#"p.style.color = "@@"; p.style.setProperty("margin-top", "1ch"); p.style.cssText"#);
+            assert_eq!(r, Ok("background-color: blue; margin-top: 1ch;".into()));
+            assert!(cb_recv.try_recv().is_err());
+        }
+        {
+            // methods called on an object which is not a style throw instead of aborting
+            let r = runtime.execute("", r#"p.style.getPropertyValue.call({}, "color")"#);
+            assert_eq!(r.map_err(|e| e.message), Err("TypeError: Illegal invocation".to_string()));
+            let r = runtime.execute("", "Object.create(p).style");
+            assert_eq!(r.map_err(|e| e.message), Err("TypeError: Illegal invocation".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_node_identity() {
+        let (cb_sink, _cb_recv) = crossbeam_channel::unbounded();
+        let mut runtime = JavaScriptRuntime::new(
+            Rc::new(RefCell::new(html::parse(
+                r#"<div id="parent"><p id="child">child</p></div>"#,
+            ))),
+            Rc::new(RendererAPI::new(Rc::new(cb_sink))),
+        );
+        {
+            // the same node is always wrapped by the same object
+            let r = runtime.execute(
+                "",
+                r#"document.getElementById("parent") === document.getElementById("parent")"#,
+            );
+            assert_eq!(r, Ok("true".into()));
+        }
+        {
+            // a handle to a removed node stays usable
+            let r = runtime.execute(
+                "",
+                r#"let child = document.getElementById("child");
+                   document.getElementById("parent").innerHTML = "replaced";
+                   child.innerHTML"#,
+            );
+            assert_eq!(r, Ok("child".into()));
+            let r = runtime.execute("", r#"document.getElementById("child")"#);
+            assert_eq!(r, Ok("undefined".into()));
+        }
     }
 }
@@ -1,20 +1,20 @@
 use v8::PropertyAttribute;
 
 use crate::{
-    dom::{Node, NodeType},
+    css,
+    dom::{Arena, NodeId, NodeType},
+    style::property,
     javascript::{JavaScriptRuntime, JavaScriptRuntimeState},
 };
 
-use std::ffi::c_void;
+use std::{collections::HashMap, convert::TryFrom, ffi::c_void};
 
 // use v8::READ_ONLY;
 
-type NodeRefTarget<'a> = &'a mut Box<Node>;
-
 // v8にrustのNodeを渡す
 fn to_v8_node<'s>(
     scope: &mut v8::HandleScope<'s>,
-    node_rust: NodeRefTarget,
+    node_rust: NodeId,
 ) -> v8::Local<'s, v8::Object> {
     // v8上にオブジェクトのテンプレートを作成
     let template = v8::ObjectTemplate::new(scope);
@@ -22,9 +22,8 @@ fn to_v8_node<'s>(
     let node_v8 = template.new_instance(scope).unwrap();
 
     // rustのNodeをv8のオブジェクトに紐付ける
-    let boxed_ref = Box::new(node_rust);
-    let address = Box::leak(boxed_ref) as *mut NodeRefTarget as *mut c_void;
-    let v8_external = v8::External::new(scope, address);
+    // NodeIdはアリーナ内の位置なので、ポインタではなく値としてそのまま埋め込む
+    let v8_external = v8::External::new(scope, node_rust.index() as *mut c_void);
     let target_node_ref_v8: v8::Local<v8::Data> = v8_external.into();
     node_v8.set_internal_field(0, target_node_ref_v8);
 
@@ -32,25 +31,92 @@ fn to_v8_node<'s>(
 }
 
 // v8からNodeの情報を取得し、rustのNodeに紐づける
+// Object.create(el)のように、Nodeに紐づいていないオブジェクトならNoneを返す
 fn to_linked_rust_node<'s>(
     scope: &mut v8::HandleScope<'s>,
     node_v8: v8::Local<v8::Object>,
-) -> &'s mut NodeRefTarget<'s> {
-    let node_v8 = node_v8.get_internal_field(scope, 0).unwrap();
-    let node = unsafe { v8::Local::<v8::External>::cast(node_v8) };
-    let node = node.value() as *mut NodeRefTarget;
-    unsafe { &mut *node }
+) -> Option<NodeId> {
+    if node_v8.internal_field_count() == 0 {
+        return None;
+    }
+    let node_v8 = node_v8.get_internal_field(scope, 0)?;
+    let node = v8::Local::<v8::External>::try_from(node_v8).ok()?;
+    Some(NodeId::from_index(node.value() as usize))
+}
+
+// Nodeに紐づいていないオブジェクトに対して呼ばれたときに、ブラウザと同じTypeErrorを投げる
+fn throw_illegal_invocation(scope: &mut v8::HandleScope) {
+    let message = v8::String::new(scope, "Illegal invocation").unwrap();
+    let exception = v8::Exception::type_error(scope, message);
+    scope.throw_exception(exception);
+}
+
+// ラッパーを保持するキャッシュ。Nodeのオブジェクトとstyleのオブジェクトで別々に持つ
+type WrapperCache = fn(&mut JavaScriptRuntimeState) -> &mut HashMap<NodeId, v8::Weak<v8::Object>>;
+
+fn node_wrappers(state: &mut JavaScriptRuntimeState) -> &mut HashMap<NodeId, v8::Weak<v8::Object>> {
+    &mut state.wrappers
+}
+
+fn style_wrappers(state: &mut JavaScriptRuntimeState) -> &mut HashMap<NodeId, v8::Weak<v8::Object>> {
+    &mut state.style_wrappers
+}
+
+// 同じNodeに対して作成済みのv8オブジェクトがまだ生きていれば、それを返す
+fn cached_v8_node<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    node_rust: NodeId,
+    cache: WrapperCache,
+) -> Option<v8::Local<'s, v8::Object>> {
+    // V8の呼び出し中にGCのファイナライザが状態を触れるよう、借用はすぐに手放す
+    let wrapper = {
+        let state = JavaScriptRuntime::state(scope);
+        let mut state = state.borrow_mut();
+        cache(&mut state).get(&node_rust).cloned()
+    };
+    wrapper.and_then(|wrapper| wrapper.to_local(scope))
+}
+
+// v8オブジェクトを弱参照でキャッシュに登録する
+// オブジェクトがGCで回収されたら、ファイナライザがキャッシュから取り除く
+fn cache_v8_node(
+    scope: &mut v8::HandleScope,
+    node_rust: NodeId,
+    node_v8: v8::Local<v8::Object>,
+    cache: WrapperCache,
+) {
+    let wrapper = v8::Weak::with_finalizer(
+        scope,
+        node_v8,
+        Box::new(move |isolate: &mut v8::Isolate| {
+            let state = JavaScriptRuntime::state(isolate);
+            if let Ok(mut state) = state.try_borrow_mut() {
+                let wrappers = cache(&mut state);
+                // 回収後に同じNodeの新しいオブジェクトが登録されていれば残す
+                if matches!(wrappers.get(&node_rust), Some(wrapper) if wrapper.is_empty()) {
+                    wrappers.remove(&node_rust);
+                }
+            };
+        }),
+    );
+    let state = JavaScriptRuntime::state(scope);
+    cache(&mut state.borrow_mut()).insert(node_rust, wrapper);
 }
 
 // RustのNodeのうち、node_typeがElementのものをv8に渡す
+// 同じNodeは常に同じv8オブジェクトになる
 fn to_v8_element<'s>(
     scope: &mut v8::HandleScope<'s>,
     tag_name: &str,
-    _attributes: Vec<(String, String)>,
-    node_rust: NodeRefTarget,
+    node_rust: NodeId,
 ) -> v8::Local<'s, v8::Object> {
+    if let Some(node) = cached_v8_node(scope, node_rust, node_wrappers) {
+        return node;
+    }
+
     // v8上にNodeオブジェクトを紐づける
     let node = to_v8_node(scope, node_rust);
+    cache_v8_node(scope, node_rust, node, node_wrappers);
 
     // tagNameプロパティをv8上に追加
     {
@@ -74,10 +140,13 @@ fn to_v8_element<'s>(
                   _key: v8::Local<v8::Name>,
                   args: v8::PropertyCallbackArguments,
                   mut rv: v8::ReturnValue| {
-                let this = args.this();
-                let node = to_linked_rust_node(scope, this);
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
 
-                let ret = v8::String::new(scope, node.inner_html().as_str()).unwrap();
+                let inner_html = JavaScriptRuntime::document(scope).borrow().inner_html(node);
+                let ret = v8::String::new(scope, inner_html.as_str()).unwrap();
                 rv.set(ret.into());
             },
             move |scope: &mut v8::HandleScope,
@@ -86,18 +155,279 @@ fn to_v8_element<'s>(
                   args: v8::PropertyCallbackArguments,
                   mut _rv: v8::ReturnValue
                 | {
-                let this = args.this();
-                let node = to_linked_rust_node(scope, this);
-                node.set_inner_html(value.to_rust_string_lossy(scope).as_str());
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
+                let html = value.to_rust_string_lossy(scope);
+                let result = JavaScriptRuntime::document(scope).borrow_mut().set_inner_html(node, html.as_str());
+                if let Err(e) = result {
+                    let message = v8::String::new(scope, &e.to_string()).unwrap();
+                    let exception = v8::Exception::error(scope, message);
+                    scope.throw_exception(exception);
+                    return;
+                }
 
                 JavaScriptRuntime::renderer_api(scope).rerender();
             },
         );
     }
 
+    // styleプロパティをv8上に追加
+    {
+        let key = v8::String::new(scope, "style").unwrap();
+        node.set_accessor(
+            scope,
+            key.into(),
+            move |scope: &mut v8::HandleScope,
+                  _key: v8::Local<v8::Name>,
+                  args: v8::PropertyCallbackArguments,
+                  mut rv: v8::ReturnValue| {
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
+                rv.set(to_v8_style(scope, node).into());
+            },
+        );
+    }
+
     node
 }
 
+// element.styleのオブジェクトを作る
+// プロパティの読み書きはすべてstyle属性に対して行う
+// 同じ要素のstyleは常に同じv8オブジェクトになる
+// See https://www.w3.org/TR/cssom-1/#the-elementcssinlinestyle-mixin
+fn to_v8_style<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    node_rust: NodeId,
+) -> v8::Local<'s, v8::Object> {
+    if let Some(style) = cached_v8_node(scope, node_rust, style_wrappers) {
+        return style;
+    }
+
+    let template = v8::ObjectTemplate::new(scope);
+    template.set_internal_field_count(1);
+    // 対応しているプロパティとcssTextだけを横取りし、それ以外は通常のプロパティとして扱う
+    template.set_named_property_handler(
+        v8::NamedPropertyHandlerConfiguration::new()
+            .getter(
+                |scope: &mut v8::HandleScope,
+                 key: v8::Local<v8::Name>,
+                 args: v8::PropertyCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    // Symbolなどはそのまま通常のプロパティとして扱う
+                    if !key.is_string() {
+                        return;
+                    }
+                    let name = key.to_rust_string_lossy(scope);
+                    if name != "cssText" && !property::is_supported(&to_css_property_name(&name)) {
+                        return;
+                    }
+                    let node = match to_linked_rust_node(scope, args.this()) {
+                        Some(node) => node,
+                        None => return throw_illegal_invocation(scope),
+                    };
+                    let value = {
+                        let document = JavaScriptRuntime::document(scope);
+                        let document = document.borrow();
+                        if name == "cssText" {
+                            css_text(&inline_declarations(&document, node))
+                        } else {
+                            style_property(&document, node, &to_css_property_name(&name))
+                        }
+                    };
+                    let value = v8::String::new(scope, value.as_str()).unwrap();
+                    rv.set(value.into());
+                },
+            )
+            .setter(
+                |scope: &mut v8::HandleScope,
+                 key: v8::Local<v8::Name>,
+                 value: v8::Local<v8::Value>,
+                 args: v8::PropertyCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    if !key.is_string() {
+                        return;
+                    }
+                    let name = key.to_rust_string_lossy(scope);
+                    if name != "cssText" && !property::is_supported(&to_css_property_name(&name)) {
+                        return;
+                    }
+                    let node = match to_linked_rust_node(scope, args.this()) {
+                        Some(node) => node,
+                        None => return throw_illegal_invocation(scope),
+                    };
+                    let value_rust = value.to_rust_string_lossy(scope);
+                    let changed = {
+                        let document = JavaScriptRuntime::document(scope);
+                        let mut document = document.borrow_mut();
+                        if name == "cssText" {
+                            set_style_attribute(&mut document, node, value_rust);
+                            true
+                        } else {
+                            set_style_property(&mut document, node, &to_css_property_name(&name), &value_rust)
+                        }
+                    };
+                    // 不正な値でも、通常のプロパティとしては保存しない
+                    rv.set(value);
+
+                    if changed {
+                        JavaScriptRuntime::renderer_api(scope).rerender();
+                    }
+                },
+            ),
+    );
+
+    // getPropertyValue()の関数定義
+    {
+        let key = v8::String::new(scope, "getPropertyValue").unwrap();
+        let function_template = v8::FunctionTemplate::new(
+            scope,
+            |scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut rv: v8::ReturnValue| {
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
+                let name = args.get(0).to_rust_string_lossy(scope);
+                let value = style_property(&JavaScriptRuntime::document(scope).borrow(), node, &name);
+                let value = v8::String::new(scope, value.as_str()).unwrap();
+                rv.set(value.into());
+            },
+        );
+        template.set(key.into(), function_template.into());
+    }
+
+    // setProperty()の関数定義
+    {
+        let key = v8::String::new(scope, "setProperty").unwrap();
+        let function_template = v8::FunctionTemplate::new(
+            scope,
+            |scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _rv: v8::ReturnValue| {
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
+                let name = args.get(0).to_rust_string_lossy(scope);
+                let value = args.get(1).to_rust_string_lossy(scope);
+                // 未対応のプロパティは無視する
+                if !property::is_supported(&name) {
+                    return;
+                }
+                if set_style_property(&mut JavaScriptRuntime::document(scope).borrow_mut(), node, &name, &value) {
+                    JavaScriptRuntime::renderer_api(scope).rerender();
+                }
+            },
+        );
+        template.set(key.into(), function_template.into());
+    }
+
+    // removeProperty()の関数定義。削除前の値を返す
+    {
+        let key = v8::String::new(scope, "removeProperty").unwrap();
+        let function_template = v8::FunctionTemplate::new(
+            scope,
+            |scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut rv: v8::ReturnValue| {
+                let node = match to_linked_rust_node(scope, args.this()) {
+                    Some(node) => node,
+                    None => return throw_illegal_invocation(scope),
+                };
+                let name = args.get(0).to_rust_string_lossy(scope);
+                let value = {
+                    let document = JavaScriptRuntime::document(scope);
+                    let mut document = document.borrow_mut();
+                    let value = style_property(&document, node, &name);
+                    set_style_property(&mut document, node, &name, "");
+                    value
+                };
+                let value_v8 = v8::String::new(scope, value.as_str()).unwrap();
+                rv.set(value_v8.into());
+                if !value.is_empty() {
+                    JavaScriptRuntime::renderer_api(scope).rerender();
+                }
+            },
+        );
+        template.set(key.into(), function_template.into());
+    }
+
+    let style = template.new_instance(scope).unwrap();
+    let v8_external = v8::External::new(scope, node_rust.index() as *mut c_void);
+    style.set_internal_field(0, v8_external.into());
+    cache_v8_node(scope, node_rust, style, style_wrappers);
+    style
+}
+
+// style属性の宣言を返す
+fn inline_declarations(document: &Arena, node: NodeId) -> Vec<css::Declaration> {
+    match document[node].node_type {
+        NodeType::Element(ref el) => el
+            .attribute("style")
+            .map(|style| css::parse_declarations(style).0)
+            .unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+// 宣言をstyle属性の値として書き出す
+fn css_text(declarations: &[css::Declaration]) -> String {
+    declarations.iter().map(|d| format!("{};", d)).collect::<Vec<_>>().join(" ")
+}
+
+// style属性のプロパティの値を返す。宣言がなければ空文字列
+fn style_property(document: &Arena, node: NodeId, name: &str) -> String {
+    inline_declarations(document, node)
+        .iter()
+        .rev()
+        .find(|d| d.name == name)
+        .map(|d| d.value.to_string())
+        .unwrap_or_default()
+}
+
+// style属性のプロパティを設定し、style属性が変わったかどうかを返す
+// 空文字列は宣言の削除、不正な値は無視する
+fn set_style_property(document: &mut Arena, node: NodeId, name: &str, value: &str) -> bool {
+    let before = inline_declarations(document, node);
+    let mut declarations = before.clone();
+    declarations.retain(|d| d.name != name);
+    if !value.trim().is_empty() {
+        match css::parse_declaration(&format!("{}: {}", name, value)) {
+            Some(declaration) => declarations.push(declaration),
+            None => return false,
+        }
+    }
+    let style = css_text(&declarations);
+    if style == css_text(&before) {
+        return false;
+    }
+    set_style_attribute(document, node, style);
+    true
+}
+
+fn set_style_attribute(document: &mut Arena, node: NodeId, style: String) {
+    if let NodeType::Element(ref mut el) = document[node].node_type {
+        el.set_attribute("style", style.as_str());
+    }
+}
+
+// JavaScriptでのプロパティ名をCSSのプロパティ名に変換する
+// e.g. backgroundColor -> background-color
+fn to_css_property_name(name: &str) -> String {
+    if name.contains('-') {
+        return name.to_string();
+    }
+    let mut css_name = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            css_name.push('-');
+            css_name.push(c.to_ascii_lowercase());
+        } else {
+            css_name.push(c);
+        }
+    }
+    css_name
+}
+
 // DOMを構築する
 pub fn create_document_object<'s>(
     scope:&mut v8::ContextScope<'s, v8::EscapableHandleScope>,
@@ -110,26 +440,29 @@ pub fn create_document_object<'s>(
         let function_template = v8::FunctionTemplate::new(
             scope,
             |scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut return_val: v8::ReturnValue| {
-                let id = args.get(0).to_string(scope).unwrap().to_rust_string_lossy(scope);
+                // Symbolなど文字列に変換できない値なら、変換で投げられた例外をそのまま返す
+                let id = match args.get(0).to_string(scope) {
+                    Some(id) => id.to_rust_string_lossy(scope),
+                    None => return,
+                };
 
-                let document_element = JavaScriptRuntime::document_element(scope);
-                let document_element = &mut document_element.borrow_mut();
-
-                return_val.set(
-                    document_element
+                let element = {
+                    let document = JavaScriptRuntime::document(scope);
+                    let document = document.borrow();
+                    document
                         .get_element_by_id(id.as_str())
                         .and_then(|node| {
-                            if let NodeType::Element(ref mut el) = node.node_type {
-                                let tag_name = el.tag_name.clone();
-                                let attributes = el.attributes();
-                                Some((node, tag_name, attributes))
+                            if let NodeType::Element(ref el) = document[node].node_type {
+                                Some((node, el.tag_name.clone()))
                             } else {
                                 None
                             }
                         })
-                        .and_then(|(node, tag_name, attributes)| {
-                            Some(to_v8_element(scope, tag_name.as_str(), attributes, node).into())
-                        })
+                };
+
+                return_val.set(
+                    element
+                        .map(|(node, tag_name)| to_v8_element(scope, tag_name.as_str(), node).into())
                         .unwrap_or_else(|| v8::undefined(scope).into()),
                 );
             },
@@ -1,11 +1,77 @@
 
-use crate::style::{Display, PropertyMap, StyledNode};
+use crate::style::{Display, PropertyMap, StyledNode, WhiteSpace};
+use crate::css::{CSSValue, Viewport, CELL_HEIGHT_PX, CELL_WIDTH_PX};
 use crate::dom::NodeType;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 #[derive(Debug, PartialEq)]
 pub struct LayoutBox<'a> {
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    // `layout`で計算されるまではすべて0
+    pub dimensions: Dimensions,
+    // テキストのボックスの場合、行に配置された断片
+    pub fragments: Vec<TextFragment>,
+}
+
+/// `TextFragment` is the part of a text placed on a line.
+/// A text broken into lines has a fragment per line.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextFragment {
+    pub rect: Rect,
+    pub text: String,
+}
+
+/// `Dimensions` represents the geometry of a box in character cells.
+/// See https://www.w3.org/TR/CSS2/box.html#box-dimensions
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Dimensions {
+    // 内容領域の位置と大きさ。位置は画面の左上からの相対位置
+    pub content: Rect,
+    pub padding: EdgeSizes,
+    pub border: EdgeSizes,
+    pub margin: EdgeSizes,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct EdgeSizes {
+    pub top: i64,
+    pub right: i64,
+    pub bottom: i64,
+    pub left: i64,
+}
+
+impl Rect {
+    fn expanded_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x - edge.left,
+            y: self.y - edge.top,
+            width: self.width + edge.left + edge.right,
+            height: self.height + edge.top + edge.bottom,
+        }
+    }
+}
+
+impl Dimensions {
+    pub fn padding_box(self) -> Rect {
+        self.content.expanded_by(self.padding)
+    }
+
+    pub fn border_box(self) -> Rect {
+        self.padding_box().expanded_by(self.border)
+    }
+
+    pub fn margin_box(self) -> Rect {
+        self.border_box().expanded_by(self.margin)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -44,6 +110,10 @@ pub fn to_layout_box<'a>(snode: StyledNode<'a>) -> LayoutBox<'a> {
     let children = snode.children
         .into_iter()
         .fold(vec![], |mut acc: Vec<LayoutBox>, child| {
+            // コメントやDOCTYPEはボックスを生成しない
+            if !matches!(child.node_type, NodeType::Element(_) | NodeType::Text(_)) {
+                return acc;
+            }
             match child.display() {
                 // 
                 Display::Block => {
@@ -59,6 +129,8 @@ pub fn to_layout_box<'a>(snode: StyledNode<'a>) -> LayoutBox<'a> {
                         _ => acc.push(LayoutBox {
                             box_type: BoxType::AnonymousBox,
                             children: vec![],
+                            dimensions: Dimensions::default(),
+                            fragments: vec![],
                         }),
                     };
                     acc.last_mut().unwrap().children.push(to_layout_box(child));
@@ -70,13 +142,416 @@ pub fn to_layout_box<'a>(snode: StyledNode<'a>) -> LayoutBox<'a> {
 
     LayoutBox {
         box_type: box_type,
-        children: children
+        children: children,
+        dimensions: Dimensions::default(),
+        fragments: vec![],
+    }
+}
+
+/// `layout` computes the dimensions of the box and its descendants, placing the root at the top left of the viewport.
+pub fn layout(root: &mut LayoutBox, viewport: Viewport) {
+    let mut containing_block = Dimensions::default();
+    containing_block.content.width = viewport.width as i64;
+    root.layout(containing_block, viewport);
+}
+
+impl<'a> LayoutBox<'a> {
+    fn properties(&self) -> Option<&PropertyMap> {
+        match self.box_type {
+            BoxType::BlockBox(ref props) | BoxType::InlineBox(ref props) => Some(&props.properties),
+            BoxType::AnonymousBox => None,
+        }
+    }
+
+    /// `layout` lays out the box in the containing block, below the boxes already placed in it.
+    /// The contents of an anonymous box are laid out in lines, and the others are stacked as blocks.
+    ///
+    /// NOTE: margins do not collapse.
+    /// See https://www.w3.org/TR/CSS2/visuren.html#block-formatting
+    fn layout(&mut self, containing_block: Dimensions, viewport: Viewport) {
+        self.calculate_width(containing_block, viewport);
+        self.calculate_position(containing_block, viewport);
+
+        if let BoxType::AnonymousBox = self.box_type {
+            self.layout_inline(viewport);
+        } else {
+            let mut dimensions = self.dimensions;
+            for child in &mut self.children {
+                child.layout(dimensions, viewport);
+                dimensions.content.height += child.dimensions.margin_box().height;
+            }
+            self.dimensions.content.height = dimensions.content.height;
+        }
+
+        self.calculate_height(containing_block, viewport);
+    }
+
+    /// `layout_inline` breaks the texts in the box into lines which fit its width.
+    /// A line is a row of cells, and a block inside an inline box is placed between lines.
+    /// See https://www.w3.org/TR/CSS2/visuren.html#inline-formatting
+    fn layout_inline(&mut self, viewport: Viewport) {
+        let content = self.dimensions.content;
+        let mut leaves = vec![];
+        for child in &mut self.children {
+            inline_leaves(child, &mut leaves);
+        }
+
+        let mut lines = LineBreaker {
+            content,
+            y: 0,
+            x: 0,
+            has_content: false,
+            pending: vec![],
+        };
+        for i in 0..leaves.len() {
+            let text = match leaves[i].box_type {
+                BoxType::InlineBox(BoxProps { node_type: NodeType::Text(ref text), ref properties }) => {
+                    Some((text.data.clone(), WhiteSpace::from_properties(properties)))
+                },
+                _ => None,
+            };
+            match text {
+                Some((text, white_space)) => {
+                    for segment in segments(&text, white_space) {
+                        lines.place(&mut leaves, i, segment, white_space);
+                    }
+                },
+                None => {
+                    lines.break_line();
+                    let containing_block = Dimensions {
+                        content: Rect { height: lines.y, ..content },
+                        ..Default::default()
+                    };
+                    leaves[i].layout(containing_block, viewport);
+                    lines.y += leaves[i].dimensions.margin_box().height;
+                },
+            }
+        }
+        lines.break_line();
+        self.dimensions.content.height = lines.y;
+    }
+
+    /// `calculate_width` resolves `width` and the horizontal margins, borders and paddings.
+    /// See https://www.w3.org/TR/CSS2/visudet.html#blockwidth
+    fn calculate_width(&mut self, containing_block: Dimensions, viewport: Viewport) {
+        let cb_width = containing_block.content.width;
+        let length = |name: &str| self.properties().and_then(|props| props.get(name));
+        let resolve = |name: &str| to_cells(length(name), cb_width, CELL_WIDTH_PX, viewport);
+
+        let width = resolve("width");
+        let mut margin_left = resolve("margin-left");
+        let mut margin_right = resolve("margin-right");
+        let border_left = to_border_cells(length("border-left-width"), CELL_WIDTH_PX, viewport);
+        let border_right = to_border_cells(length("border-right-width"), CELL_WIDTH_PX, viewport);
+        let padding_left = resolve("padding-left").unwrap_or(0);
+        let padding_right = resolve("padding-right").unwrap_or(0);
+
+        // `auto`はNoneで表される
+        let total = [width, margin_left, margin_right]
+            .iter()
+            .map(|v| v.unwrap_or(0))
+            .sum::<i64>()
+            + border_left
+            + border_right
+            + padding_left
+            + padding_right;
+
+        // 幅が指定されていて収まらない場合、autoのマージンは0になる
+        if width.is_some() && total > cb_width {
+            margin_left = margin_left.or(Some(0));
+            margin_right = margin_right.or(Some(0));
+        }
+
+        let underflow = cb_width - total;
+        let (width, margin_left, margin_right) = match (width, margin_left, margin_right) {
+            // 過剰指定の場合は右マージンで調整する
+            (Some(width), Some(left), Some(right)) => (width, left, right + underflow),
+            (Some(width), None, Some(right)) => (width, underflow, right),
+            (Some(width), Some(left), None) => (width, left, underflow),
+            // 左右ともautoなら中央寄せ
+            (Some(width), None, None) => (width, underflow / 2, underflow - underflow / 2),
+            (None, left, right) => {
+                let (left, right) = (left.unwrap_or(0), right.unwrap_or(0));
+                if underflow >= 0 {
+                    (underflow, left, right)
+                } else {
+                    // 幅は負にならず、右マージンで調整する
+                    (0, left, right + underflow)
+                }
+            },
+        };
+
+        let d = &mut self.dimensions;
+        d.content.width = width;
+        d.margin.left = margin_left;
+        d.margin.right = margin_right;
+        d.border.left = border_left;
+        d.border.right = border_right;
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+    }
+
+    /// `calculate_position` resolves the vertical margins, borders and paddings and places the box.
+    fn calculate_position(&mut self, containing_block: Dimensions, viewport: Viewport) {
+        let cb_width = containing_block.content.width;
+        let length = |name: &str| self.properties().and_then(|props| props.get(name));
+        // 上下のパーセンテージも包含ブロックの幅が基準になる
+        let resolve = |name: &str| to_cells(length(name), cb_width, CELL_HEIGHT_PX, viewport).unwrap_or(0);
+
+        let margin_top = resolve("margin-top");
+        let margin_bottom = resolve("margin-bottom");
+        let border_top = to_border_cells(length("border-top-width"), CELL_HEIGHT_PX, viewport);
+        let border_bottom = to_border_cells(length("border-bottom-width"), CELL_HEIGHT_PX, viewport);
+        let padding_top = resolve("padding-top");
+        let padding_bottom = resolve("padding-bottom");
+
+        let d = &mut self.dimensions;
+        d.margin.top = margin_top;
+        d.margin.bottom = margin_bottom;
+        d.border.top = border_top;
+        d.border.bottom = border_bottom;
+        d.padding.top = padding_top;
+        d.padding.bottom = padding_bottom;
+
+        d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+        d.content.y = containing_block.content.y
+            + containing_block.content.height
+            + d.margin.top
+            + d.border.top
+            + d.padding.top;
+        d.content.height = 0;
+    }
+
+    /// `calculate_height` applies `height` if it is specified, and otherwise keeps the height of the contents.
+    fn calculate_height(&mut self, containing_block: Dimensions, viewport: Viewport) {
+        let height = self.properties().and_then(|props| props.get("height"));
+        // パーセンテージは包含ブロックの高さが決まっていないので無視する
+        if !matches!(height, Some(CSSValue::Percentage(_))) {
+            if let Some(height) = to_cells(height, containing_block.content.width, CELL_HEIGHT_PX, viewport) {
+                self.dimensions.content.height = height.max(0);
+            }
+        }
+    }
+}
+
+/// `inline_leaves` collects the texts and the blocks in an inline formatting context in order.
+fn inline_leaves<'a, 'b>(layout_box: &'b mut LayoutBox<'a>, leaves: &mut Vec<&'b mut LayoutBox<'a>>) {
+    match layout_box.box_type {
+        BoxType::BlockBox(_) | BoxType::InlineBox(BoxProps { node_type: NodeType::Text(_), .. }) => {
+            leaves.push(layout_box)
+        },
+        BoxType::InlineBox(_) | BoxType::AnonymousBox => {
+            for child in &mut layout_box.children {
+                inline_leaves(child, leaves);
+            }
+        },
+    }
+}
+
+/// `Segment` is a unit of a text for line breaking.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    // 改行できない文字の並び。全角文字は1文字ずつになる
+    Word(String),
+    Space(String),
+    Tab,
+    Newline,
+}
+
+/// `collapse_spaces` replaces each run of spaces, tabs and newlines with a single space
+/// unless `white_space` preserves them.
+/// See https://www.w3.org/TR/css-text-3/#white-space-phase-1
+pub fn collapse_spaces(text: &str, white_space: WhiteSpace) -> String {
+    if white_space.preserves() {
+        return text.to_string();
+    }
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.chars() {
+        if is_white_space(c) {
+            if !collapsed.ends_with(' ') {
+                collapsed.push(' ');
+            }
+        } else {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+fn is_white_space(c: char) -> bool {
+    matches!(c, ' ' | '\n' | '\t' | '\r' | '\x0c')
+}
+
+/// `segments` splits a text at break opportunities.
+/// Lines can be broken at spaces and around wide characters such as CJK ideographs.
+/// See https://www.w3.org/TR/css-text-3/#line-breaking
+fn segments(text: &str, white_space: WhiteSpace) -> Vec<Segment> {
+    let text = collapse_spaces(text, white_space);
+    let mut segments = vec![];
+    let mut word = String::new();
+    let mut space = String::new();
+    for c in text.chars() {
+        let segment = match c {
+            '\n' if white_space.preserves() => Some(Segment::Newline),
+            '\t' if white_space.preserves() => Some(Segment::Tab),
+            c if is_white_space(c) => {
+                if !word.is_empty() {
+                    segments.push(Segment::Word(std::mem::take(&mut word)));
+                }
+                space.push(' ');
+                continue;
+            },
+            c if c.width() == Some(2) => Some(Segment::Word(c.to_string())),
+            c => {
+                if !space.is_empty() {
+                    segments.push(Segment::Space(std::mem::take(&mut space)));
+                }
+                word.push(c);
+                continue;
+            },
+        };
+        if !word.is_empty() {
+            segments.push(Segment::Word(std::mem::take(&mut word)));
+        }
+        if !space.is_empty() {
+            segments.push(Segment::Space(std::mem::take(&mut space)));
+        }
+        segments.extend(segment);
+    }
+    if !word.is_empty() {
+        segments.push(Segment::Word(word));
+    }
+    if !space.is_empty() {
+        segments.push(Segment::Space(space));
+    }
+    segments
+}
+
+/// `LineBreaker` places segments from left to right, starting a new line when a segment does not fit.
+struct LineBreaker {
+    // 行を並べる領域
+    content: Rect,
+    // 現在の行の位置
+    y: i64,
+    x: i64,
+    has_content: bool,
+    // 次の単語と同じ行に置かれる場合にだけ配置する空白と、それが詰められるかどうか
+    pending: Vec<(usize, String, bool)>,
+}
+
+impl LineBreaker {
+    fn place(&mut self, leaves: &mut [&mut LayoutBox], leaf: usize, segment: Segment, white_space: WhiteSpace) {
+        match segment {
+            Segment::Word(word) => {
+                let pending = self.pending.iter().map(|(_, space, _)| space.width() as i64).sum::<i64>();
+                let width = word.width() as i64;
+                // 行頭に収まらない単語は、はみ出させる
+                if white_space.wraps() && self.has_content && self.x + pending + width > self.content.width {
+                    self.break_line();
+                }
+                for (owner, space, _) in std::mem::take(&mut self.pending) {
+                    self.put(leaves, owner, space);
+                }
+                self.put(leaves, leaf, word);
+                self.has_content = true;
+            },
+            Segment::Space(space) => {
+                if white_space.preserves() {
+                    self.pending.push((leaf, space, false));
+                    return;
+                }
+                // 行頭の空白と、インライン要素の境界をまたいで続く空白は取り除く
+                // See https://www.w3.org/TR/css-text-3/#white-space-phase-2
+                let collapsed = self.pending.last().is_some_and(|&(_, _, collapsible)| collapsible);
+                if self.has_content && !collapsed {
+                    self.pending.push((leaf, space, true));
+                }
+            },
+            Segment::Tab => {
+                // タブは8文字ごとの位置まで進める
+                let pending = self.pending.iter().map(|(_, space, _)| space.width()).sum::<usize>();
+                let x = self.x as usize + pending;
+                self.pending.push((leaf, " ".repeat(8 - x % 8), false));
+            },
+            Segment::Newline => {
+                // 空行も1行として数える
+                self.has_content = true;
+                self.break_line();
+            },
+        }
+    }
+
+    /// `put` appends the text to the fragment of the leaf on the current line.
+    fn put(&mut self, leaves: &mut [&mut LayoutBox], leaf: usize, text: String) {
+        let width = text.width() as i64;
+        let rect = Rect {
+            x: self.content.x + self.x,
+            y: self.content.y + self.y,
+            width,
+            height: 1,
+        };
+        self.x += width;
+
+        let fragments = &mut leaves[leaf].fragments;
+        match fragments.last_mut() {
+            Some(last) if last.rect.y == rect.y && last.rect.x + last.rect.width == rect.x => {
+                last.rect.width += width;
+                last.text.push_str(&text);
+            },
+            _ => fragments.push(TextFragment { rect, text }),
+        }
+    }
+
+    /// `break_line` ends the current line if it has contents. Spaces at the end of the line are dropped.
+    fn break_line(&mut self) {
+        self.pending.clear();
+        if self.has_content {
+            self.y += 1;
+            self.x = 0;
+            self.has_content = false;
+        }
+    }
+}
+
+/// `MAX_CELLS` bounds the lengths in cells, so that adding up the edges of nested boxes does not overflow.
+const MAX_CELLS: f64 = 1e9;
+
+/// `round_cells` rounds a length in cells and clamps it into `-MAX_CELLS..=MAX_CELLS`.
+fn round_cells(cells: f64) -> i64 {
+    cells.round().clamp(-MAX_CELLS, MAX_CELLS) as i64
+}
+
+/// `to_cells` converts a length into the number of cells. `auto` and other keywords are `None`.
+/// Percentages refer to `percentage_base`, which is already in cells.
+fn to_cells(value: Option<&CSSValue>, percentage_base: i64, cell_px: f64, viewport: Viewport) -> Option<i64> {
+    match value? {
+        CSSValue::Length(value, unit) => Some(round_cells(unit.to_px(*value, &viewport) / cell_px)),
+        CSSValue::Percentage(value) => Some(round_cells(percentage_base as f64 * value / 100.0)),
+        CSSValue::Integer(0) => Some(0),
+        _ => None,
+    }
+}
+
+/// `to_border_cells` converts a border width into the number of cells.
+/// A border cannot be thinner than a cell, so any visible border takes at least one.
+fn to_border_cells(value: Option<&CSSValue>, cell_px: f64, viewport: Viewport) -> i64 {
+    match value {
+        Some(CSSValue::Keyword(k)) if matches!(k.as_str(), "thin" | "medium" | "thick") => 1,
+        Some(CSSValue::Length(value, unit)) if *value > 0.0 => {
+            round_cells(unit.to_px(*value, &viewport) / cell_px).max(1)
+        },
+        _ => 0,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{css::CSSValue, dom::Element};
+    use crate::{
+        css::{self, CSSValue},
+        dom::Element,
+        html,
+        style::to_styled_node,
+    };
 
     use super::*;
 
@@ -91,10 +566,7 @@ mod tests {
             CSSValue::Keyword("inline".to_string()),
         )];
 
-        let node = NodeType::Element(Element {
-            tag_name: "div".into(),
-            attributes: [].iter().cloned().collect(),
-        });
+        let node = NodeType::Element(Element::new("div", [].iter().cloned().collect()));
         let snode = StyledNode {
             node_type: &node,
             properties: block.iter().cloned().collect(),
@@ -140,28 +612,38 @@ mod tests {
                     node_type: &node,
                     properties: block.iter().cloned().collect(),
                 }),
+                dimensions: Dimensions::default(),
+                fragments: vec![],
                 children: vec![
                     LayoutBox {
                         box_type: BoxType::BlockBox(BoxProps {
                             node_type: &node,
                             properties: block.iter().cloned().collect(),
                         }),
+                        dimensions: Dimensions::default(),
+                        fragments: vec![],
                         children: vec![],
                     },
                     LayoutBox {
                         box_type: BoxType::AnonymousBox,
+                        dimensions: Dimensions::default(),
+                        fragments: vec![],
                         children: vec![
                             LayoutBox {
                                 box_type: BoxType::InlineBox(BoxProps {
                                     node_type: &node,
                                     properties: inline.iter().cloned().collect(),
                                 }),
+                                dimensions: Dimensions::default(),
+                                fragments: vec![],
                                 children: vec![
                                     LayoutBox {
                                         box_type: BoxType::BlockBox(BoxProps {
                                             node_type: &node,
                                             properties: block.iter().cloned().collect(),
                                         }),
+                                        dimensions: Dimensions::default(),
+                                        fragments: vec![],
                                         children: vec![],
                                     },
                                     LayoutBox {
@@ -169,6 +651,8 @@ mod tests {
                                             node_type: &node,
                                             properties: block.iter().cloned().collect(),
                                         }),
+                                        dimensions: Dimensions::default(),
+                                        fragments: vec![],
                                         children: vec![],
                                     }
                                 ],
@@ -178,6 +662,8 @@ mod tests {
                                     node_type: &node,
                                     properties: inline.iter().cloned().collect(),
                                 }),
+                                dimensions: Dimensions::default(),
+                                fragments: vec![],
                                 children: vec![],
                             }
                         ]
@@ -187,10 +673,170 @@ mod tests {
                             node_type: &node,
                             properties: block.iter().cloned().collect(),
                         }),
+                        dimensions: Dimensions::default(),
+                        fragments: vec![],
                         children: vec![],
                     }
                 ],
             }
         );
     }
+
+    #[test]
+    fn test_layout() {
+        let arena = html::parse(
+            r#"<div id="outer"><p id="centered">a</p><p id="spaced">b</p><div id="wide"></div><p id="fixed"></p></div>"#,
+        );
+        let stylesheet = css::parse(
+            r#"html, body, div, p { display: block; }
+            head { display: none; }
+            #outer { margin: 1ch 2ch; padding: 1em 1ch; border: 1px solid; }
+            #centered { width: 50%; margin: 0 auto; }
+            #spaced { margin-left: 3ch; padding-right: 2ch; }
+            #wide { width: 200ch; margin: 0 auto; }
+            #fixed { width: 10ch; height: 3em; margin-left: auto; }"#,
+        );
+        let root = arena.document_element().unwrap();
+        let styled_node = to_styled_node(&arena, root, &[stylesheet]).unwrap();
+        let mut layout_box = to_layout_box(styled_node);
+        layout(&mut layout_box, Viewport { width: 80, height: 24 });
+
+        let outer = &layout_box.children[0].children[0];
+        let d = outer.dimensions;
+        assert_eq!(d.margin, EdgeSizes { top: 1, right: 2, bottom: 1, left: 2 });
+        assert_eq!(d.border, EdgeSizes { top: 1, right: 1, bottom: 1, left: 1 });
+        assert_eq!(d.padding, EdgeSizes { top: 1, right: 1, bottom: 1, left: 1 });
+        // 80 - (2 + 1 + 1) * 2
+        assert_eq!(d.content, Rect { x: 4, y: 3, width: 72, height: 1 + 1 + 0 + 3 });
+        assert_eq!(d.margin_box(), Rect { x: 0, y: 0, width: 80, height: 11 });
+
+        // auto margins center the box
+        let centered = outer.children[0].dimensions;
+        assert_eq!(centered.content, Rect { x: 4 + 18, y: 3, width: 36, height: 1 });
+        assert_eq!((centered.margin.left, centered.margin.right), (18, 18));
+
+        // auto width fills the rest
+        let spaced = outer.children[1].dimensions;
+        assert_eq!(spaced.content, Rect { x: 4 + 3, y: 4, width: 72 - 3 - 2, height: 1 });
+
+        // auto margins of an overflowing box become 0
+        let wide = outer.children[2].dimensions;
+        assert_eq!((wide.margin.left, wide.content.width, wide.margin.right), (0, 200, -128));
+
+        let fixed = outer.children[3].dimensions;
+        assert_eq!(fixed.content, Rect { x: 4 + 62, y: 5, width: 10, height: 3 });
+    }
+
+    #[test]
+    fn test_huge_lengths() {
+        let arena = html::parse("<div></div><div></div>");
+        let stylesheet = css::parse(
+            "html, body, div { display: block; } head { display: none; }
+            div { height: 1e300px; width: 1e12px; padding: 1e12px 1e300%; border: 1e300px solid; margin: -1e300px 1e300px; }",
+        );
+        let root = arena.document_element().unwrap();
+        let mut layout_box = to_layout_box(to_styled_node(&arena, root, &[stylesheet]).unwrap());
+        layout(&mut layout_box, Viewport { width: 80, height: 24 });
+
+        // lengths are clamped instead of overflowing
+        let div = layout_box.children[0].children[0].dimensions;
+        assert_eq!(div.content.height, MAX_CELLS as i64);
+        assert_eq!(div.margin_box().width > 0, true);
+        assert_eq!(layout_box.dimensions.margin_box().height > 0, true);
+    }
+
+    /// `lines` lays out the body of the document and returns the texts placed on each line.
+    fn lines(html: &str, css: &str, width: usize) -> Vec<String> {
+        fn collect(layout_box: &LayoutBox, fragments: &mut Vec<TextFragment>) {
+            fragments.extend(layout_box.fragments.iter().cloned());
+            for child in &layout_box.children {
+                collect(child, fragments);
+            }
+        }
+
+        let arena = html::parse(html);
+        let stylesheet = css::parse(&format!("html, body, div, p {{ display: block; }} head {{ display: none; }} {}", css));
+        let root = arena.document_element().unwrap();
+        let mut layout_box = to_layout_box(to_styled_node(&arena, root, &[stylesheet]).unwrap());
+        layout(&mut layout_box, Viewport { width, height: 24 });
+
+        let mut fragments = vec![];
+        collect(&layout_box, &mut fragments);
+        let height = layout_box.dimensions.content.height as usize;
+        let mut lines = vec![String::new(); height];
+        for fragment in fragments {
+            let line = &mut lines[fragment.rect.y as usize];
+            while line.width() < fragment.rect.x as usize {
+                line.push('_');
+            }
+            line.push_str(&fragment.text);
+        }
+        lines
+    }
+
+    #[test]
+    fn test_inline_layout() {
+        // lines are broken at spaces, and inline elements flow with the text
+        assert_eq!(
+            lines("<p>The quick <b>brown fox jumps</b> over the lazy dog</p>", "", 16),
+            vec!["The quick brown", "fox jumps over", "the lazy dog"]
+        );
+        // a word longer than the line overflows
+        assert_eq!(lines("<p>a supercalifragilistic b</p>", "", 8), vec!["a", "supercalifragilistic", "b"]);
+        // a block in an inline box is placed between lines
+        assert_eq!(
+            lines("<p>one <span>two<div>three</div>four</span></p>", "", 80),
+            vec!["one two", "three", "four"]
+        );
+        // padding moves the lines
+        assert_eq!(lines("<p>a b</p>", "p { padding-left: 2ch; }", 3), vec!["__a", "__b"]);
+    }
+
+    #[test]
+    fn test_white_space() {
+        let html = "<p>a  b\tc\n\nd e</p>";
+        assert_eq!(lines(html, "", 4), vec!["a b", "c d", "e"]);
+        assert_eq!(lines(html, "p { white-space: nowrap; }", 4), vec!["a b c d e"]);
+        assert_eq!(lines(html, "p { white-space: pre; }", 4), vec!["a  b    c", "", "d e"]);
+        assert_eq!(lines(html, "p { white-space: pre-wrap; }", 4), vec!["a  b", "c", "", "d e"]);
+    }
+
+    #[test]
+    fn test_collapse_spaces() {
+        // spaces at the start and the end of lines are removed, and a single space is kept across elements
+        assert_eq!(
+            lines("<p>\n  one <b> two </b>  <i>three</i>\n</p>", "", 80),
+            vec!["one two three"]
+        );
+        assert_eq!(lines("<p><b>a</b> <i>b</i><b> </b></p>", "", 80), vec!["a b"]);
+        assert_eq!(lines("<p>one  two three</p>", "", 7), vec!["one two", "three"]);
+        // whitespace between blocks produces no lines
+        assert_eq!(lines("<div>\n  <p>a</p>\n  <p>b</p>\n</div>", "", 80), vec!["a", "b"]);
+        // preserved spaces are not collapsed across elements
+        assert_eq!(
+            lines("<p>  a <b> b</b></p>", "p { white-space: pre-wrap; }", 80),
+            vec!["  a  b"]
+        );
+        assert_eq!(collapse_spaces(" a \n\t b ", WhiteSpace::Normal), " a b ");
+        assert_eq!(collapse_spaces(" a \n\t b ", WhiteSpace::Pre), " a \n\t b ");
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        // CJK characters take two cells and lines can be broken between them
+        assert_eq!(
+            lines("<p>日本語の文章は折り返せる</p>", "", 10),
+            vec!["日本語の文", "章は折り返", "せる"]
+        );
+        assert_eq!(lines("<p>abc漢字def</p>", "", 6), vec!["abc漢", "字def"]);
+        assert_eq!(
+            segments("ab 漢字", WhiteSpace::Normal),
+            vec![
+                Segment::Word("ab".to_string()),
+                Segment::Space(" ".to_string()),
+                Segment::Word("漢".to_string()),
+                Segment::Word("字".to_string()),
+            ]
+        );
+    }
 }
@@ -3,6 +3,11 @@ pub mod dom;
 pub mod html;
 pub mod style;
 pub mod layout;
-pub mod render;
+pub mod paint;
 pub mod javascript;
-pub mod renderer;
\ No newline at end of file
+pub mod renderer;
+pub mod source;
+pub mod error;
+pub mod headless;
+pub mod inspector;
+pub mod cli;
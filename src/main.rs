@@ -1,8 +1,10 @@
-use std::rc::Rc;
+use std::{fmt::Display, process, rc::Rc};
 
-use cursive::theme::Theme;
+use cursive::{theme::Theme, views::Dialog};
 use exercise_rendering_tree::{
-    html,
+    cli::{self, Options},
+    css::Viewport,
+    headless,
     renderer::Renderer
 };
 
@@ -30,6 +32,42 @@ const HTML: &str = r#"<body>
 
 
 fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        },
+    };
+    if options.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+    if let Err(message) = run(options) {
+        eprintln!("error: {}", message);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let (document, parse_errors) = cli::read_document(&options.input, HTML)?;
+    let user_stylesheet = options
+        .user_stylesheet
+        .as_deref()
+        .map(cli::read_user_stylesheet)
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    // --headlessが指定された場合は、端末を使わずに標準出力に書き出す
+    if let Some(format) = options.headless {
+        let viewport = Viewport { width: options.width, height: options.height };
+        let renderer = headless::render(document, parse_errors, user_stylesheet, viewport, options.scripts)
+            .map_err(|e| e.to_string())?;
+        print_diagnostics(renderer.diagnostics());
+        print!("{}", headless::format(&renderer.canvas, format));
+        return Ok(());
+    }
+
     let mut siv = cursive::default();
     let mut palette= siv.current_theme().palette.clone();
     palette.set_color("background", cursive::theme::Color::TerminalDefault);
@@ -41,17 +79,38 @@ fn main() {
     };
     siv.set_theme(theme);
 
-    let node = html::parse(HTML);
-
     // Rendererを生成する
-    let mut renderer = Renderer::new(Rc::new(siv.cb_sink().clone()), node);
+    // 端末全体に表示するので、最初のlayoutで描き直さずに済むよう端末の大きさで描く
+    let size = siv.screen_size();
+    let viewport = Viewport { width: size.x, height: size.y };
+    let mut renderer = Renderer::new(Rc::new(siv.cb_sink().clone()), document, viewport, user_stylesheet)
+        .map_err(|e| e.to_string())?;
+    renderer.set_parse_errors(parse_errors);
+
+    // inline JavaScriptを実行してから描画する
+    let result = if options.scripts { renderer.execute_inline_scripts() } else { Ok(()) };
+    renderer.rerender();
 
-    // inline JavaScriptを実行する
-    renderer.execute_inline_scripts();
+    // 端末のUIを抜けてから標準エラー出力に書き出すため、ここで控えておく
+    let diagnostics = renderer.diagnostics().map(|e| e.to_string()).collect::<Vec<_>>();
 
     // Cursiveによる描画を開始する
     // siv.add_fullscreen_layer(renderer.view);
     siv.add_fullscreen_layer(renderer);
 
+    // スクリプトが失敗しても、そこまでの結果を表示した上でエラーを知らせる
+    if let Err(e) = result {
+        siv.add_layer(Dialog::info(e.to_string()).title("Error"));
+    }
+
     siv.run();
+    print_diagnostics(diagnostics);
+    Ok(())
+}
+
+/// `print_diagnostics` prints the problems the page was rendered with to the standard error.
+fn print_diagnostics(diagnostics: impl IntoIterator<Item = impl Display>) {
+    for diagnostic in diagnostics {
+        eprintln!("warning: {}", diagnostic);
+    }
 }
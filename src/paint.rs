@@ -0,0 +1,230 @@
+//! This module implements the paint stage, which turns laid-out boxes into a display list.
+//!
+//! The display list is rasterized into a grid of terminal cells by `canvas`.
+//! See https://www.w3.org/TR/CSS2/zindex.html
+
+use crate::{
+    css::{CSSValue, Color},
+    dom::NodeType,
+    layout::{BoxProps, BoxType, EdgeSizes, LayoutBox, Rect, TextFragment},
+    style::PropertyMap,
+};
+
+pub mod canvas;
+
+/// `DisplayCommand` represents a drawing operation in character cells.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DisplayCommand {
+    FillRect {
+        rect: Rect,
+        color: Color,
+    },
+    // `rect`はボーダーボックス。幅が0でない辺に罫線を描く
+    Border {
+        rect: Rect,
+        widths: EdgeSizes,
+        color: Option<Color>,
+    },
+    Text {
+        x: i64,
+        y: i64,
+        text: String,
+        // Noneの場合は端末の既定の色
+        color: Option<Color>,
+        style: TextStyle,
+    },
+}
+
+pub type DisplayList = Vec<DisplayCommand>;
+
+/// `TextStyle` holds the attributes a terminal can show.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub line_through: bool,
+}
+
+/// `build_display_list` paints the boxes in tree order, i.e. the backgrounds and borders of a box come before its contents.
+///
+/// NOTE: borders are drawn in `color`, as the initial value of `border-color` is `currentcolor`.
+pub fn build_display_list(layout_box: &LayoutBox) -> DisplayList {
+    let mut list = vec![];
+    paint(&mut list, layout_box, TextStyle::default());
+    list
+}
+
+fn paint(list: &mut DisplayList, layout_box: &LayoutBox, decorations: TextStyle) {
+    let properties = match layout_box.box_type {
+        BoxType::BlockBox(ref props) | BoxType::InlineBox(ref props) => Some(&props.properties),
+        BoxType::AnonymousBox => None,
+    };
+
+    // 下線などの装飾は子孫のテキストに伝播する
+    // See https://www.w3.org/TR/css-text-decor-3/#line-decoration
+    let mut decorations = decorations;
+    if let Some(properties) = properties {
+        let decoration = keywords(properties.get("text-decoration"));
+        decorations.underline |= decoration.contains(&"underline");
+        decorations.line_through |= decoration.contains(&"line-through");
+    }
+
+    match layout_box.box_type {
+        BoxType::BlockBox(ref props) if is_visible(&props.properties) => {
+            let rect = layout_box.dimensions.border_box();
+            if let Some(color) = background(&props.properties) {
+                list.push(DisplayCommand::FillRect { rect, color });
+            }
+            let widths = layout_box.dimensions.border;
+            if widths != EdgeSizes::default() {
+                list.push(DisplayCommand::Border {
+                    rect,
+                    widths,
+                    color: color(&props.properties),
+                });
+            }
+        },
+        BoxType::InlineBox(BoxProps {
+            node_type: NodeType::Text(_),
+            ref properties,
+        }) if is_visible(properties) => {
+            for fragment in &layout_box.fragments {
+                list.push(DisplayCommand::Text {
+                    x: fragment.rect.x,
+                    y: fragment.rect.y,
+                    text: fragment.text.clone(),
+                    color: color(properties),
+                    style: text_style(properties, decorations),
+                });
+            }
+        },
+        // インライン要素の背景は、その中のテキストの断片ごとに塗る
+        BoxType::InlineBox(ref props) if is_visible(&props.properties) => {
+            if let Some(color) = background(&props.properties) {
+                let mut fragments = vec![];
+                text_fragments(layout_box, &mut fragments);
+                for fragment in fragments {
+                    list.push(DisplayCommand::FillRect { rect: fragment.rect, color });
+                }
+            }
+        },
+        _ => {},
+    }
+
+    for child in &layout_box.children {
+        paint(list, child, decorations);
+    }
+}
+
+/// `text_fragments` collects the fragments of the texts in the inline box.
+fn text_fragments<'b>(layout_box: &'b LayoutBox, fragments: &mut Vec<&'b TextFragment>) {
+    fragments.extend(layout_box.fragments.iter());
+    for child in &layout_box.children {
+        if !matches!(child.box_type, BoxType::BlockBox(_)) {
+            text_fragments(child, fragments);
+        }
+    }
+}
+
+fn keywords(value: Option<&CSSValue>) -> Vec<&str> {
+    match value {
+        Some(CSSValue::Keyword(k)) => vec![k.as_str()],
+        Some(CSSValue::SpaceList(values)) => values.iter().flat_map(|v| keywords(Some(v))).collect(),
+        _ => vec![],
+    }
+}
+
+fn is_visible(properties: &PropertyMap) -> bool {
+    !matches!(properties.get("visibility"), Some(CSSValue::Keyword(k)) if k == "hidden" || k == "collapse")
+}
+
+fn color(properties: &PropertyMap) -> Option<Color> {
+    properties.get("color").and_then(CSSValue::to_color)
+}
+
+/// `background` returns the background color unless it is transparent.
+fn background(properties: &PropertyMap) -> Option<Color> {
+    properties
+        .get("background-color")
+        .and_then(CSSValue::to_color)
+        .filter(|color| color.a > 0)
+}
+
+fn text_style(properties: &PropertyMap, decorations: TextStyle) -> TextStyle {
+    let bold = match properties.get("font-weight") {
+        Some(CSSValue::Keyword(k)) => k == "bold" || k == "bolder",
+        Some(CSSValue::Integer(weight)) => *weight >= 600,
+        _ => false,
+    };
+    let italic = keywords(properties.get("font-style")).iter().any(|k| *k == "italic" || *k == "oblique");
+    TextStyle {
+        bold,
+        italic,
+        ..decorations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        css::{self, Viewport},
+        html,
+        layout::{layout, to_layout_box},
+        style::to_styled_node,
+    };
+
+    #[test]
+    fn test_build_display_list() {
+        let arena = html::parse(r#"<div id="box">a <u>b <b>c</b></u><span>d</span></div>"#);
+        let stylesheet = css::parse(
+            r#"html, body, div { display: block; } head { display: none; }
+            #box { background-color: navy; border: 1ch solid; color: yellow; width: 20ch; }
+            u { text-decoration: underline; }
+            b { font-weight: bold; }
+            span { background-color: red; visibility: hidden; }"#,
+        );
+        let root = arena.document_element().unwrap();
+        let mut layout_box = to_layout_box(to_styled_node(&arena, root, &[stylesheet]).unwrap());
+        layout(&mut layout_box, Viewport { width: 80, height: 24 });
+
+        let yellow = Color::from_name("yellow");
+        let border_box = Rect { x: 0, y: 0, width: 22, height: 3 };
+        assert_eq!(
+            build_display_list(&layout_box),
+            vec![
+                DisplayCommand::FillRect {
+                    rect: border_box,
+                    color: Color::from_name("navy").unwrap(),
+                },
+                DisplayCommand::Border {
+                    rect: border_box,
+                    widths: EdgeSizes { top: 1, right: 1, bottom: 1, left: 1 },
+                    color: yellow,
+                },
+                DisplayCommand::Text {
+                    x: 1,
+                    y: 1,
+                    text: "a ".to_string(),
+                    color: yellow,
+                    style: TextStyle::default(),
+                },
+                DisplayCommand::Text {
+                    x: 3,
+                    y: 1,
+                    text: "b ".to_string(),
+                    color: yellow,
+                    style: TextStyle { underline: true, ..TextStyle::default() },
+                },
+                DisplayCommand::Text {
+                    x: 5,
+                    y: 1,
+                    text: "c".to_string(),
+                    color: yellow,
+                    style: TextStyle { bold: true, underline: true, ..TextStyle::default() },
+                },
+            ]
+        );
+    }
+}
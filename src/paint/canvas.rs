@@ -0,0 +1,354 @@
+//! This module rasterizes a display list into a grid of terminal cells.
+
+use std::fmt;
+use unicode_width::UnicodeWidthChar;
+
+use super::{DisplayCommand, DisplayList, TextStyle};
+use crate::{css::Color, layout::Rect};
+
+/// `Cell` represents a character cell of a terminal.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Cell {
+    // 全角文字の右半分のセルはNone
+    pub ch: Option<char>,
+    // Noneの場合は端末の既定の色
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub style: TextStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            ch: Some(' '),
+            fg: None,
+            bg: None,
+            style: TextStyle::default(),
+        }
+    }
+}
+
+/// `Canvas` is a grid of cells, which are stored row by row.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Cell>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Cell]> {
+        // 幅が0でもchunksが使えるようにする
+        self.cells.chunks(self.width.max(1)).take(self.height)
+    }
+
+    fn cell_mut(&mut self, x: i64, y: i64) -> Option<&mut Cell> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        self.cells.get_mut(y as usize * self.width + x as usize)
+    }
+
+    /// `clip` returns the cells of the rect which are inside the canvas.
+    fn clip(&self, rect: Rect) -> impl Iterator<Item = (i64, i64)> {
+        let x0 = rect.x.max(0);
+        let y0 = rect.y.max(0);
+        let x1 = (rect.x + rect.width).min(self.width as i64);
+        let y1 = (rect.y + rect.height).min(self.height as i64);
+        (y0..y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+
+    /// `put` writes a character, clearing the other half of a wide character it overwrites.
+    fn put(&mut self, x: i64, y: i64, ch: char, fg: Option<Color>, style: TextStyle) {
+        let width = ch.width().unwrap_or(0) as i64;
+        if width == 0 || x + width > self.width as i64 {
+            return;
+        }
+        for dx in 0..width {
+            match self.cell_mut(x + dx, y).map(|cell| cell.ch) {
+                // 全角文字の右半分を上書きする場合は、左半分を消す
+                Some(None) if dx == 0 => {
+                    if let Some(left) = self.cell_mut(x - 1, y) {
+                        left.ch = Some(' ');
+                    }
+                },
+                // 全角文字の左半分を上書きする場合は、右半分を消す
+                Some(Some(c)) if dx == width - 1 && c.width() == Some(2) => {
+                    if let Some(right) = self.cell_mut(x + dx + 1, y) {
+                        right.ch = Some(' ');
+                    }
+                },
+                _ => {},
+            }
+        }
+        for dx in 0..width {
+            if let Some(cell) = self.cell_mut(x + dx, y) {
+                cell.ch = if dx == 0 { Some(ch) } else { None };
+                cell.fg = fg;
+                cell.style = style;
+            }
+        }
+    }
+
+    pub fn paint(&mut self, command: &DisplayCommand) {
+        match command {
+            DisplayCommand::FillRect { rect, color } => {
+                for (x, y) in self.clip(*rect).collect::<Vec<_>>() {
+                    if let Some(cell) = self.cell_mut(x, y) {
+                        cell.bg = Some(*color);
+                    }
+                }
+            },
+            DisplayCommand::Border { rect, widths, color } => {
+                let (left, right) = (rect.x, rect.x + rect.width - 1);
+                let (top, bottom) = (rect.y, rect.y + rect.height - 1);
+                for (x, y) in self.clip(*rect).collect::<Vec<_>>() {
+                    let on_top = y == top && widths.top > 0;
+                    let on_bottom = y == bottom && widths.bottom > 0;
+                    let on_left = x == left && widths.left > 0;
+                    let on_right = x == right && widths.right > 0;
+                    let ch = match (on_top, on_bottom, on_left, on_right) {
+                        (true, _, true, _) => '┌',
+                        (true, _, _, true) => '┐',
+                        (_, true, true, _) => '└',
+                        (_, true, _, true) => '┘',
+                        (true, _, _, _) | (_, true, _, _) => '─',
+                        (_, _, true, _) | (_, _, _, true) => '│',
+                        _ => continue,
+                    };
+                    self.put(x, y, ch, *color, TextStyle::default());
+                }
+            },
+            DisplayCommand::Text { x, y, text, color, style } => {
+                let mut x = *x;
+                for ch in text.chars() {
+                    self.put(x, *y, ch, *color, *style);
+                    x += ch.width().unwrap_or(0) as i64;
+                }
+            },
+        }
+    }
+
+    /// `to_ansi` prints the canvas with ANSI escape sequences for colors and attributes.
+    /// Every line ends with a reset, so that the lines can be printed separately.
+    pub fn to_ansi(&self) -> String {
+        let mut output = String::new();
+        for row in self.rows() {
+            // 既定のスタイルのまま末尾に続く空白は出力しない
+            let end = row
+                .iter()
+                .rposition(|cell| *cell != Cell::default())
+                .map_or(0, |i| i + 1);
+            let mut current = Cell::default();
+            for cell in &row[..end] {
+                if (cell.fg, cell.bg, cell.style) != (current.fg, current.bg, current.style) {
+                    output.push_str(&sgr(cell));
+                    current = *cell;
+                }
+                output.extend(cell.ch);
+            }
+            if (current.fg, current.bg, current.style) != (None, None, TextStyle::default()) {
+                output.push_str("\x1b[0m");
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// `to_json` prints the canvas as rows of cells like
+    /// `{"width":1,"height":1,"rows":[[{"ch":"a","fg":"#ff0000","bg":null,"bold":false,...}]]}`.
+    /// The right half of a wide character has an empty `ch`.
+    pub fn to_json(&self) -> String {
+        let color = |color: Option<Color>| match color {
+            Some(Color { r, g, b, .. }) => format!("\"#{:02x}{:02x}{:02x}\"", r, g, b),
+            None => "null".to_string(),
+        };
+        let rows = self
+            .rows()
+            .map(|row| {
+                let cells = row
+                    .iter()
+                    .map(|cell| {
+                        format!(
+                            r#"{{"ch":{},"fg":{},"bg":{},"bold":{},"italic":{},"underline":{},"line_through":{}}}"#,
+                            json_string(&cell.ch.map(String::from).unwrap_or_default()),
+                            color(cell.fg),
+                            color(cell.bg),
+                            cell.style.bold,
+                            cell.style.italic,
+                            cell.style.underline,
+                            cell.style.line_through,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("[{}]", cells.join(","))
+            })
+            .collect::<Vec<_>>();
+        format!(r#"{{"width":{},"height":{},"rows":[{}]}}"#, self.width, self.height, rows.join(","))
+    }
+}
+
+/// `rasterize` paints the display list on a new canvas of the given size. Anything outside the canvas is clipped.
+pub fn rasterize(list: &DisplayList, width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    for command in list {
+        canvas.paint(command);
+    }
+    canvas
+}
+
+/// `sgr` returns the escape sequence which resets the attributes and then applies the ones of the cell.
+/// See https://en.wikipedia.org/wiki/ANSI_escape_code#SGR
+fn sgr(cell: &Cell) -> String {
+    let mut params = vec!["0".to_string()];
+    for (enabled, param) in [
+        (cell.style.bold, "1"),
+        (cell.style.italic, "3"),
+        (cell.style.underline, "4"),
+        (cell.style.line_through, "9"),
+    ] {
+        if enabled {
+            params.push(param.to_string());
+        }
+    }
+    if let Some(Color { r, g, b, .. }) = cell.fg {
+        params.push(format!("38;2;{};{};{}", r, g, b));
+    }
+    if let Some(Color { r, g, b, .. }) = cell.bg {
+        params.push(format!("48;2;{};{};{}", r, g, b));
+    }
+    format!("\x1b[{}m", params.join(";"))
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// `Canvas` is printed as plain text, without colors and trailing spaces.
+impl fmt::Display for Canvas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.rows() {
+            let line = row.iter().filter_map(|cell| cell.ch).collect::<String>();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::EdgeSizes;
+
+    fn text(x: i64, y: i64, text: &str) -> DisplayCommand {
+        DisplayCommand::Text {
+            x,
+            y,
+            text: text.to_string(),
+            color: None,
+            style: TextStyle::default(),
+        }
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let red = Color::rgb(255, 0, 0);
+        let canvas = rasterize(
+            &vec![
+                DisplayCommand::FillRect {
+                    rect: Rect { x: 1, y: 0, width: 2, height: 5 },
+                    color: red,
+                },
+                DisplayCommand::Border {
+                    rect: Rect { x: 0, y: 0, width: 6, height: 3 },
+                    widths: EdgeSizes { top: 1, right: 1, bottom: 1, left: 1 },
+                    color: None,
+                },
+                text(1, 1, "日本"),
+                // overflowing text is clipped
+                text(2, 3, "abcdef"),
+                text(-1, 4, "xyz"),
+            ],
+            6,
+            5,
+        );
+        assert_eq!(canvas.to_string(), "┌────┐\n│日本│\n└────┘\n  abcd\nyz\n");
+        assert_eq!(canvas.cells[6 + 1].ch, Some('日'));
+        assert_eq!(canvas.cells[6 + 2].ch, None);
+        assert_eq!(canvas.cells[6 + 2].bg, Some(red));
+        assert_eq!(canvas.cells[6 + 3].bg, None);
+    }
+
+    #[test]
+    fn test_overwrite_wide_characters() {
+        let canvas = rasterize(&vec![text(0, 0, "漢字漢"), text(1, 0, "a"), text(4, 0, "b")], 6, 1);
+        assert_eq!(canvas.to_string(), " a字b\n");
+        // a wide character which does not fit in the row is dropped
+        let canvas = rasterize(&vec![text(0, 0, "a漢字")], 4, 1);
+        assert_eq!(canvas.to_string(), "a漢\n");
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        let canvas = rasterize(
+            &vec![
+                DisplayCommand::FillRect {
+                    rect: Rect { x: 2, y: 0, width: 2, height: 1 },
+                    color: Color::rgb(0, 0, 128),
+                },
+                DisplayCommand::Text {
+                    x: 0,
+                    y: 0,
+                    text: "ab".to_string(),
+                    color: Some(Color::rgb(255, 0, 0)),
+                    style: TextStyle { bold: true, ..TextStyle::default() },
+                },
+                text(0, 1, "c "),
+            ],
+            6,
+            2,
+        );
+        assert_eq!(
+            canvas.to_ansi(),
+            "\x1b[0;1;38;2;255;0;0mab\x1b[0;48;2;0;0;128m  \x1b[0m\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let canvas = rasterize(&vec![text(0, 0, "漢\"")], 3, 1);
+        let cell = |ch: &str| {
+            format!(
+                r#"{{"ch":{},"fg":null,"bg":null,"bold":false,"italic":false,"underline":false,"line_through":false}}"#,
+                ch
+            )
+        };
+        assert_eq!(
+            canvas.to_json(),
+            format!(
+                r#"{{"width":3,"height":1,"rows":[[{},{},{}]]}}"#,
+                cell(r#""漢""#),
+                cell(r#""""#),
+                cell(r#""\"""#)
+            )
+        );
+    }
+}
@@ -1,15 +1,21 @@
 use crate::{
-    css,
-    dom::{Node, NodeType},
+    css::{self, loader::{self, Loader}, MediaQuery, MediaQueryList, Origin, Stylesheet, Viewport},
+    dom::{Arena, NodeId, NodeType},
+    error::Error,
+    html,
+    inspector::{self, NodeInspection},
     javascript::{JavaScriptRuntime},
     javascript::renderapi::RendererAPI,
-    layout::to_layout_box,
-    render::{to_element_container, ElementContainer},
+    layout::{layout, to_layout_box},
+    paint::{build_display_list, canvas::{rasterize, Canvas}},
+    source::SourcePosition,
     style::to_styled_node,
 };
 
 use cursive::{
-    direction::{Direction, Orientation}, event::{AnyCb, Event, EventResult}, view::{CannotFocus, Selector, View, ViewNotFound}, CbSink, Rect, Vec2
+    theme::{ColorStyle, ColorType, Effect, Style},
+    view::View,
+    CbSink, Printer, Vec2,
 };
 
 use std::{
@@ -18,127 +24,345 @@ use std::{
 };
 
 pub struct Renderer {
-    pub view: ElementContainer, // 表示中のView
-    document_element: Rc<RefCell<Box<Node>>>, // DOMツリー
+    pub canvas: Canvas, // 描画結果
+    document: Rc<RefCell<Arena>>, // DOMツリー
     js_runtime_instance: JavaScriptRuntime, // JavaScriptのランタイム
+    viewport: Viewport, // 直近のlayoutで与えられた大きさ
+    user_stylesheet: Option<Stylesheet>, // 利用者のスタイルシート
+    parse_errors: Vec<Error>, // HTMLの構文エラー
+    author_styles: AuthorStyles, // 文書のスタイルシート。変わらない限り読み直さない
 }
 
 const DEFAULT_STYLESHEET: &str = r#"
-script, style {
+head, script, style {
     display: none;
 }
-p, div {
+html, body, p, div, pre {
     display: block;
 }
+html {
+    color: canvastext;
+}
+pre {
+    white-space: pre;
+}
 "#;
 
-pub fn collect_tag_inners(node: &Box<Node>, tag_name: &str) -> Vec<String> {
-    if let NodeType::Element(ref el) = node.node_type {
-        if el.tag_name.as_str() == tag_name {
-            return vec![node.inner_text()];
+/// `MAX_CANVAS_HEIGHT` is the number of rows rasterized at most, so that a huge `height` does not exhaust memory.
+/// The rest of the page is clipped.
+const MAX_CANVAS_HEIGHT: usize = 10_000;
+
+/// `StyleSource` is where an author stylesheet comes from: the text of `<style>` or the `href` of `<link rel="stylesheet">`,
+/// with the `media` attribute.
+#[derive(Debug, PartialEq, Clone)]
+enum StyleSource {
+    Inline {
+        text: String,
+        // 文書中で内容が始まる位置
+        start: Option<SourcePosition>,
+        media: Option<String>,
+    },
+    Link {
+        href: String,
+        media: Option<String>,
+    },
+}
+
+/// `AuthorStyles` is the author stylesheets loaded from `sources`, with the errors found while loading them.
+/// They are kept in `Renderer` so that linked files are not read again unless the sources change.
+#[derive(Default)]
+struct AuthorStyles {
+    url: Option<String>,
+    sources: Vec<StyleSource>,
+    stylesheets: Vec<Stylesheet>,
+    errors: Vec<Error>,
+}
+
+impl AuthorStyles {
+    /// `load` loads the stylesheets of the document.
+    /// Stylesheets which cannot be loaded are skipped, and returned as errors with the parse errors.
+    fn load(document: &Arena) -> AuthorStyles {
+        let mut sources = vec![];
+        collect_style_sources(document, document.document(), &mut sources);
+        let url = document.url().map(str::to_string);
+
+        // 文書のURLが不明な場合は、作業ディレクトリからの相対パスとみなす
+        let base = url.as_deref().and_then(|url| loader::resolve(None, url).ok());
+        let mut loader = Loader::new();
+        let mut stylesheets = vec![];
+        for source in &sources {
+            match source {
+                StyleSource::Inline { text, start, media } => {
+                    let mut stylesheet = loader.load_str(text, base.as_deref());
+                    // 規則の位置を、文書中の位置に直す
+                    stylesheet.locate(url.as_deref(), *start);
+                    stylesheets.push(loader::apply_media(stylesheet, to_media(media.as_deref())));
+                },
+                StyleSource::Link { href, media } => {
+                    if let Some(stylesheet) = loader.load(base.as_deref(), href) {
+                        stylesheets.push(loader::apply_media(stylesheet, to_media(media.as_deref())));
+                    }
+                },
+            }
+        }
+
+        let errors = loader
+            .diagnostics
+            .into_iter()
+            .map(Error::from)
+            .chain(loader.errors.into_iter().map(Error::from))
+            .collect();
+        AuthorStyles { url, sources, stylesheets, errors }
+    }
+
+    /// `update` loads the stylesheets again only if the document has different ones.
+    fn update(&mut self, document: &Arena) {
+        let mut sources = vec![];
+        collect_style_sources(document, document.document(), &mut sources);
+        if sources != self.sources || document.url() != self.url.as_deref() {
+            *self = AuthorStyles::load(document);
         }
     }
+}
+
+/// `to_media` parses the `media` attribute. A missing attribute matches everything, and an invalid one matches nothing.
+/// See https://html.spec.whatwg.org/multipage/common-microsyntaxes.html#valid-media-query-list
+fn to_media(media: Option<&str>) -> MediaQueryList {
+    match media {
+        None => vec![],
+        Some(raw) => css::parse_media(raw).unwrap_or_else(|| {
+            // `not all`として扱う
+            vec![MediaQuery {
+                negated: true,
+                media_type: "all".to_string(),
+                features: vec![],
+            }]
+        }),
+    }
+}
+
+/// `collect_style_sources` collects the sources of `<style>` and `<link rel="stylesheet">` in document order.
+/// See https://html.spec.whatwg.org/multipage/links.html#link-type-stylesheet
+fn collect_style_sources(arena: &Arena, id: NodeId, sources: &mut Vec<StyleSource>) {
+    if let NodeType::Element(ref el) = arena[id].node_type {
+        let media = el.attribute("media").cloned();
+        match el.tag_name.as_str() {
+            "style" => {
+                let start = arena[id].first_child().and_then(|child| arena[child].position());
+                sources.push(StyleSource::Inline {
+                    text: arena.inner_text(id),
+                    start,
+                    media,
+                });
+                return;
+            },
+            "link" => {
+                let is_stylesheet = el
+                    .attribute("rel")
+                    .is_some_and(|rel| rel.split_ascii_whitespace().any(|t| t.eq_ignore_ascii_case("stylesheet")));
+                if let (true, Some(href)) = (is_stylesheet, el.attribute("href")) {
+                    sources.push(StyleSource::Link {
+                        href: href.to_string(),
+                        media,
+                    });
+                }
+            },
+            _ => {},
+        }
+    }
+
+    for child in arena.children(id) {
+        collect_style_sources(arena, child, sources);
+    }
+}
+
+/// `stylesheets` returns the user-agent stylesheet, the user stylesheet if any and the author stylesheets,
+/// in which `@media` rules are evaluated against the viewport.
+fn stylesheets(author: &AuthorStyles, user: Option<&Stylesheet>, viewport: &Viewport) -> Vec<Stylesheet> {
+    let mut user_agent = css::parse(DEFAULT_STYLESHEET);
+    user_agent.origin = Origin::UserAgent;
 
-    node.children
-        .iter()
-        .map(|child| collect_tag_inners(child, tag_name))
-        .collect::<Vec<Vec<String>>>()
-        .into_iter()
-        .flatten()
+    std::iter::once(&user_agent)
+        .chain(user)
+        .chain(author.stylesheets.iter())
+        .map(|stylesheet| stylesheet.resolve_media(viewport))
         .collect()
 }
 
+/// `render` runs style, layout and paint for the viewport.
+/// The canvas is as wide as the viewport and as tall as the document, up to `MAX_CANVAS_HEIGHT` rows.
+fn render(document: &Arena, stylesheets: &[Stylesheet], viewport: &Viewport) -> Canvas {
+    let root = document.document();
+    let mut layout_box = match to_styled_node(document, root, stylesheets).map(to_layout_box) {
+        Some(layout_box) => layout_box,
+        // ルート要素がdisplay: noneなら何も描かない
+        None => return Canvas::new(viewport.width, viewport.height),
+    };
+    layout(&mut layout_box, *viewport);
+
+    let height = (layout_box.dimensions.margin_box().height.max(0) as usize).min(MAX_CANVAS_HEIGHT);
+    rasterize(&build_display_list(&layout_box), viewport.width, height)
+}
+
+/// `collect_scripts` collects the inline scripts in document order, with where their contents start.
+fn collect_scripts(arena: &Arena, id: NodeId, scripts: &mut Vec<(String, Option<SourcePosition>)>) {
+    if let NodeType::Element(ref el) = arena[id].node_type {
+        if el.tag_name == "script" {
+            let position = arena[id].first_child().and_then(|child| arena[child].position());
+            scripts.push((arena.inner_text(id), position));
+            return;
+        }
+    }
+
+    for child in arena.children(id) {
+        collect_scripts(arena, child, scripts);
+    }
+}
+
 impl Renderer {
-    pub fn new(ui_cb_sink: Rc<CbSink>, document_element: Box<Node>) -> Renderer {
-        let stylesheet = css::parse(&format!(
-            "{}\n{}",
-            DEFAULT_STYLESHEET,
-            collect_tag_inners(&document_element, "style".into()).join("\n")
-        ));
-
-        let view = to_styled_node(&document_element, &stylesheet)
-            .and_then(|styled_node| Some(to_layout_box(styled_node)))
-            .and_then(|layout_box| Some(to_element_container(layout_box)))
-            .unwrap();
-
-        let document_element = Rc::new(RefCell::new(document_element));
-        let document_element_ref = document_element.clone();
-        Renderer {
-            document_element,
-            view,
+    /// `new` prepares the document for the viewport, with the user stylesheet if any.
+    /// Nothing is rendered until `rerender`, so that inline scripts can change the DOM before the first rendering.
+    /// The problems the parsers and the loader recover from do not make it fail, but are reported by `diagnostics`.
+    pub fn new(
+        ui_cb_sink: Rc<CbSink>,
+        document: Arena,
+        viewport: Viewport,
+        user_stylesheet: Option<Stylesheet>,
+    ) -> Result<Renderer, Error> {
+        let canvas = Canvas::new(viewport.width, viewport.height);
+        let user_stylesheet = user_stylesheet.map(|stylesheet| Stylesheet {
+            origin: Origin::User,
+            ..stylesheet
+        });
+
+        let document = Rc::new(RefCell::new(document));
+        let document_ref = document.clone();
+        Ok(Renderer {
+            document,
+            canvas,
             // js_runtime_instance: JavaScriptRuntime::new(),
             js_runtime_instance: JavaScriptRuntime::new(
-                document_element_ref,
+                document_ref,
                 Rc::new(RendererAPI::new(ui_cb_sink)),
             ),
-        }
+            viewport,
+            user_stylesheet,
+            parse_errors: vec![],
+            // 最初のrerenderで読み込む
+            author_styles: AuthorStyles::default(),
+        })
     }
 
     // 再描画
     pub fn rerender(&mut self) {
-        let document_element = self.document_element.borrow();
-        let stylesheet = css::parse(&format!(
-            "{}\n{}",
-            DEFAULT_STYLESHEET,
-            collect_tag_inners(&document_element, "style".into()).join("\n")
-        ));
-        let view = to_styled_node(&document_element, &stylesheet)
-            .and_then(|styled_node| Some(to_layout_box(styled_node)))
-            .and_then(|layout_box| Some(to_element_container(layout_box)))
-            .unwrap();
-        self.view = view;
-    }
-
-    // inlineスクリプトを実行
-    pub fn execute_inline_scripts(&mut self) {
-        let scripts = {
-            let document_element = self.document_element.borrow();
-            collect_tag_inners(&document_element, "script".into()).join("\n")
-        };
-        self.js_runtime_instance
-            .execute("(inline)", &scripts.as_str())
-            .unwrap();
+        let document = self.document.borrow();
+        self.author_styles.update(&document);
+        let stylesheets = stylesheets(&self.author_styles, self.user_stylesheet.as_ref(), &self.viewport);
+        self.canvas = render(&document, &stylesheets, &self.viewport);
     }
-}
 
-impl View for Renderer {
-    fn draw(&self, printer: &cursive::Printer) {
-        self.view.draw(printer)
+    /// `set_parse_errors` records the errors the document was parsed with, which are returned by `diagnostics`.
+    /// See `html::parse_with_errors`.
+    pub fn set_parse_errors(&mut self, errors: Vec<html::ParseError>) {
+        self.parse_errors = errors.into_iter().map(Error::from).collect();
     }
 
-    fn layout(&mut self, v: Vec2) {
-        self.view.layout(v)
+    /// `diagnostics` returns the problems the page was rendered with, which do not stop rendering:
+    /// the HTML parse errors, and the CSS parse errors and the stylesheets failing to load in the latest rendering.
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Error> {
+        self.parse_errors.iter().chain(self.author_styles.errors.iter())
     }
 
-    fn needs_relayout(&self) -> bool {
-        self.view.needs_relayout()
+    /// `set_user_stylesheet` applies the stylesheet with the user origin and renders the document again.
+    /// See https://www.w3.org/TR/css-cascade-4/#cascade-origin-user
+    pub fn set_user_stylesheet(&mut self, stylesheet: Stylesheet) {
+        self.user_stylesheet = Some(Stylesheet {
+            origin: Origin::User,
+            ..stylesheet
+        });
+        self.rerender();
     }
 
-    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
-        self.view.required_size(constraint)
+    /// `resize` renders the document again if the size of the viewport changes.
+    pub fn resize(&mut self, viewport: Viewport) {
+        // 端末の大きさが変わったら、@mediaを評価し直すためにカスケードからやり直す
+        if viewport != self.viewport {
+            self.viewport = viewport;
+            self.rerender();
+        }
     }
 
-    fn on_event(&mut self, e: Event) -> EventResult {
-        self.view.on_event(e)
+    /// `execute_inline_scripts` runs the inline scripts in document order.
+    /// As in browsers, an uncaught exception stops only the script which throws it.
+    /// The first exception is returned after all the scripts run, located in the document, or in `(inline)` if its URL is unknown.
+    pub fn execute_inline_scripts(&mut self) -> Result<(), Error> {
+        let (scripts, filename) = {
+            let document = self.document.borrow();
+            let mut scripts = vec![];
+            collect_scripts(&document, document.document(), &mut scripts);
+            (scripts, document.url().unwrap_or("(inline)").to_string())
+        };
+
+        let mut result = Ok(());
+        for (source, position) in scripts {
+            let executed = self.js_runtime_instance.execute_at(&filename, &source, position);
+            if let (Err(e), Ok(())) = (executed, &result) {
+                result = Err(e.into());
+            }
+        }
+        result
     }
 
-    fn call_on_any<'a>(&mut self, s: &Selector<'_>, cb: AnyCb<'a>) {
-        self.view.call_on_any(s, cb)
+    /// `inspect` describes the node with the stylesheets the document is rendered with.
+    pub fn inspect(&self, id: NodeId) -> NodeInspection {
+        let document = self.document.borrow();
+        let stylesheets = stylesheets(&self.author_styles, self.user_stylesheet.as_ref(), &self.viewport);
+        inspector::inspect(&document, id, &stylesheets)
     }
+}
 
-    fn focus_view(&mut self, s: &Selector<'_>) -> Result<EventResult, ViewNotFound> {
-        self.view.focus_view(s)
+/// `to_cursive_color` converts a color into the one of cursive. The alpha channel is ignored.
+fn to_cursive_color(color: Option<css::Color>) -> cursive::theme::Color {
+    match color {
+        Some(css::Color { r, g, b, .. }) => cursive::theme::Color::Rgb(r, g, b),
+        None => cursive::theme::Color::TerminalDefault,
     }
+}
 
-    fn take_focus(&mut self, source: Direction) -> Result<EventResult, CannotFocus>{
-        self.view.take_focus(source)
+/// `Renderer` shows the canvas as it is.
+impl View for Renderer {
+    fn draw(&self, printer: &Printer) {
+        for (y, row) in self.canvas.rows().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                // 全角文字の右半分は左半分と一緒に描かれる
+                let ch = match cell.ch {
+                    Some(ch) => ch,
+                    None => continue,
+                };
+                let mut style = Style::from(ColorStyle::new(
+                    to_cursive_color(cell.fg),
+                    cell.bg.map_or(ColorType::InheritParent, |bg| to_cursive_color(Some(bg)).into()),
+                ));
+                for (enabled, effect) in [
+                    (cell.style.bold, Effect::Bold),
+                    (cell.style.italic, Effect::Italic),
+                    (cell.style.underline, Effect::Underline),
+                    (cell.style.line_through, Effect::Strikethrough),
+                ] {
+                    if enabled {
+                        style.effects.insert(effect);
+                    }
+                }
+                printer.with_style(style, |printer| printer.print((x, y), ch.encode_utf8(&mut [0; 4])));
+            }
+        }
     }
 
-    fn important_area(&self, view_size: Vec2) -> Rect {
-        self.view.important_area(view_size)
+    fn layout(&mut self, v: Vec2) {
+        self.resize(Viewport { width: v.x, height: v.y });
     }
 
-    fn type_name(&self) -> &'static str {
-        self.view.type_name()
+    fn required_size(&mut self, _constraint: Vec2) -> Vec2 {
+        Vec2::new(self.canvas.width, self.canvas.height)
     }
 }
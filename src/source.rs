@@ -0,0 +1,102 @@
+//! This module locates nodes and rules in the source text they were parsed from.
+
+use std::{cell::Cell, fmt};
+
+/// `SourcePosition` represents a position in a source text.
+/// `offset` is in bytes, whereas `line` and `column` are 1-origin and `column` counts characters.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct SourcePosition {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourcePosition {
+    /// `relative_to` converts a position in a text embedded at `start` in another text, such as the content of `<style>`,
+    /// into the position in the outer text.
+    pub fn relative_to(self, start: SourcePosition) -> SourcePosition {
+        SourcePosition {
+            offset: start.offset + self.offset,
+            line: start.line + self.line - 1,
+            // 埋め込まれたテキストの1行目だけは、外側の行の途中から始まる
+            column: if self.line == 1 { start.column + self.column - 1 } else { self.column },
+        }
+    }
+}
+
+/// `SourcePosition` is printed as `line:column`, which is how editors and compilers refer to a position.
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// `LineIndex` converts byte offsets in a source text into positions.
+/// `\n`, `\r\n` and `\r` all end a line, as both HTML and CSS normalize them into `\n` before parsing.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    // 各行の先頭のバイトオフセット
+    line_starts: Vec<usize>,
+    // 直前に求めたオフセットとその列
+    last: Cell<(usize, usize)>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> LineIndex {
+        let mut line_starts = vec![0];
+        let bytes = source.as_bytes();
+        for (i, &b) in bytes.iter().enumerate() {
+            // `\r\n`は`\n`の位置で改行とみなす
+            if b == b'\n' || (b == b'\r' && bytes.get(i + 1) != Some(&b'\n')) {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex {
+            line_starts,
+            last: Cell::new((0, 1)),
+        }
+    }
+
+    /// `position` returns the position of `offset`, which must be on a character boundary of `source`.
+    /// `source` must be the text the index was created from.
+    pub fn position(&self, source: &str, offset: usize) -> SourcePosition {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        // パーサーは先頭から順に位置を求めるので、直前の位置から数え進めれば長い行でも線形時間で済む
+        let (from, column) = match self.last.get() {
+            (last, column) if line_start <= last && last <= offset => (last, column),
+            _ => (line_start, 1),
+        };
+        let column = column + source[from..offset].chars().count();
+        self.last.set((offset, column));
+        SourcePosition { offset, line, column }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let source = "a\r\nbc\rd日本\ne\n";
+        let index = LineIndex::new(source);
+        let position = |offset| {
+            let p = index.position(source, offset);
+            (p.line, p.column)
+        };
+        assert_eq!(position(0), (1, 1));
+        assert_eq!(position(1), (1, 2));
+        assert_eq!(position(3), (2, 1));
+        assert_eq!(position(6), (3, 1));
+        // columns count characters rather than bytes
+        assert_eq!(position(10), (3, 3));
+        assert_eq!(position(14), (4, 1));
+        assert_eq!(position(source.len()), (5, 1));
+        assert_eq!(index.position(source, 10).to_string(), "3:3");
+
+        let start = SourcePosition { offset: 20, line: 2, column: 8 };
+        assert_eq!(index.position(source, 1).relative_to(start), SourcePosition { offset: 21, line: 2, column: 9 });
+        assert_eq!(index.position(source, 10).relative_to(start), SourcePosition { offset: 30, line: 4, column: 3 });
+    }
+}
@@ -1,13 +1,19 @@
 //! This module includes some implementations on node styles.
 
 use crate::{
-    css::{CSSValue, Stylesheet},
-    dom::{Node, NodeType},
+    css::{self, CSSValue, Declaration, Origin, Specificity, Stylesheet},
+    dom::{Arena, NodeId, NodeType},
 };
 use std::collections::HashMap;
 
+pub mod property;
+
 pub type PropertyMap = HashMap<String, CSSValue>;
 
+/// `INLINE_SPECIFICITY` is given to the declarations in a `style` attribute so that they win over any selector.
+/// See https://www.w3.org/TR/CSS2/cascade.html#specificity
+pub(crate) const INLINE_SPECIFICITY: Specificity = (usize::MAX, 0, 0);
+
 #[derive(Debug, PartialEq)]
 pub enum Display {
     Inline,
@@ -15,8 +21,45 @@ pub enum Display {
     None,
 }
 
+/// `WhiteSpace` represents the value of `white-space`.
+/// See https://www.w3.org/TR/css-text-3/#white-space-property
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+}
+
+impl WhiteSpace {
+    pub fn from_properties(properties: &PropertyMap) -> WhiteSpace {
+        match properties.get("white-space") {
+            Some(CSSValue::Keyword(s)) => match s.as_str() {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                _ => WhiteSpace::Normal,
+            },
+            _ => WhiteSpace::Normal,
+        }
+    }
+
+    /// `wraps` tells whether lines may be broken to fit the container.
+    pub fn wraps(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::PreWrap)
+    }
+
+    /// `preserves` tells whether spaces, tabs and newlines are kept as they are written.
+    pub fn preserves(self) -> bool {
+        matches!(self, WhiteSpace::Pre | WhiteSpace::PreWrap)
+    }
+}
+
 /// `StyledNode` wraps `Node` with related CSS properties.
 /// It forms a tree as `Node` does.
+///
+/// `properties` holds the computed values, i.e. every property in `property::PROPERTIES` has a value
+/// after inheritance and initial values are applied.
 #[derive(Debug, PartialEq)]
 pub struct StyledNode<'a> {
     pub node_type: &'a NodeType,
@@ -25,27 +68,72 @@ pub struct StyledNode<'a> {
     pub properties: PropertyMap,
 }
 
-pub fn to_styled_node<'a>(node: &'a Box<Node>, stylesheet: &Stylesheet) -> Option<StyledNode<'a>> {
-    // todo!("you need to implement this")
-    let mut properties: HashMap<String, CSSValue> = HashMap::new();
+/// `to_styled_node` cascades the given stylesheets onto the node and its descendants.
+/// Stylesheets are expected to be in document order, which decides the winner among declarations of equal precedence.
+pub fn to_styled_node<'a>(arena: &'a Arena, id: NodeId, stylesheets: &[Stylesheet]) -> Option<StyledNode<'a>> {
+    styled_node(arena, id, stylesheets, None)
+}
+
+fn styled_node<'a>(
+    arena: &'a Arena,
+    id: NodeId,
+    stylesheets: &[Stylesheet],
+    parent: Option<&PropertyMap>,
+) -> Option<StyledNode<'a>> {
+    let node = &arena[id];
+    match node.node_type {
+        // ドキュメントの場合はルート要素から始める
+        NodeType::Document(_) => {
+            return arena
+                .children(id)
+                .find(|&child| matches!(arena[child].node_type, NodeType::Element(_)))
+                .and_then(|element| styled_node(arena, element, stylesheets, None));
+        },
+        // コメントとDOCTYPEは描画しない
+        NodeType::Comment(_) | NodeType::DocumentType(_) => return None,
+        NodeType::Element(_) | NodeType::Text(_) => {},
+    };
 
-    // filterling & cascading
-    let matched_rules = stylesheet.rules.iter().filter(|rule| rule.matches(node));
-    for matched_rule in matched_rules {
-        for declaration in &matched_rule.declarations {
-            properties.insert(declaration.name.clone(), declaration.value.clone());
+    // style属性はauthorスタイルシートとして扱う
+    let inline_declarations = match node.node_type {
+        NodeType::Element(ref el) => el.attribute("style").map(|style| css::parse_declarations(style).0),
+        _ => None,
+    }
+    .unwrap_or_default();
+
+    // filterling
+    // セレクタは要素にしかマッチしないので、テキストは継承した値と初期値だけを持つ
+    let mut declarations: Vec<(Origin, Specificity, &Declaration)> = vec![];
+    let rule_stylesheets = if matches!(node.node_type, NodeType::Element(_)) { stylesheets } else { &[] };
+    for stylesheet in rule_stylesheets {
+        for rule in &stylesheet.rules {
+            if let Some(specificity) = rule.matching_specificity(arena, id) {
+                for declaration in &rule.declarations {
+                    declarations.push((stylesheet.origin, specificity, declaration));
+                }
+            }
         }
     }
+    for declaration in &inline_declarations {
+        declarations.push((Origin::Author, INLINE_SPECIFICITY, declaration));
+    }
+
+    // cascading
+    let cascaded: PropertyMap = cascade(&declarations)
+        .into_iter()
+        .map(|(name, (value, _))| (name, value))
+        .collect();
 
     // defaulting
+    let properties = compute_values(cascaded, parent);
 
     if properties.get("display") == Some(&CSSValue::Keyword("none".to_string())) {
         return None;
     }
 
     // 子要素を再帰的に処理
-    let children = node.children.iter()
-        .filter_map(|child_node| to_styled_node(child_node, stylesheet))
+    let children = arena.children(id)
+        .filter_map(|child_node| styled_node(arena, child_node, stylesheets, Some(&properties)))
         .collect();
 
     Some(StyledNode {
@@ -55,6 +143,92 @@ pub fn to_styled_node<'a>(node: &'a Box<Node>, stylesheet: &Stylesheet) -> Optio
     })
 }
 
+/// `cascade` finds the declaration which wins each property, and returns the values with the indices of the winners.
+/// Shorthands are expanded into longhands.
+/// Declarations are expected in document order, which decides the winner among declarations of equal precedence and specificity.
+/// See https://www.w3.org/TR/css-cascade-4/#cascade-sort
+pub(crate) fn cascade(declarations: &[(Origin, Specificity, &Declaration)]) -> HashMap<String, (CSSValue, usize)> {
+    // 安定ソートなので、優先度が同じ宣言はソース順のまま並び、後のものが勝つ
+    let mut order = (0..declarations.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| {
+        let (origin, specificity, declaration) = declarations[i];
+        (precedence(origin, declaration.important), specificity)
+    });
+
+    let mut cascaded = HashMap::new();
+    for i in order {
+        let declaration = declarations[i].2;
+        // ショートハンドは個別のプロパティに展開する
+        match property::expand(&declaration.name, &declaration.value) {
+            Some(longhands) => cascaded.extend(longhands.into_iter().map(|(name, value)| (name, (value, i)))),
+            None => {
+                cascaded.insert(declaration.name.clone(), (declaration.value.clone(), i));
+            },
+        }
+    }
+    cascaded
+}
+
+/// `compute_values` resolves the cascaded values against the parent's computed values.
+/// It handles the CSS-wide keywords `inherit`, `initial` and `unset`, and fills in properties without any declaration.
+/// See https://www.w3.org/TR/css-cascade-4/#defaulting
+fn compute_values(mut cascaded: PropertyMap, parent: Option<&PropertyMap>) -> PropertyMap {
+    let inherit = |name: &str| parent.and_then(|parent| parent.get(name)).cloned();
+
+    let mut computed = PropertyMap::new();
+    for property in property::PROPERTIES {
+        let value = match cascaded.remove(property.name) {
+            Some(CSSValue::Keyword(ref k)) if k == "initial" => None,
+            Some(CSSValue::Keyword(ref k)) if k == "inherit" => inherit(property.name),
+            Some(CSSValue::Keyword(ref k)) if k == "unset" => {
+                if property.inherited { inherit(property.name) } else { None }
+            },
+            Some(value) => Some(value),
+            None => {
+                if property.inherited { inherit(property.name) } else { None }
+            },
+        };
+        computed.insert(property.name.to_string(), value.unwrap_or_else(|| property.initial_value()));
+    }
+
+    // 未知のプロパティは初期値がわからないので、inherit以外のキーワードでは値を持たない
+    for (name, value) in cascaded {
+        let value = match value {
+            CSSValue::Keyword(ref k) if k == "inherit" => inherit(&name),
+            CSSValue::Keyword(ref k) if k == "initial" || k == "unset" => None,
+            value => Some(value),
+        };
+        if let Some(value) = value {
+            computed.insert(name, value);
+        }
+    }
+
+    // スタイルが`none`か`hidden`の辺は、幅が指定されていても枠線を描かない
+    // See https://www.w3.org/TR/css-backgrounds-3/#border-width
+    for side in ["top", "right", "bottom", "left"] {
+        let style = computed.get(&format!("border-{}-style", side));
+        if matches!(style, Some(CSSValue::Keyword(k)) if k == "none" || k == "hidden") {
+            computed.insert(format!("border-{}-width", side), CSSValue::Integer(0));
+        }
+    }
+
+    computed
+}
+
+/// `precedence` ranks declarations by their origin and importance.
+/// Important declarations reverse the order of origins.
+/// See https://www.w3.org/TR/css-cascade-4/#cascade-origin
+pub(crate) fn precedence(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
 impl<'a> StyledNode<'a> {
     pub fn display(&self) -> Display {
         match self.properties.get("display") {
@@ -71,31 +245,43 @@ impl<'a> StyledNode<'a> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        css::{AttributeSelectorOp, Declaration, Rule, SimpleSelector},
-        dom::Element,
+        css::{self, AttributeSelectorOp, Declaration, Rule, SimpleSelector},
+        dom::AttrMap,
+        html,
     };
 
     use super::*;
 
+    /// `computed` returns the initial values overridden by the given properties.
+    fn computed(properties: &[(String, CSSValue)]) -> PropertyMap {
+        let mut computed = property::initial_values();
+        computed.extend(properties.iter().cloned());
+        computed
+    }
+
     #[test]
     fn test_to_styled_node_single() {
-        let e = &Element::new(
-            "p".to_string(),
+        let mut arena = Arena::new();
+        let e = arena.create_element(
+            "p",
             [("id".to_string(), "test".to_string())]
                 .iter()
                 .cloned()
                 .collect(),
-            vec![],
         );
         let testcases = vec![
             (
                 // * { display: block; }
                 Stylesheet::new(vec![Rule {
-                    selectors: vec![SimpleSelector::UniversalSelector],
+                    selectors: vec![SimpleSelector::UniversalSelector.into()],
                     declarations: vec![Declaration {
                         name: "display".to_string(),
                         value: CSSValue::Keyword("block".to_string()),
+                        important: false,
+                        position: None,
                     }],
+                    position: None,
+                    source: None,
                 }]),
                 vec![(
                     "display".to_string(),
@@ -107,11 +293,15 @@ mod tests {
                 Stylesheet::new(vec![Rule {
                     selectors: vec![SimpleSelector::TypeSelector {
                         tag_name: "div".into(),
-                    }],
+                    }.into()],
                     declarations: vec![Declaration {
                         name: "display".into(),
                         value: CSSValue::Keyword("block".to_string()),
+                        important: false,
+                        position: None,
                     }],
+                    position: None,
+                    source: None,
                 }]),
                 vec![],
             ),
@@ -120,20 +310,28 @@ mod tests {
                 // div { display: inline; }
                 Stylesheet::new(vec![
                     Rule {
-                        selectors: vec![SimpleSelector::UniversalSelector],
+                        selectors: vec![SimpleSelector::UniversalSelector.into()],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                     Rule {
                         selectors: vec![SimpleSelector::TypeSelector {
                             tag_name: "div".into(),
-                        }],
+                        }.into()],
                         declarations: vec![Declaration {
                             name: "display".into(),
                             value: CSSValue::Keyword("inline".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                 ]),
                 vec![(
@@ -146,26 +344,36 @@ mod tests {
                 // p { display: inline; testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
-                        selectors: vec![SimpleSelector::UniversalSelector],
+                        selectors: vec![SimpleSelector::UniversalSelector.into()],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                     Rule {
                         selectors: vec![SimpleSelector::TypeSelector {
                             tag_name: "p".into(),
-                        }],
+                        }.into()],
                         declarations: vec![
                             Declaration {
                                 name: "display".into(),
                                 value: CSSValue::Keyword("inline".into()),
+                                important: false,
+                                position: None,
                             },
                             Declaration {
                                 name: "testname".into(),
                                 value: CSSValue::Keyword("testvalue".into()),
+                                important: false,
+                                position: None,
                             },
                         ],
+                        position: None,
+                        source: None,
                     },
                 ]),
                 vec![
@@ -178,23 +386,36 @@ mod tests {
                 // p[id=hello] { testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
-                        selectors: vec![SimpleSelector::UniversalSelector],
+                        selectors: vec![SimpleSelector::UniversalSelector.into()],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                     Rule {
-                        selectors: vec![SimpleSelector::AttributeSelector {
-                            tag_name: "p".into(),
-                            op: AttributeSelectorOp::Eq,
-                            attribute: "id".into(),
-                            value: "hello".into(),
-                        }],
+                        selectors: vec![vec![
+                            SimpleSelector::TypeSelector {
+                                tag_name: "p".into(),
+                            },
+                            SimpleSelector::AttributeSelector {
+                                op: AttributeSelectorOp::Eq,
+                                case_insensitive: false,
+                                attribute: "id".into(),
+                                value: "hello".into(),
+                            },
+                        ].into()],
                         declarations: vec![Declaration {
                             name: "testname".into(),
                             value: CSSValue::Keyword("testvalue".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                 ]),
                 vec![("display".into(), CSSValue::Keyword("block".into()))],
@@ -204,23 +425,36 @@ mod tests {
                 // p[id=hello] { testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
-                        selectors: vec![SimpleSelector::UniversalSelector],
+                        selectors: vec![SimpleSelector::UniversalSelector.into()],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                     Rule {
-                        selectors: vec![SimpleSelector::AttributeSelector {
-                            tag_name: "p".into(),
-                            op: AttributeSelectorOp::Eq,
-                            attribute: "id".into(),
-                            value: "test".into(),
-                        }],
+                        selectors: vec![vec![
+                            SimpleSelector::TypeSelector {
+                                tag_name: "p".into(),
+                            },
+                            SimpleSelector::AttributeSelector {
+                                op: AttributeSelectorOp::Eq,
+                                case_insensitive: false,
+                                attribute: "id".into(),
+                                value: "test".into(),
+                            },
+                        ].into()],
                         declarations: vec![Declaration {
                             name: "testname".into(),
                             value: CSSValue::Keyword("testvalue".into()),
+                            important: false,
+                            position: None,
                         }],
+                        position: None,
+                        source: None,
                     },
                 ]),
                 vec![
@@ -232,10 +466,10 @@ mod tests {
 
         for (stylesheet, properties) in testcases {
             assert_eq!(
-                to_styled_node(e, &stylesheet),
+                to_styled_node(&arena, e, &[stylesheet]),
                 Some(StyledNode {
-                    node_type: &e.node_type,
-                    properties: properties.iter().cloned().collect(),
+                    node_type: &arena[e].node_type,
+                    properties: computed(&properties),
                     children: vec![],
                 })
             );
@@ -244,61 +478,52 @@ mod tests {
 
     #[test]
     fn test_to_styled_node_nested() {
-        let parent = &Element::new(
-            "div".to_string(),
+        let mut arena = Arena::new();
+        let parent = arena.create_element(
+            "div",
             [("id".to_string(), "test".to_string())]
                 .iter()
                 .cloned()
                 .collect(),
-            vec![Element::new(
-                "p".to_string(),
-                [("id".to_string(), "test".to_string())]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                vec![],
-            )],
         );
-        let child_node_type = Element::new(
-            "p".to_string(),
+        let child = arena.create_element(
+            "p",
             [("id".to_string(), "test".to_string())]
                 .iter()
                 .cloned()
                 .collect(),
-            vec![],
-        )
-        .node_type;
+        );
+        arena.append_child(parent, child).unwrap();
+        let child_node_type = &arena[child].node_type;
 
         {
             // * { display: block; }
             let stylesheet = Stylesheet::new(vec![Rule {
-                selectors: vec![SimpleSelector::UniversalSelector],
+                selectors: vec![SimpleSelector::UniversalSelector.into()],
                 declarations: vec![Declaration {
                     name: "display".to_string(),
                     value: CSSValue::Keyword("block".to_string()),
+                    important: false,
+                    position: None,
                 }],
+                position: None,
+                source: None,
             }]);
 
             assert_eq!(
-                to_styled_node(parent, &stylesheet),
+                to_styled_node(&arena, parent, &[stylesheet]),
                 Some(StyledNode {
-                    node_type: &parent.node_type,
-                    properties: [(
+                    node_type: &arena[parent].node_type,
+                    properties: computed(&[(
                         "display".to_string(),
                         CSSValue::Keyword("block".to_string()),
-                    )]
-                    .iter()
-                    .cloned()
-                    .collect(),
+                    )]),
                     children: vec![StyledNode {
-                        node_type: &child_node_type,
-                        properties: [(
+                        node_type: child_node_type,
+                        properties: computed(&[(
                             "display".to_string(),
                             CSSValue::Keyword("block".to_string()),
-                        )]
-                        .iter()
-                        .cloned()
-                        .collect(),
+                        )]),
                         children: vec![],
                     }],
                 })
@@ -310,27 +535,28 @@ mod tests {
             let stylesheet = Stylesheet::new(vec![Rule {
                 selectors: vec![SimpleSelector::TypeSelector {
                     tag_name: "p".into(),
-                }],
+                }.into()],
                 declarations: vec![Declaration {
                     name: "display".to_string(),
                     value: CSSValue::Keyword("block".to_string()),
+                    important: false,
+                    position: None,
                 }],
+                position: None,
+                source: None,
             }]);
 
             assert_eq!(
-                to_styled_node(parent, &stylesheet),
+                to_styled_node(&arena, parent, &[stylesheet]),
                 Some(StyledNode {
-                    node_type: &parent.node_type,
-                    properties: [].iter().cloned().collect(),
+                    node_type: &arena[parent].node_type,
+                    properties: computed(&[]),
                     children: vec![StyledNode {
-                        node_type: &child_node_type,
-                        properties: [(
+                        node_type: child_node_type,
+                        properties: computed(&[(
                             "display".to_string(),
                             CSSValue::Keyword("block".to_string()),
-                        )]
-                        .iter()
-                        .cloned()
-                        .collect(),
+                        )]),
                         children: vec![],
                     }],
                 })
@@ -340,65 +566,317 @@ mod tests {
 
     #[test]
     fn test_to_styled_node_nested_single() {
-        let parent = &Element::new(
-            "div".to_string(),
+        let mut arena = Arena::new();
+        let parent = arena.create_element(
+            "div",
             [("id".to_string(), "test".to_string())]
                 .iter()
                 .cloned()
                 .collect(),
-            vec![],
         );
 
         // p { display: none; }
         let stylesheet = Stylesheet::new(vec![Rule {
             selectors: vec![SimpleSelector::TypeSelector {
                 tag_name: "div".into(),
-            }],
+            }.into()],
             declarations: vec![Declaration {
                 name: "display".to_string(),
                 value: CSSValue::Keyword("none".to_string()),
+                important: false,
+                position: None,
             }],
+            position: None,
+            source: None,
         }]);
 
-        assert_eq!(to_styled_node(parent, &stylesheet), None);
+        assert_eq!(to_styled_node(&arena, parent, &[stylesheet]), None);
     }
 
     #[test]
     fn test_to_styled_node_nested_none() {
-        let parent = &Element::new(
-            "div".to_string(),
+        let mut arena = Arena::new();
+        let parent = arena.create_element(
+            "div",
             [("id".to_string(), "test".to_string())]
                 .iter()
                 .cloned()
                 .collect(),
-            vec![Element::new(
-                "p".to_string(),
-                [("id".to_string(), "test".to_string())]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                vec![],
-            )],
         );
+        let child = arena.create_element(
+            "p",
+            [("id".to_string(), "test".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        arena.append_child(parent, child).unwrap();
 
         // p { display: none; }
         let stylesheet = Stylesheet::new(vec![Rule {
             selectors: vec![SimpleSelector::TypeSelector {
                 tag_name: "p".into(),
-            }],
+            }.into()],
             declarations: vec![Declaration {
                 name: "display".to_string(),
                 value: CSSValue::Keyword("none".to_string()),
+                important: false,
+                position: None,
             }],
+            position: None,
+            source: None,
         }]);
 
         assert_eq!(
-            to_styled_node(parent, &stylesheet),
+            to_styled_node(&arena, parent, &[stylesheet]),
             Some(StyledNode {
-                node_type: &parent.node_type,
-                properties: [].iter().cloned().collect(),
+                node_type: &arena[parent].node_type,
+                properties: computed(&[]),
                 children: vec![],
             })
         );
     }
+
+    #[test]
+    fn test_to_styled_node_document() {
+        let mut arena = Arena::new();
+        let document = arena.document();
+        let doctype = arena.create_document_type("html", "", "");
+        let before_html = arena.create_comment("before html");
+        let html = arena.create_element("html", AttrMap::new());
+        let in_html = arena.create_comment("in html");
+        let text = arena.create_text("hello");
+        for &(parent, child) in &[(document, doctype), (document, before_html), (document, html), (html, in_html), (html, text)] {
+            arena.append_child(parent, child).unwrap();
+        }
+
+        assert_eq!(
+            to_styled_node(&arena, document, &[Stylesheet::new(vec![])]),
+            Some(StyledNode {
+                node_type: &arena[html].node_type,
+                properties: computed(&[]),
+                children: vec![StyledNode {
+                    node_type: &arena[text].node_type,
+                    properties: computed(&[]),
+                    children: vec![],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_cascade_order() {
+        let arena = html::parse(r#"<div id="main" class="note"><p id="target" class="note">a</p></div>"#);
+        let target = arena.get_element_by_id("target").unwrap();
+        let color_of = |stylesheets: &[Stylesheet]| {
+            let styled_node = to_styled_node(&arena, target, stylesheets).unwrap();
+            styled_node.properties.get("color").cloned()
+        };
+        let stylesheet = |origin: Origin, raw: &str| {
+            let mut stylesheet = css::parse(raw);
+            stylesheet.origin = origin;
+            stylesheet
+        };
+        let keyword = |s: &str| Some(CSSValue::Keyword(s.to_string()));
+
+        // the more specific selector wins regardless of source order
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, "#target { color: red; } p { color: blue; }")]),
+            keyword("red")
+        );
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, "div p.note { color: red; } p.note { color: blue; }")]),
+            keyword("red")
+        );
+
+        // the later one wins when specificities are the same
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, ".note { color: red; } p.note { color: blue; } div > p { color: green; }")]),
+            keyword("blue")
+        );
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::Author, "p { color: red; }"),
+                stylesheet(Origin::Author, "p { color: blue; }"),
+            ]),
+            keyword("blue")
+        );
+
+        // the highest specificity among the matching selectors of a rule is used
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, "#target, span { color: red; } p.note { color: blue; }")]),
+            keyword("red")
+        );
+
+        // `!important` beats specificity
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, "p { color: red !important; } #target { color: blue; }")]),
+            keyword("red")
+        );
+        assert_eq!(
+            color_of(&[stylesheet(Origin::Author, "p { color: red !important; } #target { color: blue !important; }")]),
+            keyword("blue")
+        );
+    }
+
+    #[test]
+    fn test_cascade_origin() {
+        let arena = html::parse(r#"<p id="target">a</p>"#);
+        let target = arena.get_element_by_id("target").unwrap();
+        let color_of = |stylesheets: &[Stylesheet]| {
+            let styled_node = to_styled_node(&arena, target, stylesheets).unwrap();
+            styled_node.properties.get("color").cloned()
+        };
+        let stylesheet = |origin: Origin, raw: &str| {
+            let mut stylesheet = css::parse(raw);
+            stylesheet.origin = origin;
+            stylesheet
+        };
+        let keyword = |s: &str| Some(CSSValue::Keyword(s.to_string()));
+
+        // author > user > user-agent for normal declarations
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::Author, "p { color: red; }"),
+                stylesheet(Origin::UserAgent, "#target { color: blue; }"),
+            ]),
+            keyword("red")
+        );
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::User, "#target { color: blue; }"),
+                stylesheet(Origin::Author, "p { color: red; }"),
+            ]),
+            keyword("red")
+        );
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::UserAgent, "#target { color: blue; }"),
+                stylesheet(Origin::User, "p { color: red; }"),
+            ]),
+            keyword("red")
+        );
+
+        // the order is reversed for important declarations
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::User, "p { color: red !important; }"),
+                stylesheet(Origin::Author, "#target { color: blue !important; }"),
+            ]),
+            keyword("red")
+        );
+        assert_eq!(
+            color_of(&[
+                stylesheet(Origin::UserAgent, "p { color: red !important; }"),
+                stylesheet(Origin::User, "#target { color: blue !important; }"),
+            ]),
+            keyword("red")
+        );
+
+        // user-agent styles are overridden by the author, e.g. `display: block` of `p`
+        assert_eq!(
+            to_styled_node(
+                &arena,
+                target,
+                &[
+                    stylesheet(Origin::Author, "p { display: inline; }"),
+                    stylesheet(Origin::UserAgent, "p { display: block; }"),
+                ]
+            )
+            .unwrap()
+            .display(),
+            Display::Inline
+        );
+    }
+
+    fn find<'a, 'b>(node: &'b StyledNode<'a>, target: &NodeType) -> Option<&'b StyledNode<'a>> {
+        if std::ptr::eq(node.node_type, target) {
+            return Some(node);
+        }
+        node.children.iter().find_map(|child| find(child, target))
+    }
+
+    #[test]
+    fn test_defaulting() {
+        let arena = html::parse(
+            r#"<div id="parent"><p id="child">a<span id="grandchild">b</span></p></div>"#,
+        );
+        let value_of = |raw: &str, id: &str, name: &str| {
+            let stylesheet = css::parse(raw);
+            let document = arena.document();
+            let root = to_styled_node(&arena, document, &[stylesheet]).unwrap();
+            let target = &arena[arena.get_element_by_id(id).unwrap()].node_type;
+            find(&root, target).unwrap().properties.get(name).cloned()
+        };
+        let keyword = |s: &str| Some(CSSValue::Keyword(s.to_string()));
+
+        // inherited properties propagate to descendants, including text
+        assert_eq!(value_of("div { color: red; }", "grandchild", "color"), keyword("red"));
+        assert_eq!(value_of("div { font-weight: bold; }", "child", "font-weight"), keyword("bold"));
+        assert_eq!(value_of("div { text-align: center; } p { text-align: left; }", "grandchild", "text-align"), keyword("left"));
+
+        // non-inherited properties fall back to the initial values
+        assert_eq!(value_of("div { display: block; }", "child", "display"), keyword("inline"));
+        assert_eq!(value_of("", "child", "color"), keyword("black"));
+        assert_eq!(value_of("", "child", "unknown"), None);
+
+        // CSS-wide keywords
+        assert_eq!(value_of("div { display: block; } p { display: inherit; }", "child", "display"), keyword("block"));
+        assert_eq!(value_of("div { color: red; } p { color: initial; }", "child", "color"), keyword("black"));
+        assert_eq!(value_of("div { color: red; } p { color: unset; }", "child", "color"), keyword("red"));
+        assert_eq!(value_of("div { display: block; } p { display: unset; }", "child", "display"), keyword("inline"));
+        assert_eq!(value_of("div { foo: bar; } p { foo: inherit; }", "child", "foo"), keyword("bar"));
+        assert_eq!(value_of("div { foo: bar; } p { foo: initial; }", "child", "foo"), None);
+        assert_eq!(value_of("div { foo: bar; }", "child", "foo"), None);
+
+        // a border width is 0 unless the border has a visible style, whichever property sets it
+        let px = |n: f64| Some(CSSValue::Length(n, css::Unit::Px));
+        assert_eq!(value_of("p { border-top-width: 2px; }", "child", "border-top-width"), Some(CSSValue::Integer(0)));
+        assert_eq!(value_of("p { border-top-width: 2px; border-top-style: solid; }", "child", "border-top-width"), px(2.0));
+        assert_eq!(value_of("p { border: 2px solid; border-left-style: hidden; }", "child", "border-left-width"), Some(CSSValue::Integer(0)));
+        assert_eq!(value_of("p { border: 2px solid; border-left-style: hidden; }", "child", "border-right-width"), px(2.0));
+        assert_eq!(value_of("p { border: 2px; }", "child", "border-top-width"), Some(CSSValue::Integer(0)));
+        assert_eq!(value_of("div { border: 2px solid; } p { border-top-width: inherit; }", "child", "border-top-width"), Some(CSSValue::Integer(0)));
+    }
+
+    #[test]
+    fn test_inline_style() {
+        let arena = html::parse(
+            r#"<div><p id="target" class="note" style="color: red; font-weight: bold; bogus">a</p><p id="hidden" style="display:none">b</p></div>"#,
+        );
+        let target = arena.get_element_by_id("target").unwrap();
+        let value_of = |raw: &str, name: &str| {
+            let stylesheet = css::parse(raw);
+            let styled_node = to_styled_node(&arena, target, &[stylesheet]).unwrap();
+            styled_node.properties.get(name).cloned()
+        };
+        let keyword = |s: &str| Some(CSSValue::Keyword(s.to_string()));
+
+        // the style attribute wins over any selector
+        assert_eq!(value_of("#target.note { color: blue; }", "color"), keyword("red"));
+        assert_eq!(value_of("", "font-weight"), keyword("bold"));
+        // but not over important declarations
+        assert_eq!(value_of("p { color: blue !important; }", "color"), keyword("blue"));
+
+        // `display: none` in the style attribute removes the element
+        let root = arena.document();
+        let styled_node = to_styled_node(&arena, root, &[css::parse("p { display: block; }")]).unwrap();
+        let hidden = arena.get_element_by_id("hidden").unwrap();
+        assert_eq!(find(&styled_node, &arena[hidden].node_type), None);
+        assert_eq!(find(&styled_node, &arena[target].node_type).is_some(), true);
+    }
+
+    #[test]
+    fn test_texts_are_not_matched() {
+        let arena = html::parse(r#"<div class="x">text<b>bold</b></div>"#);
+        let stylesheet = css::parse(".x * { display: none; color: red; }");
+        let root = arena.document();
+        let styled_node = to_styled_node(&arena, root, &[stylesheet]).unwrap();
+
+        // the element is hidden, whereas the text is not
+        let div = arena.children(arena.children(arena.document_element().unwrap()).nth(1).unwrap()).next().unwrap();
+        let div = find(&styled_node, &arena[div].node_type).unwrap();
+        assert_eq!(div.children.len(), 1);
+        assert_eq!(div.children[0].properties.get("color"), Some(&CSSValue::Keyword("black".to_string())));
+    }
 }
@@ -0,0 +1,309 @@
+//! The registry of CSS properties known to the renderer.
+//!
+//! Each entry records whether the property is inherited and what its initial value is,
+//! which are what the defaulting step of the cascade needs.
+//! See https://www.w3.org/TR/css-cascade-4/#defaulting
+
+use crate::{
+    css::{self, CSSValue},
+    style::PropertyMap,
+};
+
+/// `Property` describes a single CSS property.
+#[derive(Debug, PartialEq)]
+pub struct Property {
+    pub name: &'static str,
+    pub inherited: bool,
+    initial: &'static str,
+}
+
+impl Property {
+    pub fn initial_value(&self) -> CSSValue {
+        css::parse_value(self.initial).unwrap_or_else(|| CSSValue::Keyword(self.initial.to_string()))
+    }
+}
+
+/// `PROPERTIES` lists the supported properties.
+/// NOTE: initial values are simplified for a terminal, e.g. `color` is not `canvastext`.
+pub const PROPERTIES: &[Property] = &[
+    Property {
+        name: "background-color",
+        inherited: false,
+        initial: "transparent",
+    },
+    Property {
+        name: "border-bottom-style",
+        inherited: false,
+        initial: "none",
+    },
+    Property {
+        name: "border-bottom-width",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "border-left-style",
+        inherited: false,
+        initial: "none",
+    },
+    Property {
+        name: "border-left-width",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "border-right-style",
+        inherited: false,
+        initial: "none",
+    },
+    Property {
+        name: "border-right-width",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "border-top-style",
+        inherited: false,
+        initial: "none",
+    },
+    Property {
+        name: "border-top-width",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "color",
+        inherited: true,
+        initial: "black",
+    },
+    Property {
+        name: "display",
+        inherited: false,
+        initial: "inline",
+    },
+    Property {
+        name: "font-style",
+        inherited: true,
+        initial: "normal",
+    },
+    Property {
+        name: "font-weight",
+        inherited: true,
+        initial: "normal",
+    },
+    Property {
+        name: "height",
+        inherited: false,
+        initial: "auto",
+    },
+    Property {
+        name: "margin-bottom",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "margin-left",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "margin-right",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "margin-top",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "padding-bottom",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "padding-left",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "padding-right",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "padding-top",
+        inherited: false,
+        initial: "0",
+    },
+    Property {
+        name: "text-align",
+        inherited: true,
+        initial: "start",
+    },
+    Property {
+        name: "text-decoration",
+        inherited: false,
+        initial: "none",
+    },
+    Property {
+        name: "visibility",
+        inherited: true,
+        initial: "visible",
+    },
+    Property {
+        name: "white-space",
+        inherited: true,
+        initial: "normal",
+    },
+    Property {
+        name: "width",
+        inherited: false,
+        initial: "auto",
+    },
+];
+
+/// `SHORTHANDS` are the shorthand properties `expand` supports.
+pub const SHORTHANDS: &[&str] = &["border", "border-style", "border-width", "margin", "padding"];
+
+/// `is_supported` tells whether the property is either a longhand in `PROPERTIES` or one of `SHORTHANDS`.
+pub fn is_supported(name: &str) -> bool {
+    lookup(name).is_some() || SHORTHANDS.contains(&name)
+}
+
+/// `lookup` returns the definition of the property with the given name.
+pub fn lookup(name: &str) -> Option<&'static Property> {
+    PROPERTIES.iter().find(|property| property.name == name)
+}
+
+/// `BORDER_STYLES` are the keywords of `border-style`.
+/// See https://www.w3.org/TR/css-backgrounds-3/#typedef-line-style
+const BORDER_STYLES: &[&str] = &[
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+
+/// `expand` returns the longhand declarations a shorthand property like `margin: 1ch 2ch` sets,
+/// or `None` if the property is not a shorthand. An invalid value expands to nothing.
+/// See https://www.w3.org/TR/css-cascade-4/#shorthand
+pub fn expand(name: &str, value: &CSSValue) -> Option<Vec<(String, CSSValue)>> {
+    let values = match value {
+        CSSValue::SpaceList(values) => values.clone(),
+        value => vec![value.clone()],
+    };
+    let (prefix, suffix, values) = match name {
+        "margin" => ("margin-", "", values),
+        "padding" => ("padding-", "", values),
+        "border-width" => ("border-", "-width", values),
+        "border-style" => ("border-", "-style", values),
+        // `border: 1ch solid red`のうち、このレンダラが扱うのは幅とスタイルだけ
+        // 省略された幅は`medium`、スタイルは`none`になる
+        "border" => {
+            let (width, style) = match values.as_slice() {
+                [CSSValue::Keyword(k)] if matches!(k.as_str(), "inherit" | "initial" | "unset") => (value.clone(), value.clone()),
+                _ => {
+                    let style = values.iter().find(|value| match value {
+                        CSSValue::Keyword(k) => BORDER_STYLES.contains(&k.as_str()),
+                        _ => false,
+                    });
+                    let width = values.iter().find(|value| match value {
+                        CSSValue::Length(..) | CSSValue::Integer(_) | CSSValue::Number(_) => true,
+                        CSSValue::Keyword(k) => matches!(k.as_str(), "thin" | "medium" | "thick"),
+                        _ => false,
+                    });
+                    (
+                        width.cloned().unwrap_or_else(|| CSSValue::Keyword("medium".to_string())),
+                        style.cloned().unwrap_or_else(|| CSSValue::Keyword("none".to_string())),
+                    )
+                },
+            };
+            let mut longhands = expand("border-width", &width)?;
+            longhands.extend(expand("border-style", &style)?);
+            return Some(longhands);
+        },
+        _ => return None,
+    };
+
+    // 値の数に応じて上、右、下、左に割り当てる
+    let (top, right, bottom, left) = match values.as_slice() {
+        [all] => (all, all, all, all),
+        [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+        [top, horizontal, bottom] => (top, horizontal, bottom, horizontal),
+        [top, right, bottom, left] => (top, right, bottom, left),
+        _ => return Some(vec![]),
+    };
+    Some(
+        [("top", top), ("right", right), ("bottom", bottom), ("left", left)]
+            .iter()
+            .map(|(side, value)| (format!("{}{}{}", prefix, side, suffix), (*value).clone()))
+            .collect(),
+    )
+}
+
+/// `initial_values` returns the initial values of all the supported properties.
+pub fn initial_values() -> PropertyMap {
+    PROPERTIES
+        .iter()
+        .map(|property| (property.name.to_string(), property.initial_value()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let color = lookup("color").unwrap();
+        assert!(color.inherited);
+        assert_eq!(color.initial_value(), CSSValue::Keyword("black".to_string()));
+
+        let display = lookup("display").unwrap();
+        assert!(!display.inherited);
+        assert_eq!(display.initial_value(), CSSValue::Keyword("inline".to_string()));
+
+        assert_eq!(lookup("unknown"), None);
+        assert_eq!(is_supported("margin"), true);
+        assert_eq!(is_supported("margin-top"), true);
+        assert_eq!(is_supported("unknown"), false);
+
+        assert_eq!(lookup("width").unwrap().initial_value(), CSSValue::Keyword("auto".to_string()));
+        assert_eq!(lookup("margin-top").unwrap().initial_value(), CSSValue::Integer(0));
+    }
+
+    #[test]
+    fn test_expand() {
+        let longhands = |name: &str, raw: &str| {
+            expand(name, &css::parse_value(raw).unwrap())
+                .map(|longhands| longhands.into_iter().map(|(name, value)| format!("{}: {}", name, value)).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            longhands("margin", "1ch 2ch 3ch"),
+            Some(vec![
+                "margin-top: 1ch".to_string(),
+                "margin-right: 2ch".to_string(),
+                "margin-bottom: 3ch".to_string(),
+                "margin-left: 2ch".to_string(),
+            ])
+        );
+        assert_eq!(
+            longhands("padding", "inherit"),
+            Some(vec![
+                "padding-top: inherit".to_string(),
+                "padding-right: inherit".to_string(),
+                "padding-bottom: inherit".to_string(),
+                "padding-left: inherit".to_string(),
+            ])
+        );
+        assert_eq!(longhands("border", "solid 2px red").unwrap()[0], "border-top-width: 2px");
+        assert_eq!(longhands("border", "solid 2px red").unwrap()[4], "border-top-style: solid");
+        assert_eq!(longhands("border", "solid").unwrap()[3], "border-left-width: medium");
+        // an omitted style is `none`, and the width is resolved to 0 by `compute_values`
+        assert_eq!(longhands("border", "1px").unwrap()[7], "border-left-style: none");
+        assert_eq!(longhands("border", "hidden 2px").unwrap()[4], "border-top-style: hidden");
+        assert_eq!(longhands("border", "inherit").unwrap()[0], "border-top-width: inherit");
+        assert_eq!(longhands("border", "inherit").unwrap()[4], "border-top-style: inherit");
+        assert_eq!(longhands("border-style", "solid none").unwrap()[1], "border-right-style: none");
+        assert_eq!(longhands("margin", "1px 2px 3px 4px 5px"), Some(vec![]));
+        assert_eq!(longhands("margin-top", "1px"), None);
+    }
+}